@@ -0,0 +1,15 @@
+-- per-user durable event log replayed by the websocket `sync` request
+CREATE TABLE IF NOT EXISTS user_events (
+    id BIGSERIAL PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_user_events_user ON user_events (user_id, id);
+CREATE INDEX IF NOT EXISTS idx_user_events_created_at ON user_events (created_at);
+
+-- highest event id dropped by retention, so sync can tell a client it fell too far behind
+CREATE TABLE IF NOT EXISTS user_event_state (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    purged_through BIGINT NOT NULL DEFAULT 0
+);
@@ -0,0 +1,15 @@
+-- per-user sequence ids: every durable event gets the next number in its recipient's own sequence
+ALTER TABLE user_event_state ADD COLUMN IF NOT EXISTS last_sequence_id BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE user_events ADD COLUMN IF NOT EXISTS sequence_id BIGINT;
+
+-- existing events keep their log id so cursors handed out before this change stay valid
+UPDATE user_events SET sequence_id = id WHERE sequence_id IS NULL;
+ALTER TABLE user_events ALTER COLUMN sequence_id SET NOT NULL;
+
+INSERT INTO user_event_state (user_id, last_sequence_id)
+SELECT user_id, MAX(sequence_id) FROM user_events GROUP BY user_id
+ON CONFLICT (user_id) DO UPDATE SET last_sequence_id = GREATEST(user_event_state.last_sequence_id, EXCLUDED.last_sequence_id);
+UPDATE user_event_state SET last_sequence_id = purged_through WHERE last_sequence_id < purged_through;
+
+DROP INDEX IF EXISTS idx_user_events_user;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_user_events_user_seq ON user_events (user_id, sequence_id);
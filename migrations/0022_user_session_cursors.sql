@@ -0,0 +1,8 @@
+-- per-device ack cursor into user_events, so a reconnecting device can sync from where it left off
+CREATE TABLE IF NOT EXISTS user_session_cursors (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_id TEXT NOT NULL,
+    last_acked_sequence_id BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, device_id)
+);
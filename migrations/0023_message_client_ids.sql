@@ -0,0 +1,4 @@
+-- client-generated id for a sent message, so a retried websocket send is not stored twice
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS client_message_id TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_sender_client_id
+    ON messages (sender_id, client_message_id) WHERE client_message_id IS NOT NULL;
@@ -0,0 +1,4 @@
+-- service messages record chat lifecycle changes ("alice added bob") in the history
+ALTER TABLE messages DROP CONSTRAINT IF EXISTS messages_kind_check;
+ALTER TABLE messages ADD CONSTRAINT messages_kind_check CHECK (kind IN ('text','sticker','gif','service'));
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS service_action JSONB NULL;
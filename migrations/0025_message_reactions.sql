@@ -0,0 +1,17 @@
+-- one row per (message, user, reaction); a reaction is an emoji or a custom sticker
+CREATE TABLE IF NOT EXISTS message_reactions (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    emoji TEXT NULL,
+    sticker_id UUID NULL REFERENCES stickers(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK ((emoji IS NULL) <> (sticker_id IS NULL))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_message_reactions_unique
+    ON message_reactions (message_id, user_id, (COALESCE(emoji, sticker_id::text)));
+CREATE INDEX IF NOT EXISTS idx_message_reactions_message ON message_reactions (message_id, created_at DESC);
+
+-- 'all': any emoji or sticker, 'some': only allowed_reactions, 'none': reactions disabled
+ALTER TABLE chats
+    ADD COLUMN IF NOT EXISTS reactions_mode TEXT NOT NULL DEFAULT 'all' CHECK (reactions_mode IN ('all','some','none')),
+    ADD COLUMN IF NOT EXISTS allowed_reactions JSONB NOT NULL DEFAULT '[]';
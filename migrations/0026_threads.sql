@@ -0,0 +1,12 @@
+-- replies posted into a message's thread (channel post comments included); top-level messages have NULL
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS thread_root_id UUID NULL REFERENCES messages(id) ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS idx_messages_thread_root ON messages (thread_root_id, created_at DESC) WHERE thread_root_id IS NOT NULL;
+
+-- per-user read position inside a thread, like chat_members.last_read_message_id for the chat itself
+CREATE TABLE IF NOT EXISTS thread_reads (
+    root_message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    last_read_message_id UUID NULL REFERENCES messages(id) ON DELETE SET NULL,
+    PRIMARY KEY (root_message_id, user_id)
+);
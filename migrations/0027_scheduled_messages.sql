@@ -0,0 +1,18 @@
+-- "send later": held here, outside the visible history, until the dispatcher delivers them
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id UUID PRIMARY KEY,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    sender_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    reply_to_message_id UUID NULL REFERENCES messages(id) ON DELETE SET NULL,
+    thread_root_id UUID NULL REFERENCES messages(id) ON DELETE CASCADE,
+    attachment_ids UUID[] NOT NULL DEFAULT '{}',
+    scheduled_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- 'sending' rows are claimed by a dispatcher; a claim older than the timeout is retried
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending','sending','failed')),
+    claimed_at TIMESTAMPTZ NULL,
+    error TEXT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_due ON scheduled_messages (scheduled_at) WHERE status <> 'failed';
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_sender ON scheduled_messages (sender_id, chat_id, scheduled_at);
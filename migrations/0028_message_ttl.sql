@@ -0,0 +1,12 @@
+-- self-destructing messages: ttl_seconds is the timer, expires_at is set once it starts
+-- (at send time, or on the first read by someone else when ttl_starts_on_read)
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS ttl_seconds INT NULL CHECK (ttl_seconds > 0),
+    ADD COLUMN IF NOT EXISTS ttl_starts_on_read BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ NULL;
+CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+-- default timer for new messages in the chat; NULL keeps messages forever
+ALTER TABLE chats ADD COLUMN IF NOT EXISTS auto_delete_seconds INT NULL CHECK (auto_delete_seconds > 0);
+
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS expires_in INT NULL;
@@ -0,0 +1,40 @@
+-- polls live apart from messages so forwarded copies share one set of results
+CREATE TABLE IF NOT EXISTS polls (
+    id UUID PRIMARY KEY,
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    question TEXT NOT NULL,
+    is_anonymous BOOLEAN NOT NULL DEFAULT TRUE,
+    allows_multiple BOOLEAN NOT NULL DEFAULT FALSE,
+    is_quiz BOOLEAN NOT NULL DEFAULT FALSE,
+    correct_option_id INT NULL,
+    explanation TEXT NULL,
+    close_at TIMESTAMPTZ NULL,
+    closed_at TIMESTAMPTZ NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (NOT (is_quiz AND allows_multiple)),
+    CHECK (is_quiz = (correct_option_id IS NOT NULL))
+);
+CREATE INDEX IF NOT EXISTS idx_polls_close_at ON polls (close_at) WHERE close_at IS NOT NULL AND closed_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS poll_options (
+    poll_id UUID NOT NULL REFERENCES polls(id) ON DELETE CASCADE,
+    option_id INT NOT NULL,
+    text TEXT NOT NULL,
+    PRIMARY KEY (poll_id, option_id)
+);
+
+CREATE TABLE IF NOT EXISTS poll_votes (
+    poll_id UUID NOT NULL,
+    option_id INT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    voted_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (poll_id, user_id, option_id),
+    FOREIGN KEY (poll_id, option_id) REFERENCES poll_options(poll_id, option_id) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_poll_votes_option ON poll_votes (poll_id, option_id, voted_at DESC);
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS poll_id UUID NULL REFERENCES polls(id);
+CREATE INDEX IF NOT EXISTS idx_messages_poll ON messages (poll_id) WHERE poll_id IS NOT NULL;
+
+ALTER TABLE messages DROP CONSTRAINT IF EXISTS messages_kind_check;
+ALTER TABLE messages ADD CONSTRAINT messages_kind_check CHECK (kind IN ('text','sticker','gif','service','poll'));
@@ -0,0 +1,12 @@
+-- edit history: every edit moves the replaced text here; messages.revision counts versions
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS revision INT NOT NULL DEFAULT 1;
+
+CREATE TABLE IF NOT EXISTS message_revisions (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    revision INT NOT NULL,
+    content TEXT NOT NULL,
+    -- when this version was written (send or previous edit) and when an edit replaced it
+    created_at TIMESTAMPTZ NOT NULL,
+    replaced_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (message_id, revision)
+);
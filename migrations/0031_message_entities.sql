@@ -0,0 +1,4 @@
+-- formatting and text mentions over `content`; offsets and lengths in UTF-16 code units
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS entities JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE message_revisions ADD COLUMN IF NOT EXISTS entities JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS entities JSONB NOT NULL DEFAULT '[]';
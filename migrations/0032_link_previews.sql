@@ -0,0 +1,19 @@
+-- previews fetched from the web, cached by URL; failed fetches are cached too so they are not retried on every send
+CREATE TABLE IF NOT EXISTS link_previews (
+    url TEXT PRIMARY KEY,
+    status TEXT NOT NULL CHECK (status IN ('ok','failed')),
+    title TEXT NULL,
+    description TEXT NULL,
+    site_name TEXT NULL,
+    image_file_id UUID NULL REFERENCES storage_files(id) ON DELETE SET NULL,
+    image_width INT NULL,
+    image_height INT NULL,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- the URL a message previews; the preview itself is looked up in link_previews
+ALTER TABLE messages
+    ADD COLUMN IF NOT EXISTS link_preview_url TEXT NULL,
+    ADD COLUMN IF NOT EXISTS link_preview_disabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE scheduled_messages ADD COLUMN IF NOT EXISTS disable_link_preview BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,10 @@
+-- the unsent text a user left in a chat's composer, shared by all of their devices
+CREATE TABLE IF NOT EXISTS chat_drafts (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    chat_id UUID NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    entities JSONB NOT NULL DEFAULT '[]',
+    reply_to_message_id UUID NULL REFERENCES messages(id) ON DELETE SET NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, chat_id)
+);
@@ -0,0 +1,20 @@
+-- every user gets at most one "Saved Messages" chat, created on first use
+CREATE UNIQUE INDEX IF NOT EXISTS idx_chats_saved_owner ON chats (owner_id) WHERE chat_type = 'saved';
+
+-- a user's saved items: copies forwarded into their saved chat, or bookmarks of the original
+CREATE TABLE IF NOT EXISTS saved_messages (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('copy','reference')),
+    -- the copy in the saved chat, or the bookmarked original; NULL once it is hard-deleted
+    message_id UUID NULL REFERENCES messages(id) ON DELETE SET NULL,
+    -- where it came from, kept after the original is gone
+    source_chat_id UUID NULL REFERENCES chats(id) ON DELETE SET NULL,
+    source_message_id UUID NOT NULL,
+    source_sender_id UUID NULL REFERENCES users(id) ON DELETE SET NULL,
+    tag TEXT NULL,
+    note TEXT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS idx_saved_messages_user ON saved_messages (user_id, created_at DESC);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_saved_messages_reference ON saved_messages (user_id, source_message_id) WHERE kind = 'reference';
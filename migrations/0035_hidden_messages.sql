@@ -0,0 +1,7 @@
+-- messages a user deleted "for me": still there for everyone else, gone from that user's history
+CREATE TABLE IF NOT EXISTS hidden_messages (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    hidden_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (user_id, message_id)
+);
@@ -0,0 +1,3 @@
+-- history, search and mentions page over (created_at, id); the id breaks timestamp ties
+CREATE INDEX IF NOT EXISTS idx_messages_chat_created_id ON messages (chat_id, created_at DESC, id DESC);
+CREATE INDEX IF NOT EXISTS idx_member_mentions_page ON member_mentions (chat_id, user_id, created_at DESC, message_id DESC);
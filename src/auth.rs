@@ -1,5 +1,5 @@
 use crate::state::AppState;
-use actix_web::{self, http::header::HeaderMap, web};
+use actix_web::{self, http::header::HeaderMap, web, HttpMessage};
 use chrono::Utc;
 use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header as JwtHeader, Validation};
 use serde::{Deserialize, Serialize};
@@ -34,23 +34,20 @@ pub fn decode_token(token: &str, secret: &str) -> Result<Uuid, jsonwebtoken::err
     Ok(Uuid::parse_str(&data.claims.sub).expect("valid uuid in token"))
 }
 
-pub fn extract_bearer(headers: &HeaderMap) -> Result<String, ()> {
-    let Some(value) = headers.get(actix_web::http::header::AUTHORIZATION) else {
-        return Err(());
-    };
-    let Ok(s) = value.to_str() else {
-        return Err(());
-    };
-    if let Some(rest) = s.strip_prefix("Bearer ") {
-        Ok(rest.to_string())
-    } else {
-        Err(())
-    }
+pub fn extract_bearer(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(actix_web::http::header::AUTHORIZATION)?;
+    let s = value.to_str().ok()?;
+    s.strip_prefix("Bearer ").map(|rest| rest.to_string())
 }
 
 #[derive(Clone)]
 pub struct AuthUser(pub Uuid);
 
+/// Caller already authenticated by its websocket session; set on requests the
+/// session dispatches through `rpc`, which then skip token parsing.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SessionUser(pub Uuid);
+
 impl actix_web::FromRequest for AuthUser {
     type Error = actix_web::Error;
     type Future = std::pin::Pin<Box<dyn std::future::Future<Output = Result<Self, Self::Error>>>>;
@@ -59,6 +56,9 @@ impl actix_web::FromRequest for AuthUser {
         req: &actix_web::HttpRequest,
         _payload: &mut actix_web::dev::Payload,
     ) -> Self::Future {
+        if let Some(SessionUser(uid)) = req.extensions().get::<SessionUser>().copied() {
+            return Box::pin(async move { Ok(AuthUser(uid)) });
+        }
         let headers = req.headers().clone();
         let query = req.uri().query().map(|s| s.to_string());
         let secret = req
@@ -78,7 +78,7 @@ impl actix_web::FromRequest for AuthUser {
             } else {
                 None
             };
-            let token = token.or_else(|| crate::auth::extract_bearer(&headers).ok());
+            let token = token.or_else(|| crate::auth::extract_bearer(&headers));
             let token =
                 token.ok_or_else(|| actix_web::error::ErrorUnauthorized("missing token"))?;
             let uid = crate::auth::decode_token(&token, &secret)
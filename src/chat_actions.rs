@@ -0,0 +1,193 @@
+use crate::events;
+use crate::handlers::chats::load_message_dtos;
+use crate::models::SimpleUserDto;
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use tracing::error;
+
+/// A change to a chat's lifecycle, membership or settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatActionType {
+    ChatCreated,
+    TitleChanged,
+    OwnershipTransferred,
+    UserAdded,
+    UserJoined,
+    UserLeft,
+    UserRemoved,
+    MemberMuted,
+    MemberUnmuted,
+    AdminPromoted,
+    AdminDemoted,
+    VisibilityChanged,
+    MessagePinned,
+    MessageUnpinned,
+    HistoryCleared,
+    ReactionsChanged,
+    AutoDeleteChanged,
+}
+
+impl ChatActionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChatActionType::ChatCreated => "chat_created",
+            ChatActionType::TitleChanged => "title_changed",
+            ChatActionType::OwnershipTransferred => "ownership_transferred",
+            ChatActionType::UserAdded => "user_added",
+            ChatActionType::UserJoined => "user_joined",
+            ChatActionType::UserLeft => "user_left",
+            ChatActionType::UserRemoved => "user_removed",
+            ChatActionType::MemberMuted => "member_muted",
+            ChatActionType::MemberUnmuted => "member_unmuted",
+            ChatActionType::AdminPromoted => "admin_promoted",
+            ChatActionType::AdminDemoted => "admin_demoted",
+            ChatActionType::VisibilityChanged => "visibility_changed",
+            ChatActionType::MessagePinned => "message_pinned",
+            ChatActionType::MessageUnpinned => "message_unpinned",
+            ChatActionType::HistoryCleared => "history_cleared",
+            ChatActionType::ReactionsChanged => "reactions_changed",
+            ChatActionType::AutoDeleteChanged => "auto_delete_changed",
+        }
+    }
+
+    /// Plain-text rendering stored as the service message's `content`.
+    fn describe(&self, actor: &str, target: &str, payload: &serde_json::Value) -> String {
+        let title = payload["title"].as_str().unwrap_or_default();
+        match self {
+            ChatActionType::ChatCreated => format!("{actor} created \"{title}\""),
+            ChatActionType::TitleChanged => format!("{actor} changed the title to \"{title}\""),
+            ChatActionType::OwnershipTransferred => format!("{actor} transferred ownership to {target}"),
+            ChatActionType::UserAdded => format!("{actor} added {target}"),
+            ChatActionType::UserJoined => format!("{actor} joined the chat"),
+            ChatActionType::UserLeft => format!("{actor} left the chat"),
+            ChatActionType::UserRemoved => format!("{actor} removed {target}"),
+            ChatActionType::MemberMuted => format!("{actor} muted {target}"),
+            ChatActionType::MemberUnmuted => format!("{actor} unmuted {target}"),
+            ChatActionType::AdminPromoted => format!("{actor} made {target} an admin"),
+            ChatActionType::AdminDemoted => format!("{actor} removed {target} from admins"),
+            ChatActionType::VisibilityChanged if payload["is_public"] == true => format!("{actor} made the chat public"),
+            ChatActionType::VisibilityChanged => format!("{actor} made the chat private"),
+            ChatActionType::MessagePinned => format!("{actor} pinned a message"),
+            ChatActionType::MessageUnpinned => format!("{actor} unpinned a message"),
+            ChatActionType::HistoryCleared => format!("{actor} cleared the history"),
+            ChatActionType::ReactionsChanged => format!("{actor} changed the allowed reactions"),
+            ChatActionType::AutoDeleteChanged => match payload["seconds"].as_i64() {
+                Some(seconds) => format!("{actor} set messages to auto-delete after {seconds} seconds"),
+                None => format!("{actor} turned off auto-delete"),
+            },
+        }
+    }
+}
+
+/// One lifecycle change: who did it, who it was done to, and action-specific details.
+pub struct ChatAction {
+    pub chat_id: Uuid,
+    pub action: ChatActionType,
+    pub actor_id: Uuid,
+    pub target_id: Option<Uuid>,
+    pub payload: serde_json::Value,
+    /// Message the action is about (e.g. the pinned one), linked as the service message's reply
+    pub message_id: Option<Uuid>,
+}
+
+/// `messages.service_action` as stored; the actor is the message's sender.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredServiceAction {
+    pub action: ChatActionType,
+    pub target_id: Option<Uuid>,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+impl ChatAction {
+    pub fn new(chat_id: Uuid, action: ChatActionType, actor_id: Uuid) -> Self {
+        Self { chat_id, action, actor_id, target_id: None, payload: serde_json::json!({}), message_id: None }
+    }
+
+    pub fn target(mut self, target_id: Uuid) -> Self {
+        self.target_id = Some(target_id);
+        self
+    }
+
+    pub fn payload(mut self, payload: serde_json::Value) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn about_message(mut self, message_id: Uuid) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+}
+
+/// Store the action as a `service` message in the chat history, then broadcast it as a
+/// `chat_action` carrying that message to the participants. A target who is no longer a
+/// participant (left, removed) is told as well.
+///
+/// Callers have already committed the change itself, so a failure here is logged rather
+/// than failing their request.
+pub async fn record(state: &AppState, action: ChatAction) {
+    let (chat_id, action_type) = (action.chat_id, action.action);
+    if let Err(e) = try_record(state, action).await {
+        error!(%chat_id, action = action_type.as_str(), ?e, "chat action error");
+    }
+}
+
+async fn try_record(state: &AppState, action: ChatAction) -> anyhow::Result<()> {
+    let ids: Vec<Uuid> = std::iter::once(action.actor_id).chain(action.target_id).collect();
+    let names: HashMap<Uuid, String> = sqlx::query_as::<_, (Uuid, String)>("SELECT id, username FROM users WHERE id = ANY($1)")
+        .bind(&ids)
+        .fetch_all(&state.pool)
+        .await?
+        .into_iter()
+        .collect();
+    let user = |id: Uuid| SimpleUserDto { id, username: names.get(&id).cloned().unwrap_or_default() };
+    let actor = user(action.actor_id);
+    let target = action.target_id.map(user);
+
+    let message_id = Uuid::new_v4();
+    let content = action.action.describe(
+        &actor.username,
+        target.as_ref().map_or("", |t| t.username.as_str()),
+        &action.payload,
+    );
+    let stored = StoredServiceAction { action: action.action, target_id: action.target_id, payload: action.payload.clone() };
+    sqlx::query(
+        "INSERT INTO messages (id, chat_id, sender_id, content, kind, service_action, reply_to_message_id) VALUES ($1, $2, $3, $4, 'service', $5, $6)",
+    )
+    .bind(message_id)
+    .bind(action.chat_id)
+    .bind(action.actor_id)
+    .bind(&content)
+    .bind(serde_json::to_value(&stored)?)
+    .bind(action.message_id)
+    .execute(&state.pool)
+    .await?;
+    let message = load_message_dtos(&state.pool, &[message_id]).await?.pop();
+
+    let mut recipients = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM chat_participants WHERE chat_id = $1")
+        .bind(action.chat_id)
+        .fetch_all(&state.pool)
+        .await?;
+    if let Some(target_id) = action.target_id.filter(|id| !recipients.contains(id)) {
+        recipients.push(target_id);
+    }
+    let msg = ServerWsMsg::ChatAction {
+        sequence_id: 0,
+        chat_id: action.chat_id,
+        action_type: action.action.as_str().to_string(),
+        data: serde_json::json!({
+            "actor": actor,
+            "target": target,
+            "payload": action.payload,
+            "message_id": message_id,
+            "message": message,
+        }),
+    };
+    events::publish(state, &recipients, msg).await?;
+    Ok(())
+}
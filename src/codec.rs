@@ -0,0 +1,64 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Wire encoding of a websocket session, chosen by the client through
+/// `Sec-WebSocket-Protocol`. JSON travels in text frames, the binary codecs in
+/// binary frames; all three carry the same `ClientWsMsg` / `ServerWsMsg` enums.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WsCodec {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+/// One encoded websocket frame.
+#[derive(Debug)]
+pub enum WsFrame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl WsCodec {
+    pub const ALL: [WsCodec; 3] = [WsCodec::Json, WsCodec::MessagePack, WsCodec::Cbor];
+
+    /// Subprotocol token the client offers and the server echoes back.
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            WsCodec::Json => "json",
+            WsCodec::MessagePack => "msgpack",
+            WsCodec::Cbor => "cbor",
+        }
+    }
+
+    /// Pick the first codec in the client's `Sec-WebSocket-Protocol` list that we speak.
+    /// Returns `None` when the header is absent or offers nothing we know; the session
+    /// then uses JSON and no subprotocol is echoed.
+    pub fn negotiate(header: Option<&str>) -> Option<WsCodec> {
+        header?
+            .split(',')
+            .map(str::trim)
+            .find_map(|offered| Self::ALL.into_iter().find(|c| c.protocol().eq_ignore_ascii_case(offered)))
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<WsFrame> {
+        Ok(match self {
+            WsCodec::Json => WsFrame::Text(serde_json::to_string(value)?),
+            // field names are kept so the internally tagged enums decode back from a map
+            WsCodec::MessagePack => WsFrame::Binary(rmp_serde::to_vec_named(value)?),
+            WsCodec::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf)?;
+                WsFrame::Binary(buf)
+            }
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(match self {
+            WsCodec::Json => serde_json::from_slice(bytes)?,
+            WsCodec::MessagePack => rmp_serde::from_slice(bytes)?,
+            WsCodec::Cbor => ciborium::from_reader(bytes)?,
+        })
+    }
+}
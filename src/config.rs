@@ -11,6 +11,20 @@ pub struct AppConfig {
     pub redis: RedisConfig,
     pub admin: AdminConfig,
     pub gif: GifConfig,
+    #[serde(default)]
+    pub events: EventLogConfig,
+    #[serde(default)]
+    pub fanout: FanoutConfig,
+    #[serde(default)]
+    pub websocket: WebsocketConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub expiry: ExpiryConfig,
+    #[serde(default)]
+    pub messages: MessagesConfig,
+    #[serde(default)]
+    pub link_preview: LinkPreviewConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -56,6 +70,149 @@ pub struct GifConfig {
     pub api_key: Option<String>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventLogConfig {
+    pub retention_hours: u64,
+    pub sync_page_size: u32,
+    /// How often events older than `retention_hours` are purged.
+    pub purge_interval_secs: u64,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            retention_hours: 24 * 7,
+            sync_page_size: 500,
+            purge_interval_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FanoutConfig {
+    pub backend: String, // "local" or "redis"
+    pub channel: String,
+    /// How long a node's sessions keep their users online after it stops refreshing them
+    /// (redis backend).
+    pub session_ttl_secs: u64,
+}
+
+impl Default for FanoutConfig {
+    fn default() -> Self {
+        Self {
+            backend: "local".to_string(),
+            channel: "qbychat:fanout".to_string(),
+            session_ttl_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebsocketConfig {
+    /// A typing action not refreshed within this many seconds is reported as stopped.
+    pub typing_timeout_secs: u64,
+    /// Events buffered per session before a slow client is disconnected.
+    pub outbound_queue_size: usize,
+    pub ping_interval_secs: u64,
+    /// A session that sends nothing (not even a pong) for this long is closed.
+    pub idle_timeout_secs: u64,
+    /// `rpc` calls one session may have running at once; more are refused as rate_limited.
+    pub max_inflight_rpc: usize,
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        Self {
+            typing_timeout_secs: 5,
+            outbound_queue_size: 256,
+            ping_interval_secs: 15,
+            idle_timeout_secs: 45,
+            max_inflight_rpc: 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchedulerConfig {
+    /// How often the dispatcher looks for due scheduled messages.
+    pub poll_interval_ms: u64,
+    pub batch_size: i64,
+    /// A claimed message still unsent after this long (its node died) is claimed again.
+    pub claim_timeout_secs: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 1000,
+            batch_size: 100,
+            claim_timeout_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpiryConfig {
+    /// How often the reaper deletes expired self-destructing messages and closes due polls.
+    pub poll_interval_ms: u64,
+    pub batch_size: i64,
+}
+
+impl Default for ExpiryConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_ms: 1000,
+            batch_size: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagesConfig {
+    /// Messages older than this can no longer be edited; 0 allows edits forever.
+    pub edit_window_secs: u64,
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            edit_window_secs: 48 * 3600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkPreviewConfig {
+    pub enabled: bool,
+    /// Budget for fetching one page and its image, redirects included.
+    pub timeout_ms: u64,
+    /// Only the first this many bytes of a page are read.
+    pub max_page_bytes: usize,
+    /// Larger images are skipped.
+    pub max_image_bytes: usize,
+    /// A cached preview (or failure) is reused for this long before the URL is fetched again.
+    pub cache_ttl_secs: u64,
+    /// Pages fetched at once by this process; a message that waits longer than `timeout_ms`
+    /// for a slot goes without a preview.
+    pub max_concurrent_fetches: usize,
+    /// Allow fetching from loopback and private networks; only for tests and trusted setups.
+    pub allow_private_networks: bool,
+}
+
+impl Default for LinkPreviewConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            timeout_ms: 5000,
+            max_page_bytes: 512 * 1024,
+            max_image_bytes: 5 * 1024 * 1024,
+            cache_ttl_secs: 24 * 3600,
+            max_concurrent_fetches: 16,
+            allow_private_networks: false,
+        }
+    }
+}
+
 impl AppConfig {
     pub fn load() -> anyhow::Result<Self> {
         let mut builder =
@@ -73,7 +230,7 @@ impl AppConfig {
         );
 
         let cfg = builder.build()?.try_deserialize::<AppConfig>()?;
-        Ok(cfg.with_env_overrides()?)
+        cfg.with_env_overrides()
     }
 
     fn with_env_overrides(mut self) -> anyhow::Result<Self> {
@@ -90,7 +247,7 @@ impl AppConfig {
                 self.storage.dir = dir;
             }
         }
-        if let Some(url) = std::env::var("REDIS_URL").ok() {
+        if let Ok(url) = std::env::var("REDIS_URL") {
             self.redis.url = Some(url);
         }
         if self.admin.token.is_empty() {
@@ -0,0 +1,734 @@
+use crate::events;
+use crate::handlers::chats::load_message_dtos;
+use crate::handlers::drafts;
+use crate::link_preview;
+use crate::models::{MessageDto, MessageEntity, MessageEntityType, PollSendReq};
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use std::collections::{HashMap, HashSet};
+use tracing::error;
+
+const MAX_MENTIONS: usize = 50;
+const MAX_ENTITIES: usize = 100;
+const MAX_ENTITY_URL_LEN: usize = 2048;
+const MAX_PRE_LANGUAGE_LEN: usize = 32;
+/// Longest self-destruct or auto-delete timer: one year.
+pub const MAX_TTL_SECONDS: i32 = 365 * 24 * 3600;
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_POLL_QUESTION_CHARS: usize = 300;
+const MAX_POLL_OPTION_CHARS: usize = 100;
+const MAX_POLL_EXPLANATION_CHARS: usize = 200;
+
+/// A message on its way into a chat. Every send path (REST, WebSocket, forward,
+/// sticker, GIF) builds one of these and hands it to [`deliver`].
+pub struct OutgoingMessage {
+    pub chat_id: Uuid,
+    pub sender_id: Uuid,
+    pub body: MessageBody,
+    pub reply_to_message_id: Option<Uuid>,
+    /// Post into this message's thread instead of the main history.
+    pub thread_root_id: Option<Uuid>,
+    pub attachment_ids: Vec<Uuid>,
+    /// Self-destruct timer in seconds; overrides the chat's auto-delete period.
+    pub expires_in: Option<i32>,
+    /// Client-chosen id; resending with the same id returns the stored message instead of a copy.
+    pub client_message_id: Option<String>,
+    /// Do not generate a preview for links in the text.
+    pub disable_link_preview: bool,
+    /// Sending uses up the sender's draft in the chat; deliveries made on the sender's
+    /// behalf later (scheduled messages) leave whatever they are typing now alone.
+    pub clears_draft: bool,
+}
+
+pub enum MessageBody {
+    Text { content: String, entities: Vec<MessageEntity> },
+    Sticker { sticker_id: Uuid },
+    Gif(GifPayload),
+    Poll(PollSendReq),
+    Forward(ForwardSource),
+}
+
+pub struct GifPayload {
+    pub id: String,
+    pub url: String,
+    pub preview_url: String,
+    pub provider: String,
+}
+
+/// The original message a forward copies, as read from its source chat.
+pub struct ForwardSource {
+    pub chat_id: Uuid,
+    pub message_id: Uuid,
+    pub sender_id: Uuid,
+    pub content: String,
+    pub entities: Vec<MessageEntity>,
+    pub kind: String,
+    pub sticker_id: Option<Uuid>,
+    pub gif: Option<GifPayload>,
+    /// Polls are shared, not copied: the forward shows the same poll and results.
+    pub poll_id: Option<Uuid>,
+    /// The copy shows the original's preview without fetching it again.
+    pub link_preview_url: Option<String>,
+}
+
+impl OutgoingMessage {
+    pub fn new(chat_id: Uuid, sender_id: Uuid, body: MessageBody) -> Self {
+        Self {
+            chat_id,
+            sender_id,
+            body,
+            reply_to_message_id: None,
+            thread_root_id: None,
+            attachment_ids: Vec::new(),
+            expires_in: None,
+            client_message_id: None,
+            disable_link_preview: false,
+            clears_draft: true,
+        }
+    }
+}
+
+/// A stored message and the sequence id the sender's sessions received it under.
+pub struct Delivered {
+    pub message: MessageDto,
+    /// `None` when the send was a retry of an already stored `client_message_id`.
+    pub sender_sequence_id: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeliveryError {
+    #[error("{0}")]
+    Forbidden(&'static str),
+    #[error("{0}")]
+    NotFound(&'static str),
+    #[error("{0}")]
+    BadRequest(&'static str),
+    #[error("too many mentions")]
+    TooManyMentions,
+    #[error("gif provider disabled")]
+    GifDisabled,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl DeliveryError {
+    /// Machine-readable code used in WebSocket `error` frames.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeliveryError::Forbidden(_) => "forbidden",
+            DeliveryError::NotFound(_) => "not_found",
+            DeliveryError::BadRequest(_) => "bad_request",
+            DeliveryError::TooManyMentions => "too_many_mentions",
+            DeliveryError::GifDisabled => "unavailable",
+            DeliveryError::Database(_) => "internal",
+        }
+    }
+}
+
+impl ResponseError for DeliveryError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DeliveryError::Forbidden(_) => StatusCode::FORBIDDEN,
+            DeliveryError::NotFound(_) => StatusCode::NOT_FOUND,
+            DeliveryError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            DeliveryError::TooManyMentions => StatusCode::UNPROCESSABLE_ENTITY,
+            DeliveryError::GifDisabled => StatusCode::SERVICE_UNAVAILABLE,
+            DeliveryError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).body(match self {
+            DeliveryError::Database(e) => format!("{:?}", e),
+            other => other.to_string(),
+        })
+    }
+}
+
+/// Validate, persist and broadcast `msg`, returning the message as participants see it.
+pub async fn deliver(state: &AppState, msg: OutgoingMessage) -> Result<Delivered, DeliveryError> {
+    check_sender(state, msg.chat_id, msg.sender_id, msg.thread_root_id.is_some()).await?;
+    if let Some(client_id) = &msg.client_message_id {
+        if client_id.is_empty() || client_id.len() > 64 {
+            return Err(DeliveryError::BadRequest("invalid client_message_id"));
+        }
+        if let Some(existing) = find_by_client_id(state, msg.sender_id, client_id).await? {
+            return Ok(Delivered { message: existing, sender_sequence_id: None });
+        }
+    }
+    let mentions = validate_body(state, &msg).await?;
+    validate_reply_and_attachments(state, &msg).await?;
+    let thread_root_id = resolve_thread_root(state, &msg).await?;
+    let (ttl_seconds, ttl_starts_on_read) = message_ttl(state, &msg).await?;
+    let expires_at = ttl_seconds
+        .filter(|_| !ttl_starts_on_read)
+        .map(|ttl| Utc::now() + chrono::Duration::seconds(ttl.into()));
+
+    let id = Uuid::new_v4();
+    let mut tx = state.pool.begin().await?;
+    let (content, entities, kind, sticker_id, gif, forward) = match &msg.body {
+        MessageBody::Text { content, entities } => {
+            (content.trim().to_string(), normalize_entities(content, entities)?, "text", None, None, None)
+        }
+        MessageBody::Sticker { sticker_id } => (String::new(), Vec::new(), "sticker", Some(*sticker_id), None, None),
+        MessageBody::Gif(gif) => (String::new(), Vec::new(), "gif", None, Some(gif), None),
+        MessageBody::Poll(poll) => (poll.question.trim().to_string(), Vec::new(), "poll", None, None, None),
+        MessageBody::Forward(src) => (
+            src.content.clone(),
+            src.entities.clone(),
+            src.kind.as_str(),
+            src.sticker_id,
+            src.gif.as_ref(),
+            Some(src),
+        ),
+    };
+    let link_preview_url = match &msg.body {
+        MessageBody::Text { .. } if msg.disable_link_preview || !state.config.link_preview.enabled => None,
+        MessageBody::Text { .. } => link_preview::preview_url(&content, &entities),
+        MessageBody::Forward(src) => src.link_preview_url.clone(),
+        _ => None,
+    };
+    let poll_id = match &msg.body {
+        MessageBody::Poll(poll) => Some(insert_poll(&mut tx, msg.sender_id, poll).await?),
+        MessageBody::Forward(src) => src.poll_id,
+        _ => None,
+    };
+    let inserted = sqlx::query(
+        "INSERT INTO messages (id, chat_id, sender_id, content, kind, reply_to_message_id, sticker_id, gif_id, gif_url, gif_preview_url, gif_provider, forward_from_message_id, forward_from_chat_id, forward_from_sender_id, client_message_id, thread_root_id, ttl_seconds, ttl_starts_on_read, expires_at, poll_id, entities, link_preview_url, link_preview_disabled) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17,$18,$19,$20,$21,$22,$23) ON CONFLICT (sender_id, client_message_id) WHERE client_message_id IS NOT NULL DO NOTHING",
+    )
+    .bind(id)
+    .bind(msg.chat_id)
+    .bind(msg.sender_id)
+    .bind(&content)
+    .bind(kind)
+    .bind(msg.reply_to_message_id)
+    .bind(sticker_id)
+    .bind(gif.map(|g| g.id.clone()))
+    .bind(gif.map(|g| g.url.clone()))
+    .bind(gif.map(|g| g.preview_url.clone()))
+    .bind(gif.map(|g| g.provider.to_lowercase()))
+    .bind(forward.map(|f| f.message_id))
+    .bind(forward.map(|f| f.chat_id))
+    .bind(forward.map(|f| f.sender_id))
+    .bind(&msg.client_message_id)
+    .bind(thread_root_id)
+    .bind(ttl_seconds)
+    .bind(ttl_starts_on_read)
+    .bind(expires_at)
+    .bind(poll_id)
+    .bind(sqlx::types::Json(&entities))
+    .bind(&link_preview_url)
+    .bind(msg.disable_link_preview)
+    .execute(&mut *tx)
+    .await?;
+    if inserted.rows_affected() == 0 {
+        // a concurrent retry with the same client_message_id won the insert
+        tx.rollback().await?;
+        let client_id = msg.client_message_id.as_deref().unwrap_or_default();
+        let existing = find_by_client_id(state, msg.sender_id, client_id)
+            .await?
+            .ok_or(DeliveryError::NotFound("message not found"))?;
+        return Ok(Delivered { message: existing, sender_sequence_id: None });
+    }
+
+    for fid in &msg.attachment_ids {
+        sqlx::query("INSERT INTO message_attachments (message_id, file_id) VALUES ($1,$2) ON CONFLICT DO NOTHING")
+            .bind(id)
+            .bind(fid)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    if !mentions.is_empty() {
+        let excerpt: String = content.chars().take(120).collect();
+        for uid in mentions {
+            sqlx::query("INSERT INTO member_mentions (chat_id, user_id, message_id, excerpt) VALUES ($1,$2,$3,$4) ON CONFLICT DO NOTHING")
+                .bind(msg.chat_id)
+                .bind(uid)
+                .bind(id)
+                .bind(&excerpt)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    // the new message ends whatever the sender was doing; clients clear it on new_message
+    if let Err(e) = state.typing.stop(msg.chat_id, msg.sender_id).await {
+        error!(chat_id = %msg.chat_id, ?e, "typing clear error");
+    }
+
+    let dto = load_message_dtos(&state.pool, &[id])
+        .await?
+        .pop()
+        .ok_or(DeliveryError::NotFound("message not found"))?;
+
+    // The message is stored; a failed broadcast must not turn into a retry that duplicates it.
+    let event = ServerWsMsg::NewMessage { sequence_id: 0, message: Box::new(dto.clone()) };
+    let sender_sequence_id = match events::publish_to_chat(state, msg.chat_id, event).await {
+        Ok(deliveries) => deliveries
+            .into_iter()
+            .find(|(uid, _)| *uid == msg.sender_id)
+            .map(|(_, seq)| seq),
+        Err(e) => {
+            error!(message_id = %id, ?e, "new_message broadcast error");
+            None
+        }
+    };
+    if let (Some(url), MessageBody::Text { .. }) = (link_preview_url, &msg.body) {
+        link_preview::spawn_attach(state.clone(), msg.chat_id, id, url, dto.link_preview.is_some());
+    }
+    // thread replies are typed in the thread view, not the chat's composer
+    if msg.clears_draft && msg.thread_root_id.is_none() {
+        if let Err(e) = drafts::clear(state, msg.chat_id, msg.sender_id).await {
+            error!(chat_id = %msg.chat_id, ?e, "draft clear error");
+        }
+    }
+    Ok(Delivered { message: dto, sender_sequence_id })
+}
+
+/// Run the checks [`deliver`] would without storing anything, e.g. before scheduling `msg`
+/// for later. Returns the thread root the message would be posted into.
+pub async fn check(state: &AppState, msg: &OutgoingMessage) -> Result<Option<Uuid>, DeliveryError> {
+    check_sender(state, msg.chat_id, msg.sender_id, msg.thread_root_id.is_some()).await?;
+    validate_body(state, msg).await?;
+    validate_reply_and_attachments(state, msg).await?;
+    message_ttl(state, msg).await?;
+    resolve_thread_root(state, msg).await
+}
+
+async fn find_by_client_id(
+    state: &AppState,
+    sender_id: Uuid,
+    client_message_id: &str,
+) -> Result<Option<MessageDto>, DeliveryError> {
+    let id = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM messages WHERE sender_id = $1 AND client_message_id = $2",
+    )
+    .bind(sender_id)
+    .bind(client_message_id)
+    .fetch_optional(&state.pool)
+    .await?;
+    match id {
+        Some(id) => Ok(load_message_dtos(&state.pool, &[id]).await?.pop()),
+        None => Ok(None),
+    }
+}
+
+/// Membership, channel-owner and mute checks shared by every send path.
+pub async fn ensure_can_send(state: &AppState, chat_id: Uuid, user_id: Uuid) -> Result<(), DeliveryError> {
+    check_sender(state, chat_id, user_id, false).await
+}
+
+/// Like [`ensure_can_send`]; with `in_thread` any channel member may post, since
+/// threads under channel posts are the comment section.
+async fn check_sender(state: &AppState, chat_id: Uuid, user_id: Uuid, in_thread: bool) -> Result<(), DeliveryError> {
+    let is_member = sqlx::query_scalar::<_, i32>(
+        "SELECT 1 FROM chat_participants WHERE chat_id = $1 AND user_id = $2",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .is_some();
+    if !is_member {
+        return Err(DeliveryError::Forbidden("not a member"));
+    }
+
+    #[derive(sqlx::FromRow)]
+    struct Meta {
+        chat_type: Option<String>,
+        owner_id: Option<Uuid>,
+    }
+    let meta = sqlx::query_as::<_, Meta>("SELECT chat_type, owner_id FROM chats WHERE id = $1")
+        .bind(chat_id)
+        .fetch_optional(&state.pool)
+        .await?
+        .ok_or(DeliveryError::NotFound("chat not found"))?;
+    if meta.chat_type.as_deref() == Some("channel") && meta.owner_id != Some(user_id) && !in_thread {
+        return Err(DeliveryError::Forbidden("only owner can send in channel"));
+    }
+
+    let muted_until: Option<Option<DateTime<Utc>>> = sqlx::query_scalar(
+        "SELECT muted_until FROM chat_mutes WHERE chat_id = $1 AND user_id = $2",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?;
+    if muted_until.flatten().is_some_and(|until| until > Utc::now()) {
+        return Err(DeliveryError::Forbidden("muted"));
+    }
+    Ok(())
+}
+
+/// Kind-specific checks; returns the participants a text message mentions.
+async fn validate_body(state: &AppState, msg: &OutgoingMessage) -> Result<Vec<Uuid>, DeliveryError> {
+    match &msg.body {
+        MessageBody::Text { content, entities } => {
+            if content.trim().is_empty() {
+                return Err(DeliveryError::BadRequest("content required"));
+            }
+            let entities = normalize_entities(content, entities)?;
+            mentioned_users(state, msg.chat_id, msg.sender_id, content.trim(), &entities).await
+        }
+        MessageBody::Sticker { sticker_id } => {
+            #[derive(sqlx::FromRow)]
+            struct StickerMeta {
+                pack_id: Uuid,
+                created_by: Uuid,
+            }
+            let sticker = sqlx::query_as::<_, StickerMeta>(
+                "SELECT s.pack_id, sp.created_by FROM stickers s JOIN sticker_packs sp ON sp.id = s.pack_id WHERE s.id = $1",
+            )
+            .bind(sticker_id)
+            .fetch_optional(&state.pool)
+            .await?
+            .ok_or(DeliveryError::NotFound("sticker not found"))?;
+            if sticker.created_by != msg.sender_id {
+                let installed = sqlx::query_scalar::<_, i32>(
+                    "SELECT 1 FROM user_sticker_packs WHERE user_id = $1 AND pack_id = $2",
+                )
+                .bind(msg.sender_id)
+                .bind(sticker.pack_id)
+                .fetch_optional(&state.pool)
+                .await?
+                .is_some();
+                if !installed {
+                    return Err(DeliveryError::Forbidden("install pack first"));
+                }
+            }
+            Ok(Vec::new())
+        }
+        MessageBody::Gif(gif) => {
+            let provider = state.gif_provider.as_ref().ok_or(DeliveryError::GifDisabled)?;
+            if provider.provider().to_lowercase() != gif.provider.to_lowercase() {
+                return Err(DeliveryError::BadRequest("unknown provider"));
+            }
+            if gif.url.trim().is_empty() || gif.preview_url.trim().is_empty() {
+                return Err(DeliveryError::BadRequest("gif_url required"));
+            }
+            Ok(Vec::new())
+        }
+        MessageBody::Poll(poll) => {
+            if !msg.attachment_ids.is_empty() {
+                return Err(DeliveryError::BadRequest("polls cannot carry attachments"));
+            }
+            validate_poll(poll)?;
+            if !poll.is_anonymous {
+                let chat_type: Option<String> = sqlx::query_scalar("SELECT chat_type FROM chats WHERE id = $1")
+                    .bind(msg.chat_id)
+                    .fetch_optional(&state.pool)
+                    .await?
+                    .flatten();
+                if chat_type.as_deref() == Some("channel") {
+                    return Err(DeliveryError::BadRequest("polls in channels must be anonymous"));
+                }
+            }
+            Ok(Vec::new())
+        }
+        // forwarded copies keep their text but do not re-notify anyone mentioned in it
+        MessageBody::Forward(_) => Ok(Vec::new()),
+    }
+}
+
+fn validate_poll(poll: &PollSendReq) -> Result<(), DeliveryError> {
+    let question = poll.question.trim();
+    if question.is_empty() || question.chars().count() > MAX_POLL_QUESTION_CHARS {
+        return Err(DeliveryError::BadRequest("invalid poll question"));
+    }
+    if poll.options.len() < 2 || poll.options.len() > MAX_POLL_OPTIONS {
+        return Err(DeliveryError::BadRequest("a poll needs 2 to 10 options"));
+    }
+    let mut seen = HashSet::new();
+    for option in &poll.options {
+        let option = option.trim();
+        if option.is_empty() || option.chars().count() > MAX_POLL_OPTION_CHARS {
+            return Err(DeliveryError::BadRequest("invalid poll option"));
+        }
+        if !seen.insert(option.to_lowercase()) {
+            return Err(DeliveryError::BadRequest("duplicate poll option"));
+        }
+    }
+    match poll.correct_option_id {
+        Some(_) if poll.allows_multiple => {
+            return Err(DeliveryError::BadRequest("a quiz has a single correct answer"));
+        }
+        Some(correct) if correct < 0 || correct as usize >= poll.options.len() => {
+            return Err(DeliveryError::BadRequest("invalid correct_option_id"));
+        }
+        None if poll.explanation.is_some() => {
+            return Err(DeliveryError::BadRequest("only quizzes have an explanation"));
+        }
+        _ => {}
+    }
+    if poll.explanation.as_deref().is_some_and(|e| e.trim().chars().count() > MAX_POLL_EXPLANATION_CHARS) {
+        return Err(DeliveryError::BadRequest("explanation too long"));
+    }
+    if let Some(close_at) = poll.close_at {
+        let now = Utc::now();
+        if close_at <= now || close_at > now + chrono::Duration::days(365) {
+            return Err(DeliveryError::BadRequest("invalid close_at"));
+        }
+    }
+    Ok(())
+}
+
+/// Store the poll a new `poll` message points at; options are numbered from 0 in order.
+async fn insert_poll(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    created_by: Uuid,
+    poll: &PollSendReq,
+) -> Result<Uuid, DeliveryError> {
+    let poll_id = Uuid::new_v4();
+    sqlx::query(
+        "INSERT INTO polls (id, created_by, question, is_anonymous, allows_multiple, is_quiz, correct_option_id, explanation, close_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(poll_id)
+    .bind(created_by)
+    .bind(poll.question.trim())
+    .bind(poll.is_anonymous)
+    .bind(poll.allows_multiple)
+    .bind(poll.correct_option_id.is_some())
+    .bind(poll.correct_option_id)
+    .bind(poll.explanation.as_deref().map(str::trim).filter(|e| !e.is_empty()))
+    .bind(poll.close_at)
+    .execute(&mut **tx)
+    .await?;
+    for (option_id, text) in poll.options.iter().enumerate() {
+        sqlx::query("INSERT INTO poll_options (poll_id, option_id, text) VALUES ($1, $2, $3)")
+            .bind(poll_id)
+            .bind(option_id as i32)
+            .bind(text.trim())
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(poll_id)
+}
+
+async fn validate_reply_and_attachments(state: &AppState, msg: &OutgoingMessage) -> Result<(), DeliveryError> {
+    if let Some(rid) = msg.reply_to_message_id {
+        let ok = sqlx::query_scalar::<_, i32>(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2",
+        )
+        .bind(rid)
+        .bind(msg.chat_id)
+        .fetch_optional(&state.pool)
+        .await?
+        .is_some();
+        if !ok {
+            return Err(DeliveryError::BadRequest("invalid reply_to_message_id"));
+        }
+    }
+    if !msg.attachment_ids.is_empty() {
+        let unique: HashSet<&Uuid> = msg.attachment_ids.iter().collect();
+        let found: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM storage_files WHERE id = ANY($1)")
+            .bind(&msg.attachment_ids)
+            .fetch_one(&state.pool)
+            .await?;
+        if found as usize != unique.len() {
+            return Err(DeliveryError::BadRequest("invalid attachment id"));
+        }
+    }
+    Ok(())
+}
+
+/// The message's self-destruct timer and whether it waits for the first read. An explicit
+/// `expires_in` on a message with attachments is one-time media: its countdown starts when
+/// someone else first reads it. Otherwise the timer (or the chat's auto-delete period)
+/// runs from the moment it is sent.
+async fn message_ttl(state: &AppState, msg: &OutgoingMessage) -> Result<(Option<i32>, bool), DeliveryError> {
+    if let Some(ttl) = msg.expires_in {
+        if !(1..=MAX_TTL_SECONDS).contains(&ttl) {
+            return Err(DeliveryError::BadRequest("invalid expires_in"));
+        }
+        return Ok((Some(ttl), !msg.attachment_ids.is_empty()));
+    }
+    let auto_delete: Option<i32> = sqlx::query_scalar("SELECT auto_delete_seconds FROM chats WHERE id = $1")
+        .bind(msg.chat_id)
+        .fetch_optional(&state.pool)
+        .await?
+        .flatten();
+    Ok((auto_delete, false))
+}
+
+/// Replies to a reply land in the same thread: threads are one level deep.
+async fn resolve_thread_root(state: &AppState, msg: &OutgoingMessage) -> Result<Option<Uuid>, DeliveryError> {
+    let Some(root_id) = msg.thread_root_id else {
+        return Ok(None);
+    };
+    #[derive(sqlx::FromRow)]
+    struct Root {
+        chat_id: Uuid,
+        kind: String,
+        is_deleted: bool,
+        thread_root_id: Option<Uuid>,
+    }
+    let root = sqlx::query_as::<_, Root>("SELECT chat_id, kind, is_deleted, thread_root_id FROM messages WHERE id = $1")
+        .bind(root_id)
+        .fetch_optional(&state.pool)
+        .await?
+        .filter(|r| r.chat_id == msg.chat_id && !r.is_deleted && r.kind != "service")
+        .ok_or(DeliveryError::BadRequest("invalid thread_root_id"))?;
+    Ok(Some(root.thread_root_id.unwrap_or(root_id)))
+}
+
+pub(crate) fn extract_mentions(content: &str) -> Vec<String> {
+    let mut mentions = Vec::new();
+    let mut chars = content.chars().enumerate().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if c == '@' {
+            let mut name = String::new();
+            let mut j = idx + 1;
+            while let Some((pos, ch)) = chars.peek() {
+                if *pos != j {
+                    break;
+                }
+                if ch.is_alphanumeric() || *ch == '_' {
+                    name.push(*ch);
+                    chars.next();
+                    j += 1;
+                } else {
+                    break;
+                }
+            }
+            if !name.is_empty() {
+                mentions.push(name.to_lowercase());
+            }
+        }
+    }
+    mentions
+}
+
+/// Participants mentioned in `content`, by `@username` or by `text_mention` entity;
+/// an edit re-parses them with this. `entities` must already be normalized.
+pub(crate) async fn mentioned_users(
+    state: &AppState,
+    chat_id: Uuid,
+    sender_id: Uuid,
+    content: &str,
+    entities: &[MessageEntity],
+) -> Result<Vec<Uuid>, DeliveryError> {
+    let tokens = extract_mentions(content);
+    let by_id: Vec<Uuid> = entities.iter().filter_map(|e| e.user_id).collect();
+    if tokens.len() + by_id.len() > MAX_MENTIONS {
+        return Err(DeliveryError::TooManyMentions);
+    }
+    let mut mentioned = if tokens.is_empty() {
+        Vec::new()
+    } else {
+        resolve_mentions(state, chat_id, sender_id, &tokens).await?
+    };
+    if !by_id.is_empty() {
+        let members: Vec<Uuid> =
+            sqlx::query_scalar("SELECT user_id FROM chat_participants WHERE chat_id = $1 AND user_id = ANY($2)")
+                .bind(chat_id)
+                .bind(&by_id)
+                .fetch_all(&state.pool)
+                .await?;
+        for uid in by_id {
+            if !members.contains(&uid) {
+                return Err(DeliveryError::BadRequest("mentioned user is not in the chat"));
+            }
+            if uid != sender_id && !mentioned.contains(&uid) {
+                mentioned.push(uid);
+            }
+        }
+    }
+    Ok(mentioned)
+}
+
+/// Check `entities` against `content` and rebase them onto `content.trim()`, the text that
+/// is stored. Returns them ordered by position.
+pub(crate) fn normalize_entities(content: &str, entities: &[MessageEntity]) -> Result<Vec<MessageEntity>, DeliveryError> {
+    if entities.is_empty() {
+        return Ok(Vec::new());
+    }
+    if entities.len() > MAX_ENTITIES {
+        return Err(DeliveryError::BadRequest("too many entities"));
+    }
+    let trimmed_start = content.trim_start();
+    let shift = content[..content.len() - trimmed_start.len()].encode_utf16().count() as i64;
+    let len = content.trim().encode_utf16().count() as i64;
+    let mut out = Vec::with_capacity(entities.len());
+    for entity in entities {
+        let offset = i64::from(entity.offset) - shift;
+        if entity.length <= 0 || offset < 0 || offset + i64::from(entity.length) > len {
+            return Err(DeliveryError::BadRequest("entity out of range"));
+        }
+        let attrs_ok = match entity.kind {
+            MessageEntityType::TextLink => {
+                entity.user_id.is_none()
+                    && entity.language.is_none()
+                    && entity.url.as_deref().is_some_and(valid_entity_url)
+            }
+            MessageEntityType::TextMention => {
+                entity.user_id.is_some() && entity.url.is_none() && entity.language.is_none()
+            }
+            MessageEntityType::Pre => {
+                entity.url.is_none()
+                    && entity.user_id.is_none()
+                    && entity.language.as_deref().is_none_or(|l| !l.is_empty() && l.len() <= MAX_PRE_LANGUAGE_LEN)
+            }
+            _ => entity.url.is_none() && entity.user_id.is_none() && entity.language.is_none(),
+        };
+        if !attrs_ok {
+            return Err(DeliveryError::BadRequest("invalid entity"));
+        }
+        out.push(MessageEntity { offset: offset as i32, ..entity.clone() });
+    }
+    out.sort_by_key(|e| (e.offset, -e.length));
+    // code is shown verbatim, so nothing may be nested in or around it
+    for (i, a) in out.iter().enumerate() {
+        for b in &out[i + 1..] {
+            let overlaps = b.offset < a.offset + a.length;
+            let verbatim = |e: &MessageEntity| matches!(e.kind, MessageEntityType::Code | MessageEntityType::Pre);
+            if overlaps && (verbatim(a) || verbatim(b)) {
+                return Err(DeliveryError::BadRequest("code entities cannot overlap other entities"));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn valid_entity_url(url: &str) -> bool {
+    url.len() <= MAX_ENTITY_URL_LEN
+        && ["http://", "https://", "mailto:"]
+            .iter()
+            .any(|scheme| url.len() > scheme.len() && url.get(..scheme.len()).is_some_and(|p| p.eq_ignore_ascii_case(scheme)))
+}
+
+/// Map `@username` tokens to participants of the chat, skipping the sender.
+async fn resolve_mentions(
+    state: &AppState,
+    chat_id: Uuid,
+    sender_id: Uuid,
+    tokens: &[String],
+) -> Result<Vec<Uuid>, DeliveryError> {
+    #[derive(sqlx::FromRow)]
+    struct Participant {
+        user_id: Uuid,
+        username: String,
+    }
+    let participants: Vec<Participant> = sqlx::query_as(
+        "SELECT u.id as user_id, u.username FROM chat_participants cp JOIN users u ON u.id = cp.user_id WHERE cp.chat_id = $1",
+    )
+    .bind(chat_id)
+    .fetch_all(&state.pool)
+    .await?;
+    let map: HashMap<String, Uuid> = participants
+        .into_iter()
+        .filter(|p| p.user_id != sender_id)
+        .map(|p| (p.username.to_lowercase(), p.user_id))
+        .collect();
+    let mut seen = HashSet::new();
+    Ok(tokens
+        .iter()
+        .filter_map(|name| map.get(name).copied())
+        .filter(|uid| seen.insert(*uid))
+        .collect())
+}
@@ -0,0 +1,182 @@
+use crate::fanout::Envelope;
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use tracing::{error, info};
+
+/// One page of replayed events returned to a `sync` request.
+pub struct ReplayPage {
+    pub events: Vec<ServerWsMsg>,
+    pub has_more: bool,
+    /// The client asked for events that retention already dropped and must refetch state over HTTP.
+    pub resync_required: bool,
+    /// The user's latest allocated sequence id at the time of the replay.
+    pub last_sequence_id: u64,
+}
+
+/// Fan `msg` out to `user_ids`, returning the sequence id each recipient received it under.
+///
+/// Durable events take the next id in each recipient's own sequence and are appended to
+/// that user's log before delivery, so a client that sees a jump in `sequence_id` knows
+/// exactly which events it missed. Live-only events carry the recipient's current
+/// sequence id without advancing it.
+pub async fn publish(state: &AppState, user_ids: &[Uuid], msg: ServerWsMsg) -> anyhow::Result<Vec<(Uuid, u64)>> {
+    let mut user_ids = user_ids.to_vec();
+    user_ids.sort_unstable();
+    user_ids.dedup();
+    if user_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !msg.is_durable() {
+        let current = current_sequence_ids(state, &user_ids).await?;
+        let deliveries: Vec<(Uuid, u64)> = user_ids
+            .into_iter()
+            .map(|uid| (uid, current.get(&uid).copied().unwrap_or(0)))
+            .collect();
+        state.fanout.publish(Envelope { msg, deliveries: deliveries.clone() }).await?;
+        return Ok(deliveries);
+    }
+
+    // Allocating from user_event_state locks each recipient's row until the event is
+    // written, so ids within one user's sequence are gap-free and committed in order.
+    let payload = serde_json::to_value(&msg)?;
+    let rows: Vec<(Uuid, i64)> = sqlx::query_as(
+        r#"WITH seqs AS (
+               INSERT INTO user_event_state (user_id, last_sequence_id)
+               SELECT u, 1 FROM unnest($1::uuid[]) AS u
+               ON CONFLICT (user_id) DO UPDATE
+               SET last_sequence_id = user_event_state.last_sequence_id + 1
+               RETURNING user_id, last_sequence_id
+           )
+           INSERT INTO user_events (user_id, sequence_id, event)
+           SELECT user_id, last_sequence_id, $2 FROM seqs
+           RETURNING user_id, sequence_id"#,
+    )
+    .bind(&user_ids)
+    .bind(payload)
+    .fetch_all(&state.pool)
+    .await?;
+    let deliveries: Vec<(Uuid, u64)> = rows.into_iter().map(|(uid, seq)| (uid, seq as u64)).collect();
+    state.fanout.publish(Envelope { msg, deliveries: deliveries.clone() }).await?;
+    Ok(deliveries)
+}
+
+/// Fan `msg` out to every current participant of `chat_id`.
+pub async fn publish_to_chat(state: &AppState, chat_id: Uuid, msg: ServerWsMsg) -> anyhow::Result<Vec<(Uuid, u64)>> {
+    let participants = sqlx::query_scalar::<_, Uuid>(
+        "SELECT user_id FROM chat_participants WHERE chat_id = $1",
+    )
+    .bind(chat_id)
+    .fetch_all(&state.pool)
+    .await?;
+    publish(state, &participants, msg).await
+}
+
+/// Send a live-only reply (ack, error) to one session, stamped with the user's current sequence id.
+pub async fn reply(state: &AppState, user_id: Uuid, session_id: Uuid, mut msg: ServerWsMsg) -> anyhow::Result<()> {
+    let current = current_sequence_ids(state, &[user_id]).await?;
+    msg.set_sequence_id(current.get(&user_id).copied().unwrap_or(0));
+    send_to_session(state, user_id, session_id, msg);
+    Ok(())
+}
+
+/// Hand `msg` to a single session of the user on this node, e.g. a reply to a request that session made.
+pub fn send_to_session(state: &AppState, user_id: Uuid, session_id: Uuid, msg: ServerWsMsg) {
+    if let Some(handle) = state.clients.get(&user_id).and_then(|s| s.get(&session_id).cloned()) {
+        handle.send(msg);
+    }
+}
+
+async fn current_sequence_ids(state: &AppState, user_ids: &[Uuid]) -> anyhow::Result<HashMap<Uuid, u64>> {
+    let rows: Vec<(Uuid, i64)> = sqlx::query_as(
+        "SELECT user_id, last_sequence_id FROM user_event_state WHERE user_id = ANY($1)",
+    )
+    .bind(user_ids)
+    .fetch_all(&state.pool)
+    .await?;
+    Ok(rows.into_iter().map(|(uid, seq)| (uid, seq as u64)).collect())
+}
+
+pub async fn replay(
+    state: &AppState,
+    user_id: Uuid,
+    last_sequence_id: u64,
+    limit: u32,
+) -> anyhow::Result<ReplayPage> {
+    let (purged_through, current): (i64, i64) = sqlx::query_as(
+        "SELECT purged_through, last_sequence_id FROM user_event_state WHERE user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?
+    .unwrap_or((0, 0));
+    if last_sequence_id < purged_through as u64 {
+        return Ok(ReplayPage {
+            events: Vec::new(),
+            has_more: false,
+            resync_required: true,
+            last_sequence_id: current as u64,
+        });
+    }
+
+    let limit = limit.clamp(1, state.config.events.sync_page_size.max(1));
+    let rows: Vec<(i64, serde_json::Value)> = sqlx::query_as(
+        "SELECT sequence_id, event FROM user_events WHERE user_id = $1 AND sequence_id > $2 ORDER BY sequence_id ASC LIMIT $3",
+    )
+    .bind(user_id)
+    .bind(last_sequence_id as i64)
+    .bind(limit as i64 + 1)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let has_more = rows.len() > limit as usize;
+    let mut events = Vec::with_capacity(rows.len());
+    for (seq, event) in rows.into_iter().take(limit as usize) {
+        let mut msg: ServerWsMsg = serde_json::from_value(event)?;
+        msg.set_sequence_id(seq as u64);
+        events.push(msg);
+    }
+    Ok(ReplayPage {
+        events,
+        has_more,
+        resync_required: false,
+        last_sequence_id: current as u64,
+    })
+}
+
+/// Purge events past their retention until the process exits.
+pub fn spawn_purger(state: AppState) {
+    let interval = std::time::Duration::from_secs(state.config.events.purge_interval_secs.max(1));
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            match purge_expired(&state).await {
+                Ok(0) => {}
+                Ok(purged) => info!(purged, "purged expired events"),
+                Err(e) => error!(?e, "event purge error"),
+            }
+        }
+    });
+}
+
+/// Drop events older than the configured retention and remember, per user, how far the log was cut.
+pub async fn purge_expired(state: &AppState) -> anyhow::Result<u64> {
+    let threshold = chrono::Utc::now()
+        - chrono::Duration::hours(state.config.events.retention_hours as i64);
+    let purged: i64 = sqlx::query_scalar(
+        r#"WITH purged AS (
+               DELETE FROM user_events WHERE created_at < $1 RETURNING user_id, sequence_id
+           ), horizons AS (
+               UPDATE user_event_state s SET purged_through = GREATEST(s.purged_through, p.max_seq)
+               FROM (SELECT user_id, MAX(sequence_id) AS max_seq FROM purged GROUP BY user_id) p
+               WHERE s.user_id = p.user_id
+           )
+           SELECT COUNT(*) FROM purged"#,
+    )
+    .bind(threshold)
+    .fetch_one(&state.pool)
+    .await?;
+    Ok(purged as u64)
+}
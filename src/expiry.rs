@@ -0,0 +1,77 @@
+use crate::events;
+use crate::handlers::polls;
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use tracing::{error, info};
+
+/// Delete expired self-destructing messages and close due polls until the process exits.
+pub fn spawn_reaper(state: AppState) {
+    let interval = std::time::Duration::from_millis(state.config.expiry.poll_interval_ms.max(50));
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            if let Err(e) = reap_expired(&state).await {
+                error!(?e, "message expiry error");
+            }
+            // polls past their close date are the other thing that runs out on a timer
+            if let Err(e) = polls::close_due(&state).await {
+                error!(?e, "poll close error");
+            }
+        }
+    });
+}
+
+/// Hard-delete one batch of expired messages, with their thread replies, and broadcast
+/// `message_deleted` per chat; returns how many messages were deleted. Attachments links,
+/// reads, mentions and reactions go with the rows through their cascading foreign keys.
+///
+/// Expired rows are locked with `FOR UPDATE SKIP LOCKED`, so several instances can reap
+/// the same database without deleting (and announcing) a message twice.
+pub async fn reap_expired(state: &AppState) -> anyhow::Result<usize> {
+    let mut tx = state.pool.begin().await?;
+    let expired: Vec<Uuid> = sqlx::query_scalar(
+        "SELECT id FROM messages WHERE expires_at <= now() ORDER BY expires_at LIMIT $1 FOR UPDATE SKIP LOCKED",
+    )
+    .bind(state.config.expiry.batch_size.max(1))
+    .fetch_all(&mut *tx)
+    .await?;
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    // keep read positions that pointed at a doomed message, so the chat does not turn all unread
+    sqlx::query(
+        "UPDATE chat_members cm SET last_read_message_id = (
+             SELECT m.id FROM messages m
+             WHERE m.chat_id = cm.chat_id AND m.thread_root_id IS NULL AND m.id <> ALL($1) AND m.created_at < d.created_at
+             ORDER BY m.created_at DESC LIMIT 1)
+         FROM messages d
+         WHERE d.id = cm.last_read_message_id AND d.id = ANY($1)",
+    )
+    .bind(&expired)
+    .execute(&mut *tx)
+    .await?;
+
+    let deleted: Vec<(Uuid, Uuid)> = sqlx::query_as(
+        "DELETE FROM messages WHERE id = ANY($1) OR thread_root_id = ANY($1) RETURNING id, chat_id",
+    )
+    .bind(&expired)
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let count = deleted.len();
+    let mut by_chat: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (id, chat_id) in deleted {
+        by_chat.entry(chat_id).or_default().push(id);
+    }
+    for (chat_id, message_ids) in by_chat {
+        info!(%chat_id, count = message_ids.len(), "expired messages deleted");
+        let msg = ServerWsMsg::MessageDeleted { sequence_id: 0, chat_id, message_ids };
+        events::publish_to_chat(state, chat_id, msg).await?;
+    }
+    Ok(count)
+}
@@ -0,0 +1,337 @@
+use crate::config::FanoutConfig;
+use crate::state::UserSessions;
+use crate::ws::ServerWsMsg;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use tracing::{error, info, warn};
+
+/// Live sessions held by this process, keyed by user.
+pub type LocalSessions = Arc<DashMap<Uuid, UserSessions>>;
+
+/// One server event and the users it goes to, each stamped with their own sequence id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub msg: ServerWsMsg,
+    pub deliveries: Vec<(Uuid, u64)>,
+}
+
+/// Carries events to whichever node holds the recipients' sessions and tracks
+/// how many sessions each user has open across the cluster.
+pub trait FanoutBus: Send + Sync {
+    /// Deliver `envelope` to the recipients' sessions on every node.
+    fn publish(&self, envelope: Envelope) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// Count a newly opened session; returns true if it is the user's first anywhere.
+    fn session_opened(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<bool>>;
+    /// Count a closed session; returns true if it was the user's last anywhere.
+    fn session_closed(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<bool>>;
+    /// Which of `user_ids` still have a session open on a live node.
+    fn online(&self, user_ids: Vec<Uuid>) -> BoxFuture<'_, anyhow::Result<HashSet<Uuid>>>;
+}
+
+/// Bump the user's session count on this node; true if it was the first.
+fn count_opened(counts: &DashMap<Uuid, usize>, user_id: Uuid) -> bool {
+    let mut count = counts.entry(user_id).or_insert(0);
+    *count += 1;
+    *count == 1
+}
+
+/// Drop the user's session count on this node; true if it was the last.
+fn count_closed(counts: &DashMap<Uuid, usize>, user_id: Uuid) -> bool {
+    match counts.entry(user_id) {
+        Entry::Occupied(mut count) => {
+            *count.get_mut() = count.get().saturating_sub(1);
+            if *count.get() == 0 {
+                count.remove();
+                true
+            } else {
+                false
+            }
+        }
+        Entry::Vacant(_) => true,
+    }
+}
+
+/// Hand every delivery in `envelope` to the matching sessions on this node.
+pub fn deliver_local(sessions: &LocalSessions, envelope: Envelope) {
+    for (user_id, sequence_id) in envelope.deliveries {
+        let Some(user_sessions) = sessions.get(&user_id) else {
+            continue;
+        };
+        let mut msg = envelope.msg.clone();
+        msg.set_sequence_id(sequence_id);
+        for handle in user_sessions.values() {
+            handle.send(msg.clone());
+        }
+    }
+}
+
+/// Build the bus selected by `fanout.backend`.
+pub fn from_config(
+    config: &FanoutConfig,
+    redis: Option<&redis::Client>,
+    sessions: LocalSessions,
+) -> anyhow::Result<Arc<dyn FanoutBus>> {
+    match config.backend.as_str() {
+        "local" => Ok(Arc::new(LocalBus::new(sessions))),
+        "redis" => {
+            let client = redis
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("fanout backend redis requires redis.url"))?;
+            let bus = Arc::new(RedisBus::new(client, config.channel.clone(), config.session_ttl_secs, sessions));
+            bus.spawn_subscriber();
+            Ok(bus)
+        }
+        other => anyhow::bail!("unknown fanout backend: {}", other),
+    }
+}
+
+/// Single-node bus: delivers straight to this process' sessions.
+pub struct LocalBus {
+    sessions: LocalSessions,
+    counts: DashMap<Uuid, usize>,
+}
+
+impl LocalBus {
+    pub fn new(sessions: LocalSessions) -> Self {
+        Self { sessions, counts: DashMap::new() }
+    }
+}
+
+impl FanoutBus for LocalBus {
+    fn publish(&self, envelope: Envelope) -> BoxFuture<'_, anyhow::Result<()>> {
+        deliver_local(&self.sessions, envelope);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn session_opened(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<bool>> {
+        let first = count_opened(&self.counts, user_id);
+        Box::pin(async move { Ok(first) })
+    }
+
+    fn session_closed(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<bool>> {
+        let last = count_closed(&self.counts, user_id);
+        Box::pin(async move { Ok(last) })
+    }
+
+    fn online(&self, user_ids: Vec<Uuid>) -> BoxFuture<'_, anyhow::Result<HashSet<Uuid>>> {
+        let online = user_ids.into_iter().filter(|id| self.counts.contains_key(id)).collect();
+        Box::pin(async move { Ok(online) })
+    }
+}
+
+/// Multi-node bus over Redis pub/sub. Every node subscribes to one channel and
+/// delivers each envelope to the sessions it holds.
+///
+/// Sessions are counted per node. Each node keeps `{channel}:node:{node_id}` alive with a
+/// TTL and lists itself in `{channel}:sessions:{user_id}` for every user it holds a session
+/// of; a user is online while any node in that set is still alive. A node that dies stops
+/// refreshing its key, so its users drop offline once the TTL runs out instead of staying
+/// "online" forever.
+pub struct RedisBus {
+    client: redis::Client,
+    channel: String,
+    node_id: Uuid,
+    session_ttl_secs: u64,
+    sessions: LocalSessions,
+    counts: Arc<DashMap<Uuid, usize>>,
+    conn: OnceCell<redis::aio::MultiplexedConnection>,
+}
+
+/// Update one user's node set and count the other nodes in it that are still alive,
+/// dropping the dead ones on the way.
+///
+/// KEYS[1]: the user's node set. ARGV: this node, the node key prefix, "add", "remove" or
+/// "check", and the set's TTL in seconds.
+const NODES_SCRIPT: &str = r#"
+if ARGV[3] == 'add' then
+    redis.call('SADD', KEYS[1], ARGV[1])
+    redis.call('EXPIRE', KEYS[1], ARGV[4])
+elseif ARGV[3] == 'remove' then
+    redis.call('SREM', KEYS[1], ARGV[1])
+end
+local alive = 0
+for _, node in ipairs(redis.call('SMEMBERS', KEYS[1])) do
+    if node ~= ARGV[1] then
+        if redis.call('EXISTS', ARGV[2] .. node) == 1 then
+            alive = alive + 1
+        else
+            redis.call('SREM', KEYS[1], node)
+        end
+    end
+end
+return alive
+"#;
+
+impl RedisBus {
+    pub fn new(client: redis::Client, channel: String, session_ttl_secs: u64, sessions: LocalSessions) -> Self {
+        Self {
+            client,
+            channel,
+            node_id: Uuid::new_v4(),
+            session_ttl_secs: session_ttl_secs.max(3),
+            sessions,
+            counts: Arc::new(DashMap::new()),
+            conn: OnceCell::new(),
+        }
+    }
+
+    async fn conn(&self) -> anyhow::Result<redis::aio::MultiplexedConnection> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| self.client.get_multiplexed_async_connection())
+            .await?;
+        Ok(conn.clone())
+    }
+
+    fn session_key(&self, user_id: Uuid) -> String {
+        format!("{}:sessions:{}", self.channel, user_id)
+    }
+
+    fn node_prefix(&self) -> String {
+        format!("{}:node:", self.channel)
+    }
+
+    /// Run [`NODES_SCRIPT`] for one user; returns how many other live nodes hold their sessions.
+    async fn other_nodes(&self, user_id: Uuid, op: &str) -> anyhow::Result<i64> {
+        let mut conn = self.conn().await?;
+        let node = if op == "check" { String::new() } else { self.node_id.to_string() };
+        Ok(redis::Script::new(NODES_SCRIPT)
+            .key(self.session_key(user_id))
+            .arg(node)
+            .arg(self.node_prefix())
+            .arg(op)
+            .arg(self.session_ttl_secs)
+            .invoke_async(&mut conn)
+            .await?)
+    }
+
+    /// Listen on the channel for the lifetime of the process, reconnecting on failure, and
+    /// keep this node's session keys alive alongside it.
+    pub fn spawn_subscriber(&self) {
+        let client = self.client.clone();
+        let channel = self.channel.clone();
+        let sessions = self.sessions.clone();
+        tokio::spawn(async move {
+            loop {
+                match subscribe(&client, &channel, &sessions).await {
+                    Ok(()) => warn!(%channel, "fanout subscription ended, reconnecting"),
+                    Err(e) => error!(%channel, ?e, "fanout subscription failed, reconnecting"),
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        });
+        let heartbeat = Heartbeat {
+            client: self.client.clone(),
+            node_key: format!("{}{}", self.node_prefix(), self.node_id),
+            node_id: self.node_id.to_string(),
+            session_prefix: format!("{}:sessions:", self.channel),
+            ttl_secs: self.session_ttl_secs,
+            counts: self.counts.clone(),
+        };
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(heartbeat.ttl_secs / 3));
+            loop {
+                tick.tick().await;
+                if let Err(e) = heartbeat.beat().await {
+                    error!(?e, "fanout heartbeat failed");
+                }
+            }
+        });
+    }
+}
+
+/// What the heartbeat task needs to keep this node's keys alive.
+struct Heartbeat {
+    client: redis::Client,
+    node_key: String,
+    node_id: String,
+    session_prefix: String,
+    ttl_secs: u64,
+    counts: Arc<DashMap<Uuid, usize>>,
+}
+
+impl Heartbeat {
+    /// Refresh the node key and re-list this node under every user it holds sessions of,
+    /// in case another node pruned it while a refresh was late.
+    async fn beat(&self) -> anyhow::Result<()> {
+        let mut conn = self.client.get_multiplexed_async_connection().await?;
+        let mut pipe = redis::pipe();
+        pipe.set_ex(&self.node_key, 1, self.ttl_secs).ignore();
+        let users: Vec<Uuid> = self.counts.iter().map(|c| *c.key()).collect();
+        for user_id in users {
+            let key = format!("{}{}", self.session_prefix, user_id);
+            pipe.sadd(&key, &self.node_id).ignore();
+            pipe.expire(&key, self.ttl_secs as i64).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+}
+
+async fn subscribe(client: &redis::Client, channel: &str, sessions: &LocalSessions) -> anyhow::Result<()> {
+    let mut pubsub = client.get_async_pubsub().await?;
+    pubsub.subscribe(channel).await?;
+    info!(%channel, "fanout subscribed");
+    let mut stream = pubsub.on_message();
+    while let Some(msg) = stream.next().await {
+        let payload: Vec<u8> = msg.get_payload()?;
+        match serde_json::from_slice::<Envelope>(&payload) {
+            Ok(envelope) => deliver_local(sessions, envelope),
+            Err(e) => error!(?e, "invalid fanout envelope"),
+        }
+    }
+    Ok(())
+}
+
+impl FanoutBus for RedisBus {
+    fn publish(&self, envelope: Envelope) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let payload = serde_json::to_vec(&envelope)?;
+            let mut conn = self.conn().await?;
+            conn.publish::<_, _, ()>(&self.channel, payload).await?;
+            Ok(())
+        })
+    }
+
+    fn session_opened(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move {
+            if !count_opened(&self.counts, user_id) {
+                return Ok(false);
+            }
+            // the node key must be live before anyone can see this node in a user's set
+            let mut conn = self.conn().await?;
+            conn.set_ex::<_, _, ()>(format!("{}{}", self.node_prefix(), self.node_id), 1, self.session_ttl_secs)
+                .await?;
+            Ok(self.other_nodes(user_id, "add").await? == 0)
+        })
+    }
+
+    fn session_closed(&self, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<bool>> {
+        Box::pin(async move {
+            if !count_closed(&self.counts, user_id) {
+                return Ok(false);
+            }
+            Ok(self.other_nodes(user_id, "remove").await? == 0)
+        })
+    }
+
+    fn online(&self, user_ids: Vec<Uuid>) -> BoxFuture<'_, anyhow::Result<HashSet<Uuid>>> {
+        Box::pin(async move {
+            let mut online = HashSet::new();
+            for user_id in user_ids {
+                if self.counts.contains_key(&user_id) || self.other_nodes(user_id, "check").await? > 0 {
+                    online.insert(user_id);
+                }
+            }
+            Ok(online)
+        })
+    }
+}
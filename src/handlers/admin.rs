@@ -1,4 +1,5 @@
 use crate::auth::internal_err;
+use crate::events;
 use crate::state::AppState;
 use actix_web::{post, web, HttpResponse};
 use tracing::info;
@@ -22,15 +23,34 @@ pub async fn purge_storage(
     Ok(HttpResponse::Ok().json(serde_json::json!({"deleted": deleted})))
 }
 
+#[post("/v1/api/admin/events/purge")]
+pub async fn purge_events(
+    state: web::Data<AppState>,
+    req: actix_web::HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let header = req
+        .headers()
+        .get("X-Admin-Token")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if state.admin_token.is_empty() || header != state.admin_token.as_str() {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let deleted = events::purge_expired(&state)
+        .await
+        .map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({"deleted": deleted})))
+}
+
 pub async fn purge_unreferenced_internal(state: &AppState) -> anyhow::Result<usize> {
-    // find files not referenced by message_attachments
+    // find files not referenced by message_attachments or a link preview
     #[derive(sqlx::FromRow)]
     struct Row {
         id: sqlx::types::Uuid,
         path: String,
     }
     let rows: Vec<Row> = sqlx::query_as::<_, Row>(
-        "SELECT f.id, f.path FROM storage_files f LEFT JOIN message_attachments m ON m.file_id = f.id WHERE m.file_id IS NULL"
+        "SELECT f.id, f.path FROM storage_files f LEFT JOIN message_attachments m ON m.file_id = f.id WHERE m.file_id IS NULL AND NOT EXISTS (SELECT 1 FROM link_previews lp WHERE lp.image_file_id = f.id)"
     ).fetch_all(&state.pool).await?;
 
     let mut deleted = 0usize;
@@ -31,8 +31,8 @@ pub async fn register(
     let refresh_token_str = Uuid::new_v4().to_string();
     // Store refresh_token in Redis with TTL
     if let Some(redis) = &state.redis {
-        let mut conn = redis.get_async_connection().await.map_err(internal_err)?;
-        let _: () = redis::cmd("SETEX").arg(&format!("refresh:{}", refresh_token_str)).arg(60*60*24*30).arg(rec.id.to_string()).query_async(&mut conn).await.map_err(internal_err)?;
+        let mut conn = redis.get_multiplexed_async_connection().await.map_err(internal_err)?;
+        let _: () = redis::cmd("SETEX").arg(format!("refresh:{}", refresh_token_str)).arg(60*60*24*30).arg(rec.id.to_string()).query_async(&mut conn).await.map_err(internal_err)?;
     }
     Ok(HttpResponse::Ok().json(AuthResp { token, refresh_token: refresh_token_str, user: rec }))
 }
@@ -75,12 +75,13 @@ pub async fn login(
         last_seen_at: row.last_seen_at,
         online_status_visibility: row.online_status_visibility,
         created_at: row.created_at,
+        last_seen: None,
     };
     let token = make_token(user.id, &state.jwt_secret)?;
     let refresh_token_str = Uuid::new_v4().to_string();
     if let Some(redis) = &state.redis {
-        let mut conn = redis.get_async_connection().await.map_err(internal_err)?;
-        let _: () = redis::cmd("SETEX").arg(&format!("refresh:{}", refresh_token_str)).arg(60*60*24*30).arg(user.id.to_string()).query_async(&mut conn).await.map_err(internal_err)?;
+        let mut conn = redis.get_multiplexed_async_connection().await.map_err(internal_err)?;
+        let _: () = redis::cmd("SETEX").arg(format!("refresh:{}", refresh_token_str)).arg(60*60*24*30).arg(user.id.to_string()).query_async(&mut conn).await.map_err(internal_err)?;
     }
     Ok(HttpResponse::Ok().json(AuthResp { token, refresh_token: refresh_token_str, user }))
 }
@@ -103,8 +104,8 @@ pub async fn refresh_token(
 ) -> actix_web::Result<HttpResponse> {
     let refresh_token = &payload.refresh_token;
     let user_id_str: Option<String> = if let Some(redis) = &state.redis {
-        let mut conn = redis.get_async_connection().await.map_err(internal_err)?;
-        redis::cmd("GET").arg(&format!("refresh:{}", refresh_token)).query_async(&mut conn).await.map_err(internal_err)?
+        let mut conn = redis.get_multiplexed_async_connection().await.map_err(internal_err)?;
+        redis::cmd("GET").arg(format!("refresh:{}", refresh_token)).query_async(&mut conn).await.map_err(internal_err)?
     } else {
         return Ok(HttpResponse::Unauthorized().finish());
     };
@@ -122,10 +123,10 @@ pub async fn refresh_token(
     let token = make_token(user.id, &state.jwt_secret)?;
     let new_refresh_token_str = Uuid::new_v4().to_string();
     if let Some(redis) = &state.redis {
-        let mut conn = redis.get_async_connection().await.map_err(internal_err)?;
-        let _: () = redis::cmd("SETEX").arg(&format!("refresh:{}", new_refresh_token_str)).arg(60*60*24*30).arg(user.id.to_string()).query_async(&mut conn).await.map_err(internal_err)?;
+        let mut conn = redis.get_multiplexed_async_connection().await.map_err(internal_err)?;
+        let _: () = redis::cmd("SETEX").arg(format!("refresh:{}", new_refresh_token_str)).arg(60*60*24*30).arg(user.id.to_string()).query_async(&mut conn).await.map_err(internal_err)?;
         // Optionally delete old refresh token
-        let _: () = redis::cmd("DEL").arg(&format!("refresh:{}", refresh_token)).query_async(&mut conn).await.map_err(internal_err)?;
+        let _: () = redis::cmd("DEL").arg(format!("refresh:{}", refresh_token)).query_async(&mut conn).await.map_err(internal_err)?;
     }
     Ok(HttpResponse::Ok().json(AuthResp { token, refresh_token: new_refresh_token_str, user }))
 }
@@ -1,4 +1,7 @@
 use crate::auth::{internal_err, AuthUser};
+use crate::handlers::drafts::load_drafts;
+use crate::models::DraftDto;
+use crate::pagination::{self, Anchor, Cursor};
 use crate::state::AppState;
 use actix_web::{get, web, HttpResponse};
 use serde::Deserialize;
@@ -9,6 +12,11 @@ use std::collections::HashMap;
 pub struct ListChatsQuery {
     pub include_unread: Option<bool>,
     pub include_first: Option<bool>,
+    pub limit: Option<usize>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// A chat id to centre the page on.
+    pub around: Option<Uuid>,
 }
 
 #[get("/v1/api/chats")]
@@ -28,12 +36,40 @@ pub async fn list_chats(
         is_public: bool,
         public_handle: Option<String>,
     }
-    let rows: Vec<Row> = sqlx::query_as::<_, Row>(
-        "SELECT c.id, c.is_direct, c.chat_type, c.title, c.created_at, c.pinned_message_id, c.is_public, c.public_handle FROM chats c JOIN chat_participants p ON p.chat_id = c.id WHERE p.user_id = $1 ORDER BY c.created_at DESC"
-    ).bind(user.0).fetch_all(&state.pool).await.map_err(internal_err)?;
+    let anchor = Anchor::from_query(q.before.as_deref(), q.after.as_deref(), q.around)?;
+    let around = match anchor.around() {
+        Some(chat_id) => {
+            let created_at: chrono::DateTime<chrono::Utc> = sqlx::query_scalar(
+                "SELECT c.created_at FROM chats c JOIN chat_participants p ON p.chat_id = c.id WHERE c.id = $1 AND p.user_id = $2",
+            )
+            .bind(chat_id)
+            .bind(user.0)
+            .fetch_optional(&state.pool)
+            .await
+            .map_err(internal_err)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("chat not found"))?;
+            Some(Cursor::new(created_at, chat_id))
+        }
+        None => None,
+    };
+    let pool = &state.pool;
+    let viewer = user.0;
+    let limit = pagination::limit(q.limit, 100);
+    let key = |r: &Row| Cursor::new(r.created_at, r.id);
+    let page = pagination::load(anchor, around, limit, key, |w| {
+        let sql = format!(
+            "SELECT c.id, c.is_direct, c.chat_type, c.title, c.created_at, c.pinned_message_id, c.is_public, c.public_handle FROM chats c JOIN chat_participants p ON p.chat_id = c.id WHERE p.user_id = $1 {}",
+            w.sql("c.created_at", "c.id", 2)
+        );
+        async move { w.bind(sqlx::query_as::<_, Row>(&sql).bind(viewer)).fetch_all(pool).await }
+    })
+    .await
+    .map_err(internal_err)?;
+    let rows = page.items;
 
     let pinned_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.pinned_message_id).collect();
     let pinned_map = load_pinned_messages(&state.pool, &pinned_ids).await?;
+    let mut drafts = load_drafts(&state.pool, user.0).await.map_err(internal_err)?;
 
     #[derive(serde::Serialize)]
     struct ChatOut {
@@ -47,6 +83,8 @@ pub async fn list_chats(
         is_public: bool,
         public_handle: Option<String>,
         pinned_message: Option<serde_json::Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        draft: Option<DraftDto>,
     }
     let mut out = Vec::with_capacity(rows.len());
     for r in rows {
@@ -58,13 +96,14 @@ pub async fn list_chats(
             ).bind(r.id).bind(user.0).fetch_optional(&state.pool).await.map_err(internal_err)?;
             let c: i64 = if let Some(t) = lr {
                 sqlx::query_scalar(
-                "SELECT COUNT(*) FROM messages WHERE chat_id = $1 AND is_deleted = FALSE AND created_at > $2"
-            ).bind(r.id).bind(t).fetch_one(&state.pool).await.map_err(internal_err)?
+                "SELECT COUNT(*) FROM messages WHERE chat_id = $1 AND is_deleted = FALSE AND kind <> 'service' AND thread_root_id IS NULL AND created_at > $2 AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = messages.id AND h.user_id = $3)"
+            ).bind(r.id).bind(t).bind(user.0).fetch_one(&state.pool).await.map_err(internal_err)?
             } else {
                 sqlx::query_scalar(
-                    "SELECT COUNT(*) FROM messages WHERE chat_id = $1 AND is_deleted = FALSE",
+                    "SELECT COUNT(*) FROM messages WHERE chat_id = $1 AND is_deleted = FALSE AND kind <> 'service' AND thread_root_id IS NULL AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = messages.id AND h.user_id = $2)",
                 )
                 .bind(r.id)
+                .bind(user.0)
                 .fetch_one(&state.pool)
                 .await
                 .map_err(internal_err)?
@@ -79,8 +118,8 @@ pub async fn list_chats(
                 created_at: chrono::DateTime<chrono::Utc>,
             }
             if let Some(m) = sqlx::query_as::<_, M>(
-                "SELECT id, content, created_at FROM messages WHERE chat_id = $1 AND is_deleted = FALSE ORDER BY created_at ASC LIMIT 1"
-            ).bind(r.id).fetch_optional(&state.pool).await.map_err(internal_err)? {
+                "SELECT id, content, created_at FROM messages WHERE chat_id = $1 AND is_deleted = FALSE AND thread_root_id IS NULL AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = messages.id AND h.user_id = $2) ORDER BY created_at ASC LIMIT 1"
+            ).bind(r.id).bind(user.0).fetch_optional(&state.pool).await.map_err(internal_err)? {
                 first = Some(serde_json::json!({"id": m.id, "content": m.content, "created_at": m.created_at}));
             }
         }
@@ -98,9 +137,14 @@ pub async fn list_chats(
             is_public: r.is_public,
             public_handle: r.public_handle,
             pinned_message: pinned_msg,
+            draft: drafts.remove(&r.id),
         });
     }
-    Ok(HttpResponse::Ok().json(out))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "chats": out,
+        "next": page.next,
+        "prev": page.prev,
+    })))
 }
 
 async fn load_pinned_messages(
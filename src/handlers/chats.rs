@@ -1,4 +1,4 @@
-use actix_web::{delete, get, patch, post, web, HttpResponse};
+use actix_web::{delete, get, post, web, HttpResponse};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::types::Uuid;
@@ -6,14 +6,22 @@ use sqlx::{Pool, Postgres};
 use tracing::{info, instrument};
 
 use crate::auth::{internal_err, AuthUser};
+use crate::chat_actions::{self, ChatAction, ChatActionType, StoredServiceAction};
+use crate::delivery::MAX_TTL_SECONDS;
 use crate::models::{
     AddParticipantReq, AdminPermissionsPayload, AdminReq, ChatDto, CreateChannelReq, CreateDirectChatReq,
     CreateGroupReq, ForwardedChatDto, ForwardedFromDto, GifMessageDto, ListQuery,
-    MessageAttachmentDto, MessageDto, MessageMentionDto, MessageReadReceiptDto, MessageReplyDto,
-    MuteReq, PromoteAdminReq, SetVisibilityReq, SimpleUserDto, StickerMessageDto, UnmuteReq,
+    MessageAttachmentDto, MessageDto, MessageEntity, MessageMentionDto, MessageReadReceiptDto, MessageReplyDto,
+    MessageTtlDto, MuteReq, PromoteAdminReq, ServiceActionDto, SetAutoDeleteReq, SetTitleReq, SetVisibilityReq, SimpleUserDto,
+    StickerMessageDto, TransferOwnershipReq, UnmuteReq,
 };
+use crate::handlers::polls::load_polls;
+use crate::link_preview::load_previews;
+use crate::pagination::{self, Anchor, Cursor};
+use crate::handlers::reactions::load_reactions;
+use crate::handlers::saved::SAVED_CHAT_TYPE;
+use crate::handlers::threads::load_threads;
 use crate::state::AppState;
-use crate::ws::ServerWsMsg;
 
 use std::collections::HashMap;
 
@@ -65,7 +73,7 @@ struct AdminPermRow {
     can_manage_members: bool,
 }
 
-async fn load_admin_perms(
+pub(crate) async fn load_admin_perms(
     pool: &Pool<Postgres>,
     chat_id: Uuid,
     user_id: Uuid,
@@ -87,7 +95,7 @@ async fn load_admin_perms(
     }))
 }
 
-fn has_perm(
+pub(crate) fn has_perm(
     perms: Option<&AdminPermissionsPayload>,
     f: impl Fn(&AdminPermissionsPayload) -> bool,
 ) -> bool {
@@ -115,7 +123,7 @@ pub async fn create_group(
     req: web::Json<CreateGroupReq>,
     user: AuthUser,
 ) -> actix_web::Result<HttpResponse> {
-    let chat_id = create_chat_with_owner(&state.pool, &req.title, "group", user.0).await?;
+    let chat_id = create_chat_with_owner(&state, &req.title, "group", user.0).await?;
     let chat_dto = build_chat_dto(&state.pool, chat_id, user.0).await?;
     Ok(HttpResponse::Created().json(chat_dto))
 }
@@ -127,19 +135,19 @@ pub async fn create_channel(
     req: web::Json<CreateChannelReq>,
     user: AuthUser,
 ) -> actix_web::Result<HttpResponse> {
-    let chat_id = create_chat_with_owner(&state.pool, &req.title, "channel", user.0).await?;
+    let chat_id = create_chat_with_owner(&state, &req.title, "channel", user.0).await?;
     let chat_dto = build_chat_dto(&state.pool, chat_id, user.0).await?;
     Ok(HttpResponse::Created().json(chat_dto))
 }
 
 async fn create_chat_with_owner(
-    pool: &Pool<Postgres>,
+    state: &AppState,
     title: &str,
     chat_type: &str,
     owner_id: Uuid,
 ) -> Result<Uuid, actix_web::Error> {
     let chat_id = Uuid::new_v4();
-    let mut tx = pool.begin().await.map_err(internal_err)?;
+    let mut tx = state.pool.begin().await.map_err(internal_err)?;
     sqlx::query("INSERT INTO chats (id, is_direct, chat_type, owner_id, title) VALUES ($1, FALSE, $2, $3, $4)")
         .bind(chat_id)
         .bind(chat_type)
@@ -163,9 +171,113 @@ async fn create_chat_with_owner(
     .await
     .map_err(internal_err)?;
     tx.commit().await.map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::ChatCreated, owner_id)
+        .payload(serde_json::json!({ "title": title.trim(), "chat_type": chat_type }));
+    chat_actions::record(state, action).await;
     Ok(chat_id)
 }
 
+#[post("/v1/api/chats/{chat_id}/title")]
+#[instrument(skip(state, req, user))]
+pub async fn set_title(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<SetTitleReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    let meta = fetch_chat_meta(&state.pool, chat_id).await?;
+    if meta.is_direct {
+        return Ok(HttpResponse::BadRequest().body("direct chats have no title"));
+    }
+    let perms = load_admin_perms(&state.pool, chat_id, user.0).await?;
+    if meta.owner_id != Some(user.0) && !has_perm(perms.as_ref(), |p| p.can_change_info) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let title = req.title.trim();
+    if title.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("title required"));
+    }
+    sqlx::query("UPDATE chats SET title = $1 WHERE id = $2")
+        .bind(title)
+        .bind(chat_id)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::TitleChanged, user.0)
+        .payload(serde_json::json!({ "title": title }));
+    chat_actions::record(&state, action).await;
+    Ok(HttpResponse::Ok().json(build_chat_dto(&state.pool, chat_id, user.0).await?))
+}
+
+#[post("/v1/api/chats/{chat_id}/auto_delete")]
+#[instrument(skip(state, req, user))]
+pub async fn set_auto_delete(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<SetAutoDeleteReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    let meta = fetch_chat_meta(&state.pool, chat_id).await?;
+    // either side of a direct chat may set the timer; elsewhere it is a chat setting
+    if meta.is_direct {
+        if !ensure_member(&state.pool, chat_id, user.0).await? {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+    } else {
+        let perms = load_admin_perms(&state.pool, chat_id, user.0).await?;
+        if meta.owner_id != Some(user.0) && !has_perm(perms.as_ref(), |p| p.can_change_info) {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+    }
+    if let Some(seconds) = req.seconds {
+        if !(1..=MAX_TTL_SECONDS).contains(&seconds) {
+            return Ok(HttpResponse::BadRequest().body("invalid auto-delete period"));
+        }
+    }
+    sqlx::query("UPDATE chats SET auto_delete_seconds = $1 WHERE id = $2")
+        .bind(req.seconds)
+        .bind(chat_id)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::AutoDeleteChanged, user.0)
+        .payload(serde_json::json!({ "seconds": req.seconds }));
+    chat_actions::record(&state, action).await;
+    Ok(HttpResponse::Ok().json(build_chat_dto(&state.pool, chat_id, user.0).await?))
+}
+
+#[post("/v1/api/chats/{chat_id}/transfer_ownership")]
+#[instrument(skip(state, req, user))]
+pub async fn transfer_ownership(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<TransferOwnershipReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    let meta = fetch_chat_meta(&state.pool, chat_id).await?;
+    if meta.owner_id != Some(user.0) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if req.user_id == user.0 {
+        return Ok(HttpResponse::BadRequest().body("already the owner"));
+    }
+    if !ensure_member(&state.pool, chat_id, req.user_id).await? {
+        return Ok(HttpResponse::BadRequest().body("user must join chat first"));
+    }
+    sqlx::query("UPDATE chats SET owner_id = $1 WHERE id = $2")
+        .bind(req.user_id)
+        .bind(chat_id)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::OwnershipTransferred, user.0).target(req.user_id);
+    chat_actions::record(&state, action).await;
+    Ok(HttpResponse::Ok().finish())
+}
+
 #[post("/v1/api/chats/{chat_id}/participants")]
 #[instrument(skip(state, req, user), fields(chat_id = %path))]
 pub async fn add_participant(
@@ -180,16 +292,20 @@ pub async fn add_participant(
     if meta.owner_id != Some(user.0) && !has_perm(perms.as_ref(), |p| p.can_invite_users) {
         return Ok(HttpResponse::Forbidden().finish());
     }
+    if meta.chat_type.as_deref() == Some(SAVED_CHAT_TYPE) {
+        return Ok(HttpResponse::BadRequest().body("saved messages are private"));
+    }
 
     let mut tx = state.pool.begin().await.map_err(internal_err)?;
-    sqlx::query(
+    let added = sqlx::query(
         "INSERT INTO chat_participants (chat_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
     )
     .bind(chat_id)
     .bind(req.user_id)
     .execute(&mut *tx)
     .await
-    .map_err(internal_err)?;
+    .map_err(internal_err)?
+    .rows_affected();
     sqlx::query(
         "INSERT INTO chat_members (chat_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
     )
@@ -200,6 +316,10 @@ pub async fn add_participant(
     .map_err(internal_err)?;
     tx.commit().await.map_err(internal_err)?;
 
+    if added > 0 {
+        let action = ChatAction::new(chat_id, ChatActionType::UserAdded, user.0).target(req.user_id);
+        chat_actions::record(&state, action).await;
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -319,6 +439,10 @@ pub async fn promote_admin(
         .map_err(internal_err)?;
 
     info!(chat_id = %chat_id, target = %req.user_id, "granted admin permissions");
+    let action = ChatAction::new(chat_id, ChatActionType::AdminPromoted, user.0)
+        .target(req.user_id)
+        .payload(serde_json::json!({ "permissions": perms }));
+    chat_actions::record(&state, action).await;
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "user_id": req.user_id,
         "username": username,
@@ -340,12 +464,17 @@ pub async fn demote_admin(
     if meta.owner_id != Some(user.0) {
         return Ok(HttpResponse::Forbidden().finish());
     }
-    sqlx::query("DELETE FROM chat_admin_permissions WHERE chat_id = $1 AND user_id = $2")
+    let demoted = sqlx::query("DELETE FROM chat_admin_permissions WHERE chat_id = $1 AND user_id = $2")
         .bind(chat_id)
         .bind(req.user_id)
         .execute(&state.pool)
         .await
-        .map_err(internal_err)?;
+        .map_err(internal_err)?
+        .rows_affected();
+    if demoted > 0 {
+        let action = ChatAction::new(chat_id, ChatActionType::AdminDemoted, user.0).target(req.user_id);
+        chat_actions::record(&state, action).await;
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -364,12 +493,13 @@ pub async fn remove_participant(
         return Ok(HttpResponse::Forbidden().finish());
     }
     let mut tx = state.pool.begin().await.map_err(internal_err)?;
-    sqlx::query("DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2")
+    let removed = sqlx::query("DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2")
         .bind(chat_id)
         .bind(req.user_id)
         .execute(&mut *tx)
         .await
-        .map_err(internal_err)?;
+        .map_err(internal_err)?
+        .rows_affected();
     sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
         .bind(chat_id)
         .bind(req.user_id)
@@ -378,32 +508,10 @@ pub async fn remove_participant(
         .map_err(internal_err)?;
     tx.commit().await.map_err(internal_err)?;
 
-    let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
-        .bind(req.user_id)
-        .fetch_one(&state.pool)
-        .await
-        .map_err(internal_err)?;
-
-    // Broadcast chat_action
-    let participants = sqlx::query_scalar::<_, Uuid>(
-        "SELECT user_id FROM chat_participants WHERE chat_id = $1",
-    )
-    .bind(chat_id)
-    .fetch_all(&state.pool)
-    .await
-    .map_err(internal_err)?;
-    let msg = ServerWsMsg::ChatAction {
-        sequence_id: state.sequence_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-        chat_id,
-        action_type: "user_left".to_string(),
-        data: serde_json::json!({ "user": { "id": req.user_id, "username": username } }),
-    };
-    for uid in participants {
-        if let Some(tx) = state.clients.get(&uid) {
-            let _ = tx.send(msg.clone());
-        }
+    if removed > 0 {
+        let action = ChatAction::new(chat_id, ChatActionType::UserRemoved, user.0).target(req.user_id);
+        chat_actions::record(&state, action).await;
     }
-
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -431,6 +539,10 @@ pub async fn mute_member(
     .execute(&state.pool)
     .await
     .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::MemberMuted, user.0)
+        .target(req.user_id)
+        .payload(serde_json::json!({ "muted_until": until }));
+    chat_actions::record(&state, action).await;
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -448,12 +560,17 @@ pub async fn unmute_member(
     if meta.owner_id != Some(user.0) && !has_perm(perms.as_ref(), |p| p.can_manage_members) {
         return Ok(HttpResponse::Forbidden().finish());
     }
-    sqlx::query("DELETE FROM chat_mutes WHERE chat_id = $1 AND user_id = $2")
+    let unmuted = sqlx::query("DELETE FROM chat_mutes WHERE chat_id = $1 AND user_id = $2")
         .bind(chat_id)
         .bind(req.user_id)
         .execute(&state.pool)
         .await
-        .map_err(internal_err)?;
+        .map_err(internal_err)?
+        .rows_affected();
+    if unmuted > 0 {
+        let action = ChatAction::new(chat_id, ChatActionType::MemberUnmuted, user.0).target(req.user_id);
+        chat_actions::record(&state, action).await;
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -473,12 +590,13 @@ pub async fn leave_chat(
         return Ok(HttpResponse::Conflict().body("transfer ownership before leaving"));
     }
     let mut tx = state.pool.begin().await.map_err(internal_err)?;
-    sqlx::query("DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2")
+    let left = sqlx::query("DELETE FROM chat_participants WHERE chat_id = $1 AND user_id = $2")
         .bind(chat_id)
         .bind(user.0)
         .execute(&mut *tx)
         .await
-        .map_err(internal_err)?;
+        .map_err(internal_err)?
+        .rows_affected();
     sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
         .bind(chat_id)
         .bind(user.0)
@@ -486,6 +604,10 @@ pub async fn leave_chat(
         .await
         .map_err(internal_err)?;
     tx.commit().await.map_err(internal_err)?;
+    if left > 0 {
+        let action = ChatAction::new(chat_id, ChatActionType::UserLeft, user.0).target(user.0);
+        chat_actions::record(&state, action).await;
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -512,6 +634,9 @@ pub async fn clear_messages(
         .execute(&state.pool)
         .await
         .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::HistoryCleared, user.0)
+        .payload(serde_json::json!({ "deleted": res.rows_affected() }));
+    chat_actions::record(&state, action).await;
     Ok(HttpResponse::Ok().json(serde_json::json!({ "deleted": res.rows_affected() })))
 }
 
@@ -522,6 +647,8 @@ struct MessageRecord {
     sender_id: Uuid,
     sender_username: String,
     content: String,
+    #[sqlx(json)]
+    entities: Vec<MessageEntity>,
     created_at: DateTime<Utc>,
     edited_at: Option<DateTime<Utc>>,
     reply_to_message_id: Option<Uuid>,
@@ -533,8 +660,25 @@ struct MessageRecord {
     gif_provider: Option<String>,
     forward_from_chat_id: Option<Uuid>,
     forward_from_sender_id: Option<Uuid>,
+    service_action: Option<serde_json::Value>,
+    thread_root_id: Option<Uuid>,
+    ttl_seconds: Option<i32>,
+    ttl_starts_on_read: bool,
+    expires_at: Option<DateTime<Utc>>,
+    poll_id: Option<Uuid>,
+    revision: i32,
+    link_preview_url: Option<String>,
+}
+
+impl MessageRecord {
+    fn cursor(&self) -> Cursor {
+        Cursor::new(self.created_at, self.id)
+    }
 }
 
+/// Selects a [`MessageRecord`] as `m`; deleted messages come back blanked.
+const MESSAGE_SELECT: &str = "SELECT m.id, m.chat_id, m.sender_id, u.username AS sender_username, CASE WHEN m.is_deleted THEN '' ELSE m.content END AS content, CASE WHEN m.is_deleted THEN '[]'::jsonb ELSE m.entities END AS entities, m.created_at, m.edited_at, m.reply_to_message_id, m.kind, m.sticker_id, m.gif_id, m.gif_url, m.gif_preview_url, m.gif_provider, m.forward_from_chat_id, m.forward_from_sender_id, m.service_action, m.thread_root_id, m.ttl_seconds, m.ttl_starts_on_read, m.expires_at, m.poll_id, m.revision, CASE WHEN m.is_deleted THEN NULL ELSE m.link_preview_url END AS link_preview_url FROM messages m JOIN users u ON u.id = m.sender_id";
+
 #[get("/v1/api/chats/{chat_id}/messages")]
 #[instrument(skip(state, user, q))]
 pub async fn list_messages(
@@ -551,50 +695,27 @@ pub async fn list_messages(
     let pinned = meta.pinned_message_id;
     let include_reads = q.include_reads.unwrap_or(false);
 
-    let limit = q.limit.unwrap_or(50).min(200) as i64;
-    let base_query = if q.before.is_some() {
-        "SELECT m.id, m.chat_id, m.sender_id, u.username AS sender_username, CASE WHEN m.is_deleted THEN '' ELSE m.content END AS content, m.created_at, m.edited_at, m.reply_to_message_id, m.kind, m.sticker_id, m.gif_id, m.gif_url, m.gif_preview_url, m.gif_provider, m.forward_from_chat_id, m.forward_from_sender_id FROM messages m JOIN users u ON u.id = m.sender_id WHERE m.chat_id = $1 AND m.created_at < $2 ORDER BY m.created_at DESC LIMIT $3"
-    } else {
-        "SELECT m.id, m.chat_id, m.sender_id, u.username AS sender_username, CASE WHEN m.is_deleted THEN '' ELSE m.content END AS content, m.created_at, m.edited_at, m.reply_to_message_id, m.kind, m.sticker_id, m.gif_id, m.gif_url, m.gif_preview_url, m.gif_provider, m.forward_from_chat_id, m.forward_from_sender_id FROM messages m JOIN users u ON u.id = m.sender_id WHERE m.chat_id = $1 ORDER BY m.created_at DESC LIMIT $2"
-    };
-
-    let rows: Vec<MessageRecord> = if let Some(before) = q.before {
-        sqlx::query_as(base_query)
-            .bind(chat_id)
-            .bind(before)
-            .bind(limit)
-            .fetch_all(&state.pool)
-            .await
-            .map_err(internal_err)?
-    } else {
-        sqlx::query_as(base_query)
-            .bind(chat_id)
-            .bind(limit)
-            .fetch_all(&state.pool)
-            .await
-            .map_err(internal_err)?
+    let anchor = Anchor::from_query(q.before.as_deref(), q.after.as_deref(), q.around)?;
+    let around = match anchor.around() {
+        Some(id) => Some(locate_message(&state.pool, chat_id, user.0, id, true).await?),
+        None => None,
     };
+    let limit = pagination::limit(q.limit, 50);
+    let pool = &state.pool;
+    let viewer = user.0;
+    let page = pagination::load(anchor, around, limit, MessageRecord::cursor, |w| {
+        let sql = format!(
+            "{} WHERE m.chat_id = $1 AND (m.expires_at IS NULL OR m.expires_at > now()) AND m.thread_root_id IS NULL AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = m.id AND h.user_id = $2) {}",
+            MESSAGE_SELECT,
+            w.sql("m.created_at", "m.id", 3)
+        );
+        async move { w.bind(sqlx::query_as(&sql).bind(chat_id).bind(viewer)).fetch_all(pool).await }
+    })
+    .await
+    .map_err(internal_err)?;
 
-    let message_ids: Vec<Uuid> = rows.iter().map(|r| r.id).collect();
-    if message_ids.is_empty() {
-        return Ok(HttpResponse::Ok().json(Vec::<MessageDto>::new()));
-    }
-
-    let reply_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.reply_to_message_id).collect();
-    let sticker_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.sticker_id).collect();
-    let forward_chat_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.forward_from_chat_id).collect();
-    let forward_sender_ids: Vec<Uuid> = rows
-        .iter()
-        .filter_map(|r| r.forward_from_sender_id)
-        .collect();
-
-    let attachments = load_attachments(&state.pool, &message_ids).await?;
-    let mentions = load_mentions(&state.pool, &message_ids).await?;
-    let replies = load_replies(&state.pool, &reply_ids).await?;
-    let stickers = load_stickers(&state.pool, &sticker_ids).await?;
-    let forwarded_chats = load_forward_chats(&state.pool, &forward_chat_ids).await?;
-    let forwarded_users = load_forward_users(&state.pool, &forward_sender_ids).await?;
-    let read_map = if include_reads {
+    let message_ids: Vec<Uuid> = page.items.iter().map(|r| r.id).collect();
+    let read_map = if include_reads && !message_ids.is_empty() {
         Some(
             load_read_receipts(
                 &state.pool,
@@ -609,6 +730,78 @@ pub async fn list_messages(
         None
     };
 
+    let out = hydrate_messages(&state.pool, page.items, pinned, read_map.as_ref(), Some(user.0))
+        .await
+        .map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "messages": out,
+        "next": page.next,
+        "prev": page.prev,
+    })))
+}
+
+/// Where a message visible to `user_id` sits in the chat's history, for `around=`.
+async fn locate_message(
+    pool: &Pool<Postgres>,
+    chat_id: Uuid,
+    user_id: Uuid,
+    message_id: Uuid,
+    top_level: bool,
+) -> actix_web::Result<Cursor> {
+    let created_at: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT m.created_at FROM messages m WHERE m.id = $1 AND m.chat_id = $2 AND (m.expires_at IS NULL OR m.expires_at > now()) AND (NOT $4 OR m.thread_root_id IS NULL) AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = m.id AND h.user_id = $3)",
+    )
+    .bind(message_id)
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(top_level)
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_err)?;
+    created_at
+        .map(|t| Cursor::new(t, message_id))
+        .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))
+}
+
+/// Turn message rows into full `MessageDto`s, batching the side-table lookups.
+async fn hydrate_messages(
+    pool: &Pool<Postgres>,
+    rows: Vec<MessageRecord>,
+    pinned: Option<Uuid>,
+    read_map: Option<&HashMap<Uuid, MessageReadReceiptDto>>,
+    viewer: Option<Uuid>,
+) -> sqlx::Result<Vec<MessageDto>> {
+    let message_ids: Vec<Uuid> = rows.iter().map(|r| r.id).collect();
+
+    let reply_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.reply_to_message_id).collect();
+    let sticker_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.sticker_id).collect();
+    let forward_chat_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.forward_from_chat_id).collect();
+    let poll_ids: Vec<Uuid> = rows.iter().filter_map(|r| r.poll_id).collect();
+    let preview_urls: Vec<String> = rows.iter().filter_map(|r| r.link_preview_url.clone()).collect();
+    let services: HashMap<Uuid, StoredServiceAction> = rows
+        .iter()
+        .filter_map(|r| {
+            let stored = serde_json::from_value(r.service_action.clone()?).ok()?;
+            Some((r.id, stored))
+        })
+        .collect();
+    let user_ids: Vec<Uuid> = rows
+        .iter()
+        .filter_map(|r| r.forward_from_sender_id)
+        .chain(services.values().filter_map(|s| s.target_id))
+        .collect();
+
+    let attachments = load_attachments(pool, &message_ids).await?;
+    let mentions = load_mentions(pool, &message_ids).await?;
+    let replies = load_replies(pool, &reply_ids).await?;
+    let stickers = load_stickers(pool, &sticker_ids).await?;
+    let forwarded_chats = load_forward_chats(pool, &forward_chat_ids).await?;
+    let users = load_users(pool, &user_ids).await?;
+    let mut reactions = load_reactions(pool, &message_ids, viewer).await?;
+    let mut threads = load_threads(pool, &message_ids, viewer).await?;
+    let polls = load_polls(pool, &poll_ids, viewer).await?;
+    let previews = load_previews(pool, &preview_urls).await?;
+
     let mut out = Vec::with_capacity(rows.len());
     for row in rows {
         let sender = SimpleUserDto {
@@ -632,18 +825,25 @@ pub async fn list_messages(
             chat: forwarded_chats.get(&cid).cloned(),
             sender: row
                 .forward_from_sender_id
-                .and_then(|sid| forwarded_users.get(&sid).cloned()),
+                .and_then(|sid| users.get(&sid).cloned()),
+        });
+        let service = services.get(&row.id).map(|s| ServiceActionDto {
+            action: s.action,
+            target: s.target_id.and_then(|tid| users.get(&tid).cloned()),
+            payload: s.payload.clone(),
         });
-        let read_receipt = read_map.as_ref().and_then(|map| map.get(&row.id).cloned());
+        let read_receipt = read_map.and_then(|map| map.get(&row.id).cloned());
 
         out.push(MessageDto {
             id: row.id,
             chat_id: row.chat_id,
             sender,
             content: row.content.clone(),
+            entities: row.entities.clone(),
             kind: row.kind.clone(),
             created_at: row.created_at,
             edited_at: row.edited_at,
+            revision: row.revision,
             reply_to: reply,
             attachments: attachments.get(&row.id).cloned().unwrap_or_default(),
             mentions: mentions.get(&row.id).cloned().unwrap_or_default(),
@@ -652,16 +852,56 @@ pub async fn list_messages(
             forwarded_from: forwarded,
             sticker,
             gif,
+            service,
+            reactions: reactions.remove(&row.id).unwrap_or_default(),
+            thread_root_id: row.thread_root_id,
+            thread: threads.remove(&row.id),
+            ttl: row.ttl_seconds.map(|ttl_seconds| MessageTtlDto {
+                ttl_seconds,
+                started_at: row.expires_at.map(|at| at - chrono::Duration::seconds(ttl_seconds.into())),
+                expires_at: row.expires_at,
+                starts_on_read: row.ttl_starts_on_read,
+            }),
+            poll: row.poll_id.and_then(|id| polls.get(&id).cloned()),
+            link_preview: row.link_preview_url.as_ref().and_then(|url| previews.get(url).cloned()),
         });
     }
 
-    Ok(HttpResponse::Ok().json(out))
+    Ok(out)
+}
+
+/// Load and hydrate messages by id, e.g. to broadcast a message that was just stored.
+pub(crate) async fn load_message_dtos(pool: &Pool<Postgres>, ids: &[Uuid]) -> sqlx::Result<Vec<MessageDto>> {
+    load_message_dtos_for(pool, ids, None).await
+}
+
+/// [`load_message_dtos`] as `viewer` sees them (`reacted_by_me`, thread unread counts).
+pub(crate) async fn load_message_dtos_for(
+    pool: &Pool<Postgres>,
+    ids: &[Uuid],
+    viewer: Option<Uuid>,
+) -> sqlx::Result<Vec<MessageDto>> {
+    let rows: Vec<MessageRecord> = sqlx::query_as(&format!(
+        "{} WHERE m.id = ANY($1) ORDER BY m.created_at ASC",
+        MESSAGE_SELECT
+    ))
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    let Some(chat_id) = rows.first().map(|r| r.chat_id) else {
+        return Ok(Vec::new());
+    };
+    let pinned: Option<Uuid> = sqlx::query_scalar("SELECT pinned_message_id FROM chats WHERE id = $1")
+        .bind(chat_id)
+        .fetch_one(pool)
+        .await?;
+    hydrate_messages(pool, rows, pinned, None, viewer).await
 }
 
 async fn load_attachments(
     pool: &Pool<Postgres>,
     ids: &[Uuid],
-) -> Result<HashMap<Uuid, Vec<MessageAttachmentDto>>, actix_web::Error> {
+) -> sqlx::Result<HashMap<Uuid, Vec<MessageAttachmentDto>>> {
     #[derive(sqlx::FromRow)]
     struct Row {
         message_id: Uuid,
@@ -673,8 +913,7 @@ async fn load_attachments(
     )
     .bind(ids)
     .fetch_all(pool)
-    .await
-    .map_err(internal_err)?;
+    .await?;
     let mut map: HashMap<Uuid, Vec<MessageAttachmentDto>> = HashMap::new();
     for row in rows {
         map.entry(row.message_id)
@@ -690,7 +929,7 @@ async fn load_attachments(
 async fn load_mentions(
     pool: &Pool<Postgres>,
     ids: &[Uuid],
-) -> Result<HashMap<Uuid, Vec<MessageMentionDto>>, actix_web::Error> {
+) -> sqlx::Result<HashMap<Uuid, Vec<MessageMentionDto>>> {
     #[derive(sqlx::FromRow)]
     struct Row {
         message_id: Uuid,
@@ -702,8 +941,7 @@ async fn load_mentions(
     )
     .bind(ids)
     .fetch_all(pool)
-    .await
-    .map_err(internal_err)?;
+    .await?;
     let mut map: HashMap<Uuid, Vec<MessageMentionDto>> = HashMap::new();
     for row in rows {
         map.entry(row.message_id)
@@ -719,7 +957,7 @@ async fn load_mentions(
 async fn load_replies(
     pool: &Pool<Postgres>,
     ids: &[Uuid],
-) -> Result<HashMap<Uuid, MessageReplyDto>, actix_web::Error> {
+) -> sqlx::Result<HashMap<Uuid, MessageReplyDto>> {
     if ids.is_empty() {
         return Ok(HashMap::new());
     }
@@ -736,8 +974,7 @@ async fn load_replies(
     )
     .bind(ids)
     .fetch_all(pool)
-    .await
-    .map_err(internal_err)?;
+    .await?;
     let mut map = HashMap::new();
     for row in rows {
         map.insert(
@@ -759,7 +996,7 @@ async fn load_replies(
 async fn load_stickers(
     pool: &Pool<Postgres>,
     ids: &[Uuid],
-) -> Result<HashMap<Uuid, StickerMessageDto>, actix_web::Error> {
+) -> sqlx::Result<HashMap<Uuid, StickerMessageDto>> {
     if ids.is_empty() {
         return Ok(HashMap::new());
     }
@@ -776,8 +1013,7 @@ async fn load_stickers(
     )
     .bind(ids)
     .fetch_all(pool)
-    .await
-    .map_err(internal_err)?;
+    .await?;
     let mut map = HashMap::new();
     for row in rows {
         map.insert(
@@ -797,7 +1033,7 @@ async fn load_stickers(
 async fn load_forward_chats(
     pool: &Pool<Postgres>,
     ids: &[Uuid],
-) -> Result<HashMap<Uuid, ForwardedChatDto>, actix_web::Error> {
+) -> sqlx::Result<HashMap<Uuid, ForwardedChatDto>> {
     if ids.is_empty() {
         return Ok(HashMap::new());
     }
@@ -809,8 +1045,7 @@ async fn load_forward_chats(
     let rows: Vec<Row> = sqlx::query_as("SELECT id, title FROM chats WHERE id = ANY($1)")
         .bind(ids)
         .fetch_all(pool)
-        .await
-        .map_err(internal_err)?;
+        .await?;
     Ok(rows
         .into_iter()
         .map(|r| {
@@ -825,10 +1060,10 @@ async fn load_forward_chats(
         .collect())
 }
 
-async fn load_forward_users(
+async fn load_users(
     pool: &Pool<Postgres>,
     ids: &[Uuid],
-) -> Result<HashMap<Uuid, SimpleUserDto>, actix_web::Error> {
+) -> sqlx::Result<HashMap<Uuid, SimpleUserDto>> {
     if ids.is_empty() {
         return Ok(HashMap::new());
     }
@@ -840,8 +1075,7 @@ async fn load_forward_users(
     let rows: Vec<Row> = sqlx::query_as("SELECT id, username FROM users WHERE id = ANY($1)")
         .bind(ids)
         .fetch_all(pool)
-        .await
-        .map_err(internal_err)?;
+        .await?;
     Ok(rows
         .into_iter()
         .map(|r| {
@@ -1027,10 +1261,10 @@ pub async fn ensure_direct_chat(pool: &Pool<Postgres>, a: Uuid, b: Uuid) -> anyh
     Ok(chat_id)
 }
 
-async fn build_chat_dto(
+pub(crate) async fn build_chat_dto(
     pool: &Pool<Postgres>,
     chat_id: Uuid,
-    user_id: Uuid,
+    _user_id: Uuid,
 ) -> Result<ChatDto, actix_web::Error> {
     #[derive(sqlx::FromRow)]
     struct ChatRow {
@@ -1042,11 +1276,11 @@ async fn build_chat_dto(
         created_at: DateTime<Utc>,
         is_public: bool,
         public_handle: Option<String>,
-        pinned_message_id: Option<Uuid>,
         description: Option<String>,
+        auto_delete_seconds: Option<i32>,
     }
     let row: ChatRow = sqlx::query_as(
-        "SELECT id, is_direct, chat_type, owner_id, title, created_at, is_public, public_handle, pinned_message_id, description FROM chats WHERE id = $1",
+        "SELECT id, is_direct, chat_type, owner_id, title, created_at, is_public, public_handle, description, auto_delete_seconds FROM chats WHERE id = $1",
     )
     .bind(chat_id)
     .fetch_one(pool)
@@ -1089,6 +1323,7 @@ async fn build_chat_dto(
         public_handle: row.public_handle,
         pinned_message,
         description: row.description,
+        auto_delete_seconds: row.auto_delete_seconds,
     })
 }
 
@@ -1108,6 +1343,9 @@ pub async fn set_visibility(
     if meta.is_direct {
         return Ok(HttpResponse::BadRequest().body("direct chats cannot be public"));
     }
+    if meta.chat_type.as_deref() == Some(SAVED_CHAT_TYPE) {
+        return Ok(HttpResponse::BadRequest().body("saved messages are private"));
+    }
     let handle = req
         .public_handle
         .as_ref()
@@ -1138,11 +1376,14 @@ pub async fn set_visibility(
     }
     sqlx::query("UPDATE chats SET is_public = $1, public_handle = $2 WHERE id = $3")
         .bind(req.is_public)
-        .bind(handle)
+        .bind(&handle)
         .bind(chat_id)
         .execute(&state.pool)
         .await
         .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::VisibilityChanged, user.0)
+        .payload(serde_json::json!({ "is_public": req.is_public, "public_handle": handle }));
+    chat_actions::record(&state, action).await;
     Ok(HttpResponse::Ok().finish())
 }
 
@@ -1203,14 +1444,15 @@ pub async fn public_join(
         // allow join but channel semantics same as group
     }
     let mut tx = state.pool.begin().await.map_err(internal_err)?;
-    sqlx::query(
+    let joined = sqlx::query(
         "INSERT INTO chat_participants (chat_id, user_id) VALUES ($1,$2) ON CONFLICT DO NOTHING",
     )
     .bind(chat.id)
     .bind(user.0)
     .execute(&mut *tx)
     .await
-    .map_err(internal_err)?;
+    .map_err(internal_err)?
+    .rows_affected();
     sqlx::query(
         "INSERT INTO chat_members (chat_id, user_id) VALUES ($1,$2) ON CONFLICT DO NOTHING",
     )
@@ -1220,83 +1462,74 @@ pub async fn public_join(
     .await
     .map_err(internal_err)?;
     tx.commit().await.map_err(internal_err)?;
+    if joined > 0 {
+        let action = ChatAction::new(chat.id, ChatActionType::UserJoined, user.0).target(user.0);
+        chat_actions::record(&state, action).await;
+    }
     Ok(HttpResponse::Ok().json(serde_json::json!({"chat_id": chat.id})))
 }
 
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    pub limit: Option<usize>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub around: Option<Uuid>,
+}
+
 #[get("/v1/api/chats/{chat_id}/messages/search")]
 pub async fn search_messages(
     state: web::Data<AppState>,
     path: web::Path<Uuid>,
     user: AuthUser,
-    q: web::Query<std::collections::HashMap<String, String>>,
+    q: web::Query<SearchQuery>,
 ) -> actix_web::Result<HttpResponse> {
     let chat_id = path.into_inner();
     if !ensure_member(&state.pool, chat_id, user.0).await? {
         return Ok(HttpResponse::Forbidden().finish());
     }
-    let query = q.get("q").map_or("", |v| v).trim();
+    let anchor = Anchor::from_query(q.before.as_deref(), q.after.as_deref(), q.around)?;
+    let query = q.q.as_deref().unwrap_or("").trim();
     if query.is_empty() {
-        return Ok(HttpResponse::Ok().json(Vec::<MessageDto>::new()));
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "messages": Vec::<MessageDto>::new(),
+            "next": null,
+            "prev": null,
+        })));
     }
-    let limit = q.get("limit").and_then(|v| v.parse().ok()).unwrap_or(50).min(200) as i64;
-    let before = q.get("before").and_then(|v| DateTime::parse_from_rfc3339(v).ok()).map(|dt| dt.with_timezone(&Utc));
-
-    let base_query = if before.is_some() {
-        "SELECT m.id, m.chat_id, m.sender_id, u.username AS sender_username, CASE WHEN m.is_deleted THEN '' ELSE m.content END AS content, m.created_at, m.edited_at, m.reply_to_message_id, m.kind, m.sticker_id, m.gif_id, m.gif_url, m.gif_preview_url, m.gif_provider, m.forward_from_chat_id, m.forward_from_sender_id FROM messages m JOIN users u ON u.id = m.sender_id WHERE m.chat_id = $1 AND m.created_at < $2 AND m.content ILIKE $3 ORDER BY m.created_at DESC LIMIT $4"
-    } else {
-        "SELECT m.id, m.chat_id, m.sender_id, u.username AS sender_username, CASE WHEN m.is_deleted THEN '' ELSE m.content END AS content, m.created_at, m.edited_at, m.reply_to_message_id, m.kind, m.sticker_id, m.gif_id, m.gif_url, m.gif_preview_url, m.gif_provider, m.forward_from_chat_id, m.forward_from_sender_id FROM messages m JOIN users u ON u.id = m.sender_id WHERE m.chat_id = $1 AND m.content ILIKE $2 ORDER BY m.created_at DESC LIMIT $3"
+    let around = match anchor.around() {
+        Some(id) => Some(locate_message(&state.pool, chat_id, user.0, id, false).await?),
+        None => None,
     };
+    let limit = pagination::limit(q.limit, 50);
+    let pattern = format!("%{}%", query);
+    let pool = &state.pool;
+    let viewer = user.0;
+    let page = pagination::load(anchor, around, limit, MessageRecord::cursor, |w| {
+        let sql = format!(
+            "{} WHERE m.chat_id = $1 AND (m.expires_at IS NULL OR m.expires_at > now()) AND m.content ILIKE $2 AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = m.id AND h.user_id = $3) {}",
+            MESSAGE_SELECT,
+            w.sql("m.created_at", "m.id", 4)
+        );
+        let pattern = pattern.clone();
+        async move {
+            w.bind(sqlx::query_as(&sql).bind(chat_id).bind(pattern).bind(viewer))
+                .fetch_all(pool)
+                .await
+        }
+    })
+    .await
+    .map_err(internal_err)?;
 
-    let rows: Vec<MessageRecord> = if let Some(before) = before {
-        sqlx::query_as(base_query)
-            .bind(chat_id)
-            .bind(before)
-            .bind(format!("%{}%", query))
-            .bind(limit)
-            .fetch_all(&state.pool)
-            .await
-            .map_err(internal_err)?
-    } else {
-        sqlx::query_as(base_query)
-            .bind(chat_id)
-            .bind(format!("%{}%", query))
-            .bind(limit)
-            .fetch_all(&state.pool)
-            .await
-            .map_err(internal_err)?
-    };
-
-    let message_ids: Vec<Uuid> = rows.iter().map(|r| r.id).collect();
-    if message_ids.is_empty() {
-        return Ok(HttpResponse::Ok().json(Vec::<MessageDto>::new()));
-    }
-
-    let attachments = load_attachments(&state.pool, &message_ids).await?;
-    let mentions = load_mentions(&state.pool, &message_ids).await?;
-    let stickers = load_stickers(&state.pool, &message_ids).await?;
-    let replies = load_replies(&state.pool, &message_ids).await?;
-
-    let mut dtos = Vec::new();
-    for row in rows {
-        let dto = MessageDto {
-            id: row.id,
-            chat_id: row.chat_id,
-            sender: SimpleUserDto { id: row.sender_id, username: row.sender_username },
-            content: row.content,
-            kind: row.kind,
-            created_at: row.created_at,
-            edited_at: row.edited_at,
-            reply_to: replies.get(&row.id).cloned(),
-            attachments: attachments.get(&row.id).cloned().unwrap_or_default(),
-            mentions: mentions.get(&row.id).cloned().unwrap_or_default(),
-            read_receipt: None, // No reads for search
-            is_pinned: false, // Assume not pinned for search
-            forwarded_from: None, // Simplified for search
-            sticker: stickers.get(&row.id).cloned(),
-            gif: None, // Simplified for search
-        };
-        dtos.push(dto);
-    }
+    let pinned = fetch_chat_meta(&state.pool, chat_id).await?.pinned_message_id;
+    let dtos = hydrate_messages(&state.pool, page.items, pinned, None, Some(user.0))
+        .await
+        .map_err(internal_err)?;
 
-    Ok(HttpResponse::Ok().json(dtos))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "messages": dtos,
+        "next": page.next,
+        "prev": page.prev,
+    })))
 }
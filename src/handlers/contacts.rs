@@ -1,5 +1,6 @@
 use crate::auth::{internal_err, AuthUser};
 use crate::models::{AddContactReq, Contact, ContactDto, User};
+use crate::presence;
 use crate::state::AppState;
 use actix_web::{delete, get, post, web, HttpResponse};
 use serde::Serialize;
@@ -53,6 +54,9 @@ pub async fn list_contacts(
             added_at: contact.added_at,
         });
     }
+    presence::mask_users(&state.pool, user.0, dtos.iter_mut().map(|dto| &mut dto.user))
+        .await
+        .map_err(internal_err)?;
 
     Ok(HttpResponse::Ok().json(dtos))
 }
@@ -0,0 +1,131 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use tracing::instrument;
+
+use crate::auth::{internal_err, AuthUser};
+use crate::delivery;
+use crate::events;
+use crate::handlers::messages::ensure_member;
+use crate::models::{DraftDto, SaveDraftReq};
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+
+const COLUMNS: &str = "chat_id, content, entities, reply_to_message_id, updated_at";
+
+#[put("/v1/api/chats/{chat_id}/draft")]
+#[instrument(skip(state, req, user))]
+pub async fn save_draft(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<SaveDraftReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    if !ensure_member(&state.pool, chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let req = req.into_inner();
+    // an empty composer is the same as no draft
+    if req.content.trim().is_empty() && req.reply_to_message_id.is_none() {
+        clear(&state, chat_id, user.0).await.map_err(internal_err)?;
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    let entities = delivery::normalize_entities(&req.content, &req.entities)?;
+    if let Some(rid) = req.reply_to_message_id {
+        let ok = sqlx::query_scalar::<_, i32>(
+            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2 AND is_deleted = FALSE",
+        )
+        .bind(rid)
+        .bind(chat_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(internal_err)?
+        .is_some();
+        if !ok {
+            return Ok(HttpResponse::BadRequest().body("invalid reply_to_message_id"));
+        }
+    }
+    let draft: DraftDto = sqlx::query_as(&format!(
+        "INSERT INTO chat_drafts (user_id, chat_id, content, entities, reply_to_message_id) VALUES ($1,$2,$3,$4,$5)
+         ON CONFLICT (user_id, chat_id) DO UPDATE
+         SET content = EXCLUDED.content, entities = EXCLUDED.entities,
+             reply_to_message_id = EXCLUDED.reply_to_message_id, updated_at = now()
+         RETURNING {COLUMNS}"
+    ))
+    .bind(user.0)
+    .bind(chat_id)
+    .bind(req.content.trim())
+    .bind(sqlx::types::Json(&entities))
+    .bind(req.reply_to_message_id)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    let event = ServerWsMsg::DraftUpdated { sequence_id: 0, chat_id, draft: Some(Box::new(draft.clone())) };
+    events::publish(&state, &[user.0], event).await.map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(draft))
+}
+
+#[get("/v1/api/chats/{chat_id}/draft")]
+#[instrument(skip(state, user))]
+pub async fn get_draft(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    if !ensure_member(&state.pool, chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let draft: Option<DraftDto> = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM chat_drafts WHERE user_id = $1 AND chat_id = $2"
+    ))
+    .bind(user.0)
+    .bind(chat_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    Ok(match draft {
+        Some(draft) => HttpResponse::Ok().json(draft),
+        None => HttpResponse::NotFound().body("no draft"),
+    })
+}
+
+#[delete("/v1/api/chats/{chat_id}/draft")]
+#[instrument(skip(state, user))]
+pub async fn delete_draft(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    if !ensure_member(&state.pool, chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    clear(&state, chat_id, user.0).await.map_err(internal_err)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Drop the user's draft in `chat_id`, if any, and tell their sessions it is gone.
+pub(crate) async fn clear(state: &AppState, chat_id: Uuid, user_id: Uuid) -> anyhow::Result<()> {
+    let removed = sqlx::query("DELETE FROM chat_drafts WHERE user_id = $1 AND chat_id = $2")
+        .bind(user_id)
+        .bind(chat_id)
+        .execute(&state.pool)
+        .await?
+        .rows_affected();
+    if removed > 0 {
+        let event = ServerWsMsg::DraftUpdated { sequence_id: 0, chat_id, draft: None };
+        events::publish(state, &[user_id], event).await?;
+    }
+    Ok(())
+}
+
+/// All of the user's drafts keyed by chat, for the chat list.
+pub(crate) async fn load_drafts(pool: &sqlx::PgPool, user_id: Uuid) -> sqlx::Result<HashMap<Uuid, DraftDto>> {
+    let drafts: Vec<DraftDto> = sqlx::query_as(&format!("SELECT {COLUMNS} FROM chat_drafts WHERE user_id = $1"))
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(drafts.into_iter().map(|d| (d.chat_id, d)).collect())
+}
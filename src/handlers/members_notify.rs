@@ -1,5 +1,6 @@
 use super::members::{NotifyReq, NotifyResp};
 use crate::auth::{internal_err, AuthUser};
+use crate::pagination::{self, Anchor, Cursor};
 use crate::state::AppState;
 use actix_web::{delete, get, post, web, HttpResponse};
 use sqlx::types::Uuid;
@@ -61,11 +62,21 @@ pub async fn set_notify(
     Ok(HttpResponse::Ok().finish())
 }
 
+#[derive(serde::Deserialize)]
+pub struct MentionsQuery {
+    pub limit: Option<usize>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// A mentioning message's id.
+    pub around: Option<Uuid>,
+}
+
 #[get("/v1/api/chats/{chat_id}/member/mentions")]
 pub async fn get_mentions(
     state: web::Data<AppState>,
     path: web::Path<Uuid>,
     user: AuthUser,
+    q: web::Query<MentionsQuery>,
 ) -> actix_web::Result<HttpResponse> {
     let chat_id = path.into_inner();
     if !ensure_member(&state, chat_id, user.0).await? {
@@ -78,15 +89,41 @@ pub async fn get_mentions(
         excerpt: String,
         created_at: chrono::DateTime<chrono::Utc>,
     }
-    let rows: Vec<Row> = sqlx::query_as(
-        "SELECT message_id, chat_id, excerpt, created_at FROM member_mentions WHERE chat_id = $1 AND user_id = $2 ORDER BY created_at DESC LIMIT 200"
-    )
-    .bind(chat_id)
-    .bind(user.0)
-    .fetch_all(&state.pool)
+    let anchor = Anchor::from_query(q.before.as_deref(), q.after.as_deref(), q.around)?;
+    let around = match anchor.around() {
+        Some(message_id) => {
+            let created_at: chrono::DateTime<chrono::Utc> = sqlx::query_scalar(
+                "SELECT created_at FROM member_mentions WHERE chat_id = $1 AND user_id = $2 AND message_id = $3",
+            )
+            .bind(chat_id)
+            .bind(user.0)
+            .bind(message_id)
+            .fetch_optional(&state.pool)
+            .await
+            .map_err(internal_err)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("mention not found"))?;
+            Some(Cursor::new(created_at, message_id))
+        }
+        None => None,
+    };
+    let pool = &state.pool;
+    let viewer = user.0;
+    let limit = pagination::limit(q.limit, pagination::MAX_LIMIT);
+    let key = |r: &Row| Cursor::new(r.created_at, r.message_id);
+    let page = pagination::load(anchor, around, limit, key, |w| {
+        let sql = format!(
+            "SELECT message_id, chat_id, excerpt, created_at FROM member_mentions WHERE chat_id = $1 AND user_id = $2 {}",
+            w.sql("created_at", "message_id", 3)
+        );
+        async move { w.bind(sqlx::query_as(&sql).bind(chat_id).bind(viewer)).fetch_all(pool).await }
+    })
     .await
     .map_err(internal_err)?;
-    Ok(HttpResponse::Ok().json(serde_json::json!({"mentions": rows})))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "mentions": page.items,
+        "next": page.next,
+        "prev": page.prev,
+    })))
 }
 
 #[delete("/v1/api/chats/{chat_id}/member/mentions")]
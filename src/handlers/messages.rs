@@ -1,19 +1,39 @@
-use actix_web::{delete, get, patch, post, web, HttpResponse};
+use actix_web::http::header;
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::types::Uuid;
 use tracing::instrument;
 
 use crate::auth::{internal_err, AuthUser};
-use crate::models::{ForwardMessagesReq, MessageRow};
+use crate::delivery::{self, ForwardSource, GifPayload, MessageBody, OutgoingMessage};
+use crate::events;
+use crate::handlers::chats::load_admin_perms;
+use crate::handlers::saved;
+use crate::link_preview;
+use crate::models::{DeleteMessagesReq, DeleteScope, ForwardMessagesReq, MessageEntity, MessageRow};
+use crate::scheduled;
 use crate::state::AppState;
 use crate::ws::ServerWsMsg;
 
+/// Most messages one `delete_bulk` call may delete.
+const MAX_BULK_DELETE: usize = 100;
+
 #[derive(Deserialize)]
 pub struct SendMessageReq {
     pub content: String,
+    #[serde(default)]
+    pub entities: Vec<MessageEntity>,
     pub attachment_ids: Option<Vec<Uuid>>,
     pub reply_to_message_id: Option<Uuid>,
+    pub thread_root_id: Option<Uuid>,
+    /// Self-destruct after this many seconds; with attachments, counted from the first read.
+    pub expires_in: Option<i32>,
+    /// Send without generating a preview for links in `content`.
+    #[serde(default)]
+    pub disable_link_preview: bool,
+    /// Send later instead of now; see `scheduled_messages`.
+    pub scheduled_at: Option<DateTime<Utc>>,
 }
 
 #[post("/v1/api/chats/{chat_id}/messages")]
@@ -25,162 +45,355 @@ pub async fn send_message(
     req: web::Json<SendMessageReq>,
 ) -> actix_web::Result<HttpResponse> {
     let chat_id = path.into_inner();
-    ensure_can_send(&state, chat_id, user.0).await?;
+    let req = req.into_inner();
+    let mut msg = OutgoingMessage::new(chat_id, user.0, MessageBody::Text { content: req.content, entities: req.entities });
+    msg.reply_to_message_id = req.reply_to_message_id;
+    msg.thread_root_id = req.thread_root_id;
+    msg.attachment_ids = req.attachment_ids.unwrap_or_default();
+    msg.expires_in = req.expires_in;
+    msg.disable_link_preview = req.disable_link_preview;
+    if let Some(at) = req.scheduled_at {
+        let scheduled = scheduled::schedule(&state, msg, at).await?;
+        return Ok(HttpResponse::Created().json(scheduled));
+    }
+    let sent = delivery::deliver(&state, msg).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({"id": sent.message.id})))
+}
 
-    if let Some(rid) = req.reply_to_message_id {
-        let ok = sqlx::query_scalar::<_, Option<i32>>(
-            "SELECT 1 FROM messages WHERE id = $1 AND chat_id = $2",
-        )
-        .bind(rid)
-        .bind(chat_id)
-        .fetch_one(&state.pool)
-        .await
-        .map_err(internal_err)?
-        .is_some();
-        if !ok {
-            return Ok(HttpResponse::BadRequest().body("invalid reply_to_message_id"));
-        }
-    }
+#[derive(Deserialize)]
+pub struct EditMessageReq {
+    pub content: String,
+    /// Entities of the new text; the old ones are dropped.
+    #[serde(default)]
+    pub entities: Vec<MessageEntity>,
+    /// Revision the edit is based on, like an `If-Match` header; a stale one gets 412.
+    pub revision: Option<i32>,
+}
+
+/// The revision an edit expects from `If-Match` (`"3"`, `W/"3"` or `3`) or the body.
+fn expected_revision(http_req: &HttpRequest, body: Option<i32>) -> Result<Option<i32>, actix_web::Error> {
+    let Some(value) = http_req.headers().get(header::IF_MATCH) else {
+        return Ok(body);
+    };
+    let value = value
+        .to_str()
+        .map_err(|_| actix_web::error::ErrorBadRequest("invalid If-Match"))?
+        .trim();
+    if value == "*" {
+        return Ok(body);
+    }
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| actix_web::error::ErrorBadRequest("invalid If-Match"))
+}
 
+fn revision_etag(revision: i32) -> (header::HeaderName, String) {
+    (header::ETAG, format!("\"{}\"", revision))
+}
+
+#[patch("/v1/api/messages/{message_id}")]
+pub async fn edit_message(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+    http_req: HttpRequest,
+    req: web::Json<EditMessageReq>,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let expected = expected_revision(&http_req, req.revision)?;
     let content = req.content.trim();
     if content.is_empty() {
         return Ok(HttpResponse::BadRequest().body("content required"));
     }
+    let entities = delivery::normalize_entities(&req.content, &req.entities)?;
 
-    let mentions = extract_mentions(content);
-    if mentions.len() > 50 {
-        return Ok(HttpResponse::UnprocessableEntity().body("too many mentions"));
+    #[derive(sqlx::FromRow)]
+    struct Current {
+        chat_id: Uuid,
+        sender_id: Uuid,
+        kind: String,
+        content: String,
+        #[sqlx(json)]
+        entities: Vec<MessageEntity>,
+        is_deleted: bool,
+        created_at: DateTime<Utc>,
+        edited_at: Option<DateTime<Utc>>,
+        revision: i32,
+        link_preview_url: Option<String>,
+        link_preview_disabled: bool,
+    }
+    let mut tx = state.pool.begin().await.map_err(internal_err)?;
+    let current = sqlx::query_as::<_, Current>(
+        "SELECT chat_id, sender_id, kind, content, entities, is_deleted, created_at, edited_at, revision, link_preview_url, link_preview_disabled FROM messages WHERE id = $1 FOR UPDATE",
+    )
+    .bind(message_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(internal_err)?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))?;
+    if current.kind == "service" {
+        return Ok(HttpResponse::BadRequest().body("service messages cannot be edited"));
+    }
+    if current.kind == "poll" {
+        return Ok(HttpResponse::BadRequest().body("polls cannot be edited"));
+    }
+    if current.sender_id != user.0 || current.is_deleted {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let window = state.config.messages.edit_window_secs;
+    if window > 0 && Utc::now() - current.created_at > chrono::Duration::seconds(window as i64) {
+        return Ok(HttpResponse::Forbidden().body("edit window has passed"));
+    }
+    if expected.is_some_and(|r| r != current.revision) {
+        return Ok(HttpResponse::PreconditionFailed()
+            .insert_header(revision_etag(current.revision))
+            .json(serde_json::json!({"revision": current.revision})));
     }
+    if content == current.content && entities == current.entities {
+        // nothing changed; do not record an empty revision
+        return Ok(HttpResponse::Ok()
+            .insert_header(revision_etag(current.revision))
+            .json(serde_json::json!({"id": message_id, "revision": current.revision, "edited_at": current.edited_at})));
+    }
+    let mentioned = delivery::mentioned_users(&state, current.chat_id, user.0, content, &entities).await?;
+    let link_preview_url = if current.link_preview_disabled || !state.config.link_preview.enabled {
+        current.link_preview_url.clone()
+    } else {
+        link_preview::preview_url(content, &entities)
+    };
 
-    let id = Uuid::new_v4();
-    sqlx::query("INSERT INTO messages (id, chat_id, sender_id, content, reply_to_message_id, kind) VALUES ($1,$2,$3,$4,$5,'text')")
-        .bind(id)
-        .bind(chat_id)
-        .bind(user.0)
-        .bind(content)
-        .bind(req.reply_to_message_id)
-        .execute(&state.pool)
+    sqlx::query("INSERT INTO message_revisions (message_id, revision, content, entities, created_at) VALUES ($1,$2,$3,$4,$5)")
+        .bind(message_id)
+        .bind(current.revision)
+        .bind(&current.content)
+        .bind(sqlx::types::Json(&current.entities))
+        .bind(current.edited_at.unwrap_or(current.created_at))
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_err)?;
+    let updated = sqlx::query_as::<_, MessageRow>(
+        "UPDATE messages SET content = $1, entities = $2, link_preview_url = $3, edited_at = now(), revision = revision + 1 WHERE id = $4 RETURNING id, chat_id, sender_id, content, entities, created_at, edited_at, revision",
+    )
+    .bind(content)
+    .bind(sqlx::types::Json(&entities))
+    .bind(&link_preview_url)
+    .bind(message_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(internal_err)?;
+
+    // mentions follow the new text: dropped ones go away, new ones notify
+    sqlx::query("DELETE FROM member_mentions WHERE message_id = $1 AND NOT (user_id = ANY($2))")
+        .bind(message_id)
+        .bind(&mentioned)
+        .execute(&mut *tx)
         .await
         .map_err(internal_err)?;
+    let excerpt: String = content.chars().take(120).collect();
+    for uid in &mentioned {
+        sqlx::query("INSERT INTO member_mentions (chat_id, user_id, message_id, excerpt) VALUES ($1,$2,$3,$4) ON CONFLICT (chat_id, user_id, message_id) DO UPDATE SET excerpt = EXCLUDED.excerpt")
+            .bind(current.chat_id)
+            .bind(uid)
+            .bind(message_id)
+            .bind(&excerpt)
+            .execute(&mut *tx)
+            .await
+            .map_err(internal_err)?;
+    }
+    tx.commit().await.map_err(internal_err)?;
 
-    if let Some(att) = &req.attachment_ids {
-        for fid in att {
-            let exists =
-                sqlx::query_scalar::<_, Option<i32>>("SELECT 1 FROM storage_files WHERE id = $1")
-                    .bind(fid)
-                    .fetch_one(&state.pool)
-                    .await
-                    .map_err(internal_err)?
-                    .is_some();
-            if !exists {
-                return Ok(HttpResponse::BadRequest().body("invalid attachment id"));
-            }
-            sqlx::query("INSERT INTO message_attachments (message_id, file_id) VALUES ($1,$2) ON CONFLICT DO NOTHING")
-                .bind(id)
-                .bind(fid)
-                .execute(&state.pool)
+    let body = serde_json::json!({"id": message_id, "revision": updated.revision, "edited_at": updated.edited_at});
+    let etag = revision_etag(updated.revision);
+    let chat_id = updated.chat_id;
+    let msg = ServerWsMsg::MessageEdited { sequence_id: 0, message: updated };
+    events::publish_to_chat(&state, chat_id, msg)
+        .await
+        .map_err(internal_err)?;
+    if link_preview_url != current.link_preview_url {
+        match link_preview_url {
+            Some(url) => link_preview::spawn_attach(state.get_ref().clone(), chat_id, message_id, url, false),
+            None => link_preview::announce_removed(&state, chat_id, message_id)
                 .await
-                .map_err(internal_err)?;
+                .map_err(internal_err)?,
         }
     }
 
-    handle_mentions(&state, chat_id, id, &mentions, content, user.0).await?;
-
-    Ok(HttpResponse::Ok().json(serde_json::json!({"id": id})))
-}
-
-#[derive(Deserialize)]
-pub struct EditMessageReq {
-    pub content: String,
+    Ok(HttpResponse::Ok().insert_header(etag).json(body))
 }
 
-#[patch("/v1/api/messages/{message_id}")]
-pub async fn edit_message(
+#[get("/v1/api/messages/{message_id}/revisions")]
+pub async fn list_message_revisions(
     state: web::Data<AppState>,
     path: web::Path<Uuid>,
     user: AuthUser,
-    req: web::Json<EditMessageReq>,
 ) -> actix_web::Result<HttpResponse> {
     let message_id = path.into_inner();
-    let res = sqlx::query("UPDATE messages SET content = $1, edited_at = now() WHERE id = $2 AND sender_id = $3 AND is_deleted = FALSE")
-        .bind(req.content.trim())
+    #[derive(sqlx::FromRow)]
+    struct MsgMeta {
+        chat_id: Uuid,
+        sender_id: Uuid,
+        revision: i32,
+    }
+    let meta = sqlx::query_as::<_, MsgMeta>("SELECT chat_id, sender_id, revision FROM messages WHERE id = $1")
         .bind(message_id)
-        .bind(user.0)
-        .execute(&state.pool)
+        .fetch_optional(&state.pool)
         .await
-        .map_err(internal_err)?;
-    if res.rows_affected() == 0 {
-        return Ok(HttpResponse::Forbidden().finish());
+        .map_err(internal_err)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))?;
+    if meta.sender_id != user.0 {
+        let chat_meta = fetch_basic_chat_meta(&state.pool, meta.chat_id).await?;
+        let is_admin = load_admin_perms(&state.pool, meta.chat_id, user.0).await?.is_some();
+        if chat_meta.owner_id != Some(user.0) && !is_admin {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
     }
 
-    // Broadcast message_edited
-    if let Some(updated) = sqlx::query_as::<_, MessageRow>(
-        "SELECT id, chat_id, sender_id, content, created_at, edited_at FROM messages WHERE id = $1",
+    #[derive(sqlx::FromRow, serde::Serialize)]
+    struct RevisionRow {
+        revision: i32,
+        content: String,
+        #[sqlx(json)]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        entities: Vec<MessageEntity>,
+        created_at: DateTime<Utc>,
+        replaced_at: DateTime<Utc>,
+    }
+    let revisions: Vec<RevisionRow> = sqlx::query_as(
+        "SELECT revision, content, entities, created_at, replaced_at FROM message_revisions WHERE message_id = $1 ORDER BY revision ASC",
     )
     .bind(message_id)
-    .fetch_optional(&state.pool)
+    .fetch_all(&state.pool)
     .await
-    .map_err(internal_err)?
-    {
-        let chat_id = updated.chat_id;
-        let participants = sqlx::query_scalar::<_, Uuid>(
-            "SELECT user_id FROM chat_participants WHERE chat_id = $1",
-        )
-        .bind(chat_id)
-        .fetch_all(&state.pool)
-        .await
-        .map_err(internal_err)?;
-        let msg = ServerWsMsg::MessageEdited { sequence_id: state.sequence_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst), message: updated };
-        for uid in participants {
-            if let Some(tx) = state.clients.get(&uid) {
-                let _ = tx.send(msg.clone());
-            }
-        }
-    }
+    .map_err(internal_err)?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok()
+        .insert_header(revision_etag(meta.revision))
+        .json(serde_json::json!({
+            "message_id": message_id,
+            "revision": meta.revision,
+            "revisions": revisions,
+        })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub scope: DeleteScope,
 }
 
 #[delete("/v1/api/messages/{message_id}")]
+#[instrument(skip(state, user))]
 pub async fn delete_message(
     state: web::Data<AppState>,
     path: web::Path<Uuid>,
+    q: web::Query<DeleteQuery>,
     user: AuthUser,
 ) -> actix_web::Result<HttpResponse> {
     let message_id = path.into_inner();
-    let res = sqlx::query("UPDATE messages SET is_deleted = TRUE, deleted_at = now() WHERE id = $1 AND sender_id = $2")
-        .bind(message_id)
-        .bind(user.0)
-        .execute(&state.pool)
-        .await
-        .map_err(internal_err)?;
-    if res.rows_affected() == 0 {
-        return Ok(HttpResponse::Forbidden().finish());
-    }
-
-    // Broadcast message_deleted
-    if let Some(chat_id) = sqlx::query_scalar::<_, Uuid>("SELECT chat_id FROM messages WHERE id = $1")
+    let Some(chat_id) = sqlx::query_scalar::<_, Uuid>("SELECT chat_id FROM messages WHERE id = $1")
         .bind(message_id)
         .fetch_optional(&state.pool)
         .await
         .map_err(internal_err)?
-    {
-        let participants = sqlx::query_scalar::<_, Uuid>(
-            "SELECT user_id FROM chat_participants WHERE chat_id = $1",
-        )
+    else {
+        return Ok(HttpResponse::NotFound().body("message not found"));
+    };
+    match delete_in_chat(&state, user.0, chat_id, &[message_id], q.scope).await? {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(resp) => Ok(resp),
+    }
+}
+
+#[post("/v1/api/messages/delete_bulk")]
+#[instrument(skip(state, req, user))]
+pub async fn delete_bulk(
+    state: web::Data<AppState>,
+    req: web::Json<DeleteMessagesReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    if req.message_ids.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("message_ids required"));
+    }
+    if req.message_ids.len() > MAX_BULK_DELETE {
+        return Ok(HttpResponse::BadRequest().body("too many message_ids"));
+    }
+    match delete_in_chat(&state, user.0, req.chat_id, &req.message_ids, req.scope).await? {
+        Ok(deleted) => Ok(HttpResponse::Ok().json(serde_json::json!({"message_ids": deleted}))),
+        Err(resp) => Ok(resp),
+    }
+}
+
+/// Delete `ids`, all of which must be in `chat_id`, and announce it with one `message_deleted`:
+/// to the whole chat for [`DeleteScope::Everyone`], to the caller's sessions for
+/// [`DeleteScope::Me`]. Returns the ids that were not already deleted (or hidden).
+async fn delete_in_chat(
+    state: &AppState,
+    user_id: Uuid,
+    chat_id: Uuid,
+    ids: &[Uuid],
+    scope: DeleteScope,
+) -> actix_web::Result<Result<Vec<Uuid>, HttpResponse>> {
+    #[derive(sqlx::FromRow)]
+    struct Target {
+        sender_id: Uuid,
+    }
+    let targets: Vec<Target> = sqlx::query_as("SELECT sender_id FROM messages WHERE chat_id = $1 AND id = ANY($2)")
         .bind(chat_id)
+        .bind(ids)
         .fetch_all(&state.pool)
         .await
         .map_err(internal_err)?;
-        let msg = ServerWsMsg::MessageDeleted { sequence_id: state.sequence_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst), chat_id, message_ids: vec![message_id] };
-        for uid in participants {
-            if let Some(tx) = state.clients.get(&uid) {
-                let _ = tx.send(msg.clone());
+    let unique: std::collections::HashSet<&Uuid> = ids.iter().collect();
+    if targets.len() != unique.len() {
+        return Ok(Err(HttpResponse::NotFound().body("message not found")));
+    }
+
+    let deleted: Vec<Uuid> = match scope {
+        DeleteScope::Me => {
+            if !ensure_member(&state.pool, chat_id, user_id).await? {
+                return Ok(Err(HttpResponse::Forbidden().finish()));
             }
+            sqlx::query_scalar(
+                "INSERT INTO hidden_messages (user_id, message_id) SELECT $1, unnest($2::uuid[]) ON CONFLICT DO NOTHING RETURNING message_id",
+            )
+            .bind(user_id)
+            .bind(ids)
+            .fetch_all(&state.pool)
+            .await
+            .map_err(internal_err)?
         }
-    }
+        DeleteScope::Everyone => {
+            // the sender may always take back their own messages; others' need the owner or an admin
+            if targets.iter().any(|t| t.sender_id != user_id) {
+                let chat = fetch_basic_chat_meta(&state.pool, chat_id).await?;
+                let can_moderate = !chat.is_direct
+                    && (chat.owner_id == Some(user_id) || admin_can_delete(&state.pool, chat_id, user_id).await?);
+                if !can_moderate {
+                    return Ok(Err(HttpResponse::Forbidden().finish()));
+                }
+            }
+            sqlx::query_scalar(
+                "UPDATE messages SET is_deleted = TRUE, deleted_at = now() WHERE id = ANY($1) AND is_deleted = FALSE RETURNING id",
+            )
+            .bind(ids)
+            .fetch_all(&state.pool)
+            .await
+            .map_err(internal_err)?
+        }
+    };
 
-    Ok(HttpResponse::Ok().finish())
+    if !deleted.is_empty() {
+        let msg = ServerWsMsg::MessageDeleted { sequence_id: 0, chat_id, message_ids: deleted.clone() };
+        match scope {
+            DeleteScope::Me => events::publish(state, &[user_id], msg).await,
+            DeleteScope::Everyone => events::publish_to_chat(state, chat_id, msg).await,
+        }
+        .map_err(internal_err)?;
+    }
+    Ok(Ok(deleted))
 }
 
 #[derive(Deserialize)]
@@ -252,7 +465,7 @@ pub async fn read_bulk(
 
     if !req.message_ids.is_empty() {
         let newest: Option<(Uuid, DateTime<Utc>)> = sqlx::query_as(
-            "SELECT id, created_at FROM messages WHERE chat_id = $1 AND id = ANY($2) ORDER BY created_at DESC LIMIT 1",
+            "SELECT id, created_at FROM messages WHERE chat_id = $1 AND id = ANY($2) AND thread_root_id IS NULL ORDER BY created_at DESC LIMIT 1",
         )
         .bind(chat_id)
         .bind(&req.message_ids)
@@ -260,14 +473,16 @@ pub async fn read_bulk(
         .await
         .map_err(internal_err)?;
         if let Some((new_id, new_ts)) = newest {
-            let current: Option<DateTime<Utc>> = sqlx::query_scalar(
+            // no member row, or a read position that was never set or whose message is gone
+            let current: Option<DateTime<Utc>> = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
                 "SELECT m.created_at FROM chat_members cm LEFT JOIN messages m ON m.id = cm.last_read_message_id WHERE cm.chat_id = $1 AND cm.user_id = $2",
             )
             .bind(chat_id)
             .bind(user.0)
             .fetch_optional(&state.pool)
             .await
-            .map_err(internal_err)?;
+            .map_err(internal_err)?
+            .flatten();
             if current.map(|t| new_ts > t).unwrap_or(true) {
                 sqlx::query("INSERT INTO chat_members (chat_id, user_id, last_read_message_id) VALUES ($1,$2,$3) ON CONFLICT (chat_id,user_id) DO UPDATE SET last_read_message_id = EXCLUDED.last_read_message_id")
                     .bind(chat_id)
@@ -278,6 +493,36 @@ pub async fn read_bulk(
                     .map_err(internal_err)?;
             }
         }
+
+        // thread replies move the per-thread read position instead of the chat's
+        sqlx::query(
+            "INSERT INTO thread_reads (root_message_id, user_id, last_read_message_id)
+             SELECT DISTINCT ON (m.thread_root_id) m.thread_root_id, $3, m.id FROM messages m
+             WHERE m.chat_id = $1 AND m.id = ANY($2) AND m.thread_root_id IS NOT NULL
+             ORDER BY m.thread_root_id, m.created_at DESC
+             ON CONFLICT (root_message_id, user_id) DO UPDATE SET last_read_message_id = EXCLUDED.last_read_message_id
+             WHERE thread_reads.last_read_message_id IS NULL
+                OR (SELECT created_at FROM messages WHERE id = thread_reads.last_read_message_id)
+                 < (SELECT created_at FROM messages WHERE id = EXCLUDED.last_read_message_id)",
+        )
+        .bind(chat_id)
+        .bind(&req.message_ids)
+        .bind(user.0)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+
+        // one-time media starts its countdown when someone other than the sender first reads it
+        sqlx::query(
+            "UPDATE messages SET expires_at = now() + make_interval(secs => ttl_seconds)
+             WHERE chat_id = $1 AND id = ANY($2) AND ttl_starts_on_read AND expires_at IS NULL AND sender_id <> $3",
+        )
+        .bind(chat_id)
+        .bind(&req.message_ids)
+        .bind(user.0)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
     }
 
     Ok(HttpResponse::Ok().finish())
@@ -321,18 +566,20 @@ pub async fn unread_count(
 
     let unread: i64 = if let Some(t) = lr_time {
         sqlx::query_scalar(
-            "SELECT COUNT(*) FROM messages msg WHERE msg.chat_id = $1 AND msg.is_deleted = FALSE AND msg.created_at > $2",
+            "SELECT COUNT(*) FROM messages msg WHERE msg.chat_id = $1 AND msg.is_deleted = FALSE AND msg.kind <> 'service' AND msg.thread_root_id IS NULL AND msg.created_at > $2 AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = msg.id AND h.user_id = $3)",
         )
         .bind(chat_id)
         .bind(t)
+        .bind(user.0)
         .fetch_one(&state.pool)
         .await
         .map_err(internal_err)?
     } else {
         sqlx::query_scalar(
-            "SELECT COUNT(*) FROM messages msg WHERE msg.chat_id = $1 AND msg.is_deleted = FALSE",
+            "SELECT COUNT(*) FROM messages msg WHERE msg.chat_id = $1 AND msg.is_deleted = FALSE AND msg.kind <> 'service' AND msg.thread_root_id IS NULL AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = msg.id AND h.user_id = $2)",
         )
         .bind(chat_id)
+        .bind(user.0)
         .fetch_one(&state.pool)
         .await
         .map_err(internal_err)?
@@ -353,15 +600,44 @@ pub async fn forward_messages(
     if req.message_ids.is_empty() {
         return Ok(HttpResponse::BadRequest().body("message_ids required"));
     }
-    ensure_can_send(&state, target_chat_id, user.0).await?;
+    delivery::ensure_can_send(&state, target_chat_id, user.0).await?;
     if !ensure_member(&state.pool, req.from_chat_id, user.0).await? {
         return Ok(HttpResponse::Forbidden().finish());
     }
+    let forwarded = forward_into(&state, target_chat_id, user.0, req.from_chat_id, &req.message_ids).await?;
+    if forwarded.is_empty() {
+        return Ok(HttpResponse::BadRequest().body("messages not found"));
+    }
+    // forwarding into one's own saved chat is how copies get saved
+    if saved::is_saved_chat_of(&state.pool, target_chat_id, user.0).await.map_err(internal_err)? {
+        saved::record_copies(&state.pool, user.0, req.from_chat_id, &forwarded, None, None).await.map_err(internal_err)?;
+    }
+    let created_ids: Vec<Uuid> = forwarded.iter().map(|f| f.message_id).collect();
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message_ids": created_ids})))
+}
+
+/// A forwarded copy and the message it was made from.
+pub(crate) struct Forwarded {
+    pub message_id: Uuid,
+    pub source_id: Uuid,
+    pub source_sender_id: Uuid,
+}
 
+/// Copy `message_ids` of `from_chat_id` into `target_chat_id` as `sender_id`. Ids that are
+/// not in the source chat, and service messages, are skipped.
+pub(crate) async fn forward_into(
+    state: &AppState,
+    target_chat_id: Uuid,
+    sender_id: Uuid,
+    from_chat_id: Uuid,
+    message_ids: &[Uuid],
+) -> actix_web::Result<Vec<Forwarded>> {
     #[derive(sqlx::FromRow)]
     struct SourceMsg {
         id: Uuid,
         content: String,
+        #[sqlx(json)]
+        entities: Vec<MessageEntity>,
         sender_id: Uuid,
         kind: String,
         sticker_id: Option<Uuid>,
@@ -369,52 +645,56 @@ pub async fn forward_messages(
         gif_url: Option<String>,
         gif_preview_url: Option<String>,
         gif_provider: Option<String>,
+        poll_id: Option<Uuid>,
+        link_preview_url: Option<String>,
     }
     let sources: Vec<SourceMsg> = sqlx::query_as(
-        "SELECT id, CASE WHEN is_deleted THEN '' ELSE content END as content, sender_id, kind, sticker_id, gif_id, gif_url, gif_preview_url, gif_provider FROM messages WHERE chat_id = $1 AND id = ANY($2)",
+        "SELECT id, CASE WHEN is_deleted THEN '' ELSE content END as content, CASE WHEN is_deleted THEN '[]'::jsonb ELSE entities END as entities, sender_id, kind, sticker_id, gif_id, gif_url, gif_preview_url, gif_provider, poll_id, CASE WHEN is_deleted THEN NULL ELSE link_preview_url END AS link_preview_url FROM messages WHERE chat_id = $1 AND id = ANY($2) AND kind <> 'service'",
     )
-    .bind(req.from_chat_id)
-    .bind(&req.message_ids)
+    .bind(from_chat_id)
+    .bind(message_ids)
     .fetch_all(&state.pool)
     .await
     .map_err(internal_err)?;
-    if sources.is_empty() {
-        return Ok(HttpResponse::BadRequest().body("messages not found"));
-    }
 
-    let mut created_ids = Vec::new();
+    let mut forwarded = Vec::with_capacity(sources.len());
     for src in sources {
-        let new_id = Uuid::new_v4();
-        sqlx::query(
-            "INSERT INTO messages (id, chat_id, sender_id, content, kind, sticker_id, gif_id, gif_url, gif_preview_url, gif_provider, forward_from_message_id, forward_from_chat_id, forward_from_sender_id) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13)",
-        )
-        .bind(new_id)
-        .bind(target_chat_id)
-        .bind(user.0)
-        .bind(&src.content)
-        .bind(&src.kind)
-        .bind(src.sticker_id)
-        .bind(src.gif_id)
-        .bind(src.gif_url)
-        .bind(src.gif_preview_url)
-        .bind(src.gif_provider)
-        .bind(src.id)
-        .bind(req.from_chat_id)
-        .bind(src.sender_id)
-        .execute(&state.pool)
-        .await
-        .map_err(internal_err)?;
-
-        sqlx::query("INSERT INTO message_attachments (message_id, file_id) SELECT $1, file_id FROM message_attachments WHERE message_id = $2")
-            .bind(new_id)
-            .bind(src.id)
-            .execute(&state.pool)
-            .await
-            .map_err(internal_err)?;
-        created_ids.push(new_id);
-    }
-
-    Ok(HttpResponse::Ok().json(serde_json::json!({"message_ids": created_ids})))
+        let attachment_ids: Vec<Uuid> =
+            sqlx::query_scalar("SELECT file_id FROM message_attachments WHERE message_id = $1")
+                .bind(src.id)
+                .fetch_all(&state.pool)
+                .await
+                .map_err(internal_err)?;
+        let gif = match (src.gif_id, src.gif_url) {
+            (Some(id), Some(url)) => Some(GifPayload {
+                id,
+                url,
+                preview_url: src.gif_preview_url.unwrap_or_default(),
+                provider: src.gif_provider.unwrap_or_default(),
+            }),
+            _ => None,
+        };
+        let mut msg = OutgoingMessage::new(
+            target_chat_id,
+            sender_id,
+            MessageBody::Forward(ForwardSource {
+                chat_id: from_chat_id,
+                message_id: src.id,
+                sender_id: src.sender_id,
+                content: src.content,
+                entities: src.entities,
+                kind: src.kind,
+                sticker_id: src.sticker_id,
+                gif,
+                poll_id: src.poll_id,
+                link_preview_url: src.link_preview_url,
+            }),
+        );
+        msg.attachment_ids = attachment_ids;
+        let sent = delivery::deliver(state, msg).await?;
+        forwarded.push(Forwarded { message_id: sent.message.id, source_id: src.id, source_sender_id: src.sender_id });
+    }
+    Ok(forwarded)
 }
 
 #[derive(Deserialize)]
@@ -483,134 +763,6 @@ pub async fn list_message_reads(
     })))
 }
 
-pub(crate) async fn ensure_can_send(
-    state: &web::Data<AppState>,
-    chat_id: Uuid,
-    user_id: Uuid,
-) -> Result<ChatSendMeta, actix_web::Error> {
-    if !ensure_member(&state.pool, chat_id, user_id).await? {
-        return Err(actix_web::error::ErrorForbidden("not a member"));
-    }
-    let meta = fetch_send_meta(&state.pool, chat_id).await?;
-    if meta.chat_type.as_deref() == Some("channel") && meta.owner_id != Some(user_id) {
-        return Err(actix_web::error::ErrorForbidden(
-            "only owner can send in channel",
-        ));
-    }
-    #[derive(sqlx::FromRow)]
-    struct MuteRow {
-        muted_until: Option<DateTime<Utc>>,
-    }
-    if let Some(row) = sqlx::query_as::<_, MuteRow>(
-        "SELECT muted_until FROM chat_mutes WHERE chat_id = $1 AND user_id = $2",
-    )
-    .bind(chat_id)
-    .bind(user_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(internal_err)?
-    {
-        if let Some(until) = row.muted_until {
-            if until > chrono::Utc::now() {
-                return Err(actix_web::error::ErrorForbidden("muted"));
-            }
-        }
-    }
-    Ok(meta)
-}
-
-fn extract_mentions(content: &str) -> Vec<String> {
-    let mut mentions = Vec::new();
-    let mut chars = content.chars().enumerate().peekable();
-    while let Some((idx, c)) = chars.next() {
-        if c == '@' {
-            let mut name = String::new();
-            let mut j = idx + 1;
-            while let Some((pos, ch)) = chars.peek() {
-                if *pos != j {
-                    break;
-                }
-                if ch.is_alphanumeric() || *ch == '_' {
-                    name.push(*ch);
-                    chars.next();
-                    j += 1;
-                } else {
-                    break;
-                }
-            }
-            if !name.is_empty() {
-                mentions.push(name.to_lowercase());
-            }
-        }
-    }
-    mentions
-}
-
-async fn handle_mentions(
-    state: &web::Data<AppState>,
-    chat_id: Uuid,
-    message_id: Uuid,
-    tokens: &[String],
-    content: &str,
-    sender_id: Uuid,
-) -> Result<(), actix_web::Error> {
-    if tokens.is_empty() {
-        return Ok(());
-    }
-    #[derive(sqlx::FromRow)]
-    struct Participant {
-        user_id: Uuid,
-        username: String,
-    }
-    let participants: Vec<Participant> = sqlx::query_as(
-        "SELECT u.id as user_id, u.username FROM chat_participants cp JOIN users u ON u.id = cp.user_id WHERE cp.chat_id = $1",
-    )
-    .bind(chat_id)
-    .fetch_all(&state.pool)
-    .await
-    .map_err(internal_err)?;
-    let map: HashMap<String, Uuid> = participants
-        .into_iter()
-        .filter(|p| p.user_id != sender_id)
-        .map(|p| (p.username.to_lowercase(), p.user_id))
-        .collect();
-    let excerpt: String = content.chars().take(120).collect();
-    let mut inserted = HashSet::new();
-    for name in tokens {
-        if let Some(&uid) = map.get(name) {
-            if inserted.insert(uid) {
-                sqlx::query("INSERT INTO member_mentions (chat_id, user_id, message_id, excerpt) VALUES ($1,$2,$3,$4) ON CONFLICT DO NOTHING")
-                    .bind(chat_id)
-                    .bind(uid)
-                    .bind(message_id)
-                    .bind(&excerpt)
-                    .execute(&state.pool)
-                    .await
-                    .map_err(internal_err)?;
-            }
-        }
-    }
-    Ok(())
-}
-
-#[derive(sqlx::FromRow, Clone)]
-pub(crate) struct ChatSendMeta {
-    chat_type: Option<String>,
-    owner_id: Option<Uuid>,
-}
-
-async fn fetch_send_meta(
-    pool: &sqlx::PgPool,
-    chat_id: Uuid,
-) -> Result<ChatSendMeta, actix_web::Error> {
-    sqlx::query_as::<_, ChatSendMeta>("SELECT chat_type, owner_id FROM chats WHERE id = $1")
-        .bind(chat_id)
-        .fetch_optional(pool)
-        .await
-        .map_err(internal_err)?
-        .ok_or_else(|| actix_web::error::ErrorNotFound("chat not found"))
-}
-
 pub(crate) async fn ensure_member(
     pool: &sqlx::PgPool,
     chat_id: Uuid,
@@ -621,15 +773,14 @@ pub(crate) async fn ensure_member(
     )
     .bind(chat_id)
     .bind(user_id)
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await
     .map_err(internal_err)?
+    .flatten()
     .is_some();
     Ok(is_member)
 }
 
-use std::collections::{HashMap, HashSet};
-
 #[derive(sqlx::FromRow)]
 struct BasicChatMeta {
     owner_id: Option<Uuid>,
@@ -5,13 +5,19 @@ pub mod auth;
 pub mod chat_list;
 pub mod chats;
 pub mod contacts;
+pub mod drafts;
 pub mod files;
 pub mod gifs;
 pub mod members;
 pub mod members_notify;
 pub mod messages;
 pub mod pin;
+pub mod polls;
+pub mod reactions;
+pub mod saved;
+pub mod scheduled;
 pub mod stickers;
+pub mod threads;
 pub mod uploads;
 pub mod users;
 
@@ -37,26 +43,55 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         .service(chats::leave_chat)
         .service(chats::clear_messages)
         .service(chats::set_visibility)
+        .service(chats::set_title)
+        .service(chats::set_auto_delete)
+        .service(chats::transfer_ownership)
         .service(chats::public_search)
         .service(chats::public_join)
         .service(chats::list_messages)
         .service(chats::search_messages)
+        .service(drafts::save_draft)
+        .service(drafts::get_draft)
+        .service(drafts::delete_draft)
+        .service(saved::get_saved_chat)
+        .service(saved::list_saved)
+        .service(saved::save_message)
+        .service(saved::update_saved)
+        .service(saved::delete_saved)
         .service(users::upload_avatars)
         .service(users::set_primary)
         .service(users::list_avatars)
         .service(users::search_users)
+        .service(users::set_online_status_visibility)
         .service(files::create_download_token)
         .service(files::download_file)
         .service(uploads::upload_files)
         .service(admin::purge_storage)
+        .service(admin::purge_events)
         .service(messages::send_message)
         .service(messages::edit_message)
+        .service(messages::list_message_revisions)
         .service(messages::delete_message)
+        .service(messages::delete_bulk)
         .service(messages::forward_messages)
         .service(messages::read_bulk)
         .service(messages::list_message_reads)
         .service(messages::purge_reads)
         .service(messages::unread_count)
+        .service(reactions::add_reaction)
+        .service(reactions::remove_reaction)
+        .service(reactions::list_reactors)
+        .service(reactions::get_allowed_reactions)
+        .service(reactions::set_allowed_reactions)
+        .service(polls::send_poll)
+        .service(polls::vote)
+        .service(polls::retract_vote)
+        .service(polls::close_poll)
+        .service(polls::list_voters)
+        .service(threads::get_thread)
+        .service(scheduled::list_scheduled)
+        .service(scheduled::update_scheduled)
+        .service(scheduled::cancel_scheduled)
         .service(members::get_note)
         .service(members::set_note)
         .service(members::delete_note)
@@ -1,7 +1,6 @@
 use crate::auth::{internal_err, AuthUser};
-use crate::models::MessageRow;
+use crate::chat_actions::{self, ChatAction, ChatActionType};
 use crate::state::AppState;
-use crate::ws::ServerWsMsg;
 use actix_web::{post, web, HttpResponse};
 use sqlx::types::Uuid;
 
@@ -59,34 +58,10 @@ pub async fn pin_message(
         .await
         .map_err(internal_err)?;
 
-    // Broadcast chat_action
-    if let Some(pinned_msg) = sqlx::query_as::<_, MessageRow>(
-        "SELECT id, chat_id, sender_id, content, created_at, edited_at FROM messages WHERE id = $1",
-    )
-    .bind(message_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(internal_err)?
-    {
-        let participants = sqlx::query_scalar::<_, Uuid>(
-            "SELECT user_id FROM chat_participants WHERE chat_id = $1",
-        )
-        .bind(chat_id)
-        .fetch_all(&state.pool)
-        .await
-        .map_err(internal_err)?;
-        let msg = ServerWsMsg::ChatAction {
-            sequence_id: state.sequence_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-            chat_id,
-            action_type: "message_pinned".to_string(),
-            data: serde_json::json!({ "pinned_message": pinned_msg }),
-        };
-        for uid in participants {
-            if let Some(tx) = state.clients.get(&uid) {
-                let _ = tx.send(msg.clone());
-            }
-        }
-    }
+    let action = ChatAction::new(chat_id, ChatActionType::MessagePinned, user.0)
+        .about_message(message_id)
+        .payload(serde_json::json!({ "message_id": message_id }));
+    chat_actions::record(&state, action).await;
 
     Ok(HttpResponse::Ok().finish())
 }
@@ -119,30 +94,19 @@ pub async fn unpin_message(
     if meta.owner_id != Some(user.0) && !can_pin.unwrap_or(false) {
         return Ok(HttpResponse::Forbidden().finish());
     }
-    sqlx::query("UPDATE chats SET pinned_message_id = NULL WHERE id = $1")
-        .bind(chat_id)
-        .execute(&state.pool)
-        .await
-        .map_err(internal_err)?;
-
-    // Broadcast chat_action
-    let participants = sqlx::query_scalar::<_, Uuid>(
-        "SELECT user_id FROM chat_participants WHERE chat_id = $1",
+    let unpinned: Option<Option<Uuid>> = sqlx::query_scalar(
+        "UPDATE chats c SET pinned_message_id = NULL FROM chats old WHERE c.id = $1 AND old.id = c.id RETURNING old.pinned_message_id",
     )
     .bind(chat_id)
-    .fetch_all(&state.pool)
+    .fetch_optional(&state.pool)
     .await
     .map_err(internal_err)?;
-    let msg = ServerWsMsg::ChatAction {
-        sequence_id: state.sequence_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst),
-        chat_id,
-        action_type: "message_unpinned".to_string(),
-        data: serde_json::json!({}),
-    };
-    for uid in participants {
-        if let Some(tx) = state.clients.get(&uid) {
-            let _ = tx.send(msg.clone());
-        }
+
+    if let Some(message_id) = unpinned.flatten() {
+        let action = ChatAction::new(chat_id, ChatActionType::MessageUnpinned, user.0)
+            .about_message(message_id)
+            .payload(serde_json::json!({ "message_id": message_id }));
+        chat_actions::record(&state, action).await;
     }
 
     Ok(HttpResponse::Ok().finish())
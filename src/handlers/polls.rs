@@ -0,0 +1,415 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::collections::{HashMap, HashSet};
+use tracing::{error, instrument};
+
+use crate::auth::{internal_err, AuthUser};
+use crate::delivery::{self, MessageBody, OutgoingMessage};
+use crate::events;
+use crate::handlers::messages::ensure_member;
+use crate::models::{PollDto, PollOptionDto, PollVoteReq, SendPollReq};
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+
+/// Polls by id. With a `viewer`, options say which ones the viewer picked, and a quiz
+/// reveals its answer once the viewer voted; without one only closed quizzes do.
+pub(crate) async fn load_polls(
+    pool: &Pool<Postgres>,
+    ids: &[Uuid],
+    viewer: Option<Uuid>,
+) -> sqlx::Result<HashMap<Uuid, PollDto>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    #[derive(sqlx::FromRow)]
+    struct PollRow {
+        id: Uuid,
+        created_by: Uuid,
+        question: String,
+        is_anonymous: bool,
+        allows_multiple: bool,
+        is_quiz: bool,
+        correct_option_id: Option<i32>,
+        explanation: Option<String>,
+        close_at: Option<DateTime<Utc>>,
+        closed_at: Option<DateTime<Utc>>,
+        total_voters: i64,
+    }
+    let polls: Vec<PollRow> = sqlx::query_as(
+        "SELECT p.id, p.created_by, p.question, p.is_anonymous, p.allows_multiple, p.is_quiz, p.correct_option_id, p.explanation, p.close_at, p.closed_at,
+                (SELECT COUNT(DISTINCT v.user_id) FROM poll_votes v WHERE v.poll_id = p.id) AS total_voters
+         FROM polls p WHERE p.id = ANY($1)",
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+
+    #[derive(sqlx::FromRow)]
+    struct OptionRow {
+        poll_id: Uuid,
+        option_id: i32,
+        text: String,
+        voter_count: i64,
+        chosen: Option<bool>,
+    }
+    let options: Vec<OptionRow> = sqlx::query_as(
+        "SELECT o.poll_id, o.option_id, o.text, COUNT(v.user_id) AS voter_count,
+                CASE WHEN $2::uuid IS NULL THEN NULL ELSE COALESCE(BOOL_OR(v.user_id = $2), FALSE) END AS chosen
+         FROM poll_options o
+         LEFT JOIN poll_votes v ON v.poll_id = o.poll_id AND v.option_id = o.option_id
+         WHERE o.poll_id = ANY($1)
+         GROUP BY o.poll_id, o.option_id, o.text
+         ORDER BY o.option_id",
+    )
+    .bind(ids)
+    .bind(viewer)
+    .fetch_all(pool)
+    .await?;
+    let mut options_by_poll: HashMap<Uuid, Vec<PollOptionDto>> = HashMap::new();
+    for o in options {
+        options_by_poll.entry(o.poll_id).or_default().push(PollOptionDto {
+            id: o.option_id,
+            text: o.text,
+            voter_count: o.voter_count,
+            chosen: o.chosen,
+        });
+    }
+
+    let now = Utc::now();
+    Ok(polls
+        .into_iter()
+        .map(|p| {
+            let options = options_by_poll.remove(&p.id).unwrap_or_default();
+            let is_closed = p.closed_at.is_some() || p.close_at.is_some_and(|at| at <= now);
+            let voted = options.iter().any(|o| o.chosen == Some(true));
+            let reveal = is_closed || voted || viewer == Some(p.created_by);
+            let dto = PollDto {
+                id: p.id,
+                question: p.question,
+                options,
+                total_voters: p.total_voters,
+                is_anonymous: p.is_anonymous,
+                allows_multiple: p.allows_multiple,
+                is_quiz: p.is_quiz,
+                correct_option_id: p.correct_option_id.filter(|_| reveal),
+                explanation: p.explanation.filter(|_| reveal),
+                close_at: p.close_at,
+                is_closed,
+            };
+            (p.id, dto)
+        })
+        .collect())
+}
+
+/// Send `poll_updated` to every chat showing the poll: the original and its forwards.
+async fn broadcast_poll(state: &AppState, poll_id: Uuid) -> anyhow::Result<()> {
+    let Some(poll) = load_polls(&state.pool, &[poll_id], None).await?.remove(&poll_id) else {
+        return Ok(());
+    };
+    let messages: Vec<(Uuid, Uuid)> =
+        sqlx::query_as("SELECT id, chat_id FROM messages WHERE poll_id = $1 AND is_deleted = FALSE")
+            .bind(poll_id)
+            .fetch_all(&state.pool)
+            .await?;
+    for (message_id, chat_id) in messages {
+        let msg = ServerWsMsg::PollUpdated { sequence_id: 0, chat_id, message_id, poll: Box::new(poll.clone()) };
+        events::publish_to_chat(state, chat_id, msg).await?;
+    }
+    Ok(())
+}
+
+/// Close polls whose `close_at` has passed and announce it; returns how many were closed.
+pub async fn close_due(state: &AppState) -> anyhow::Result<usize> {
+    let closed: Vec<Uuid> = sqlx::query_scalar(
+        "UPDATE polls SET closed_at = close_at WHERE closed_at IS NULL AND close_at <= now() RETURNING id",
+    )
+    .fetch_all(&state.pool)
+    .await?;
+    for poll_id in &closed {
+        broadcast_poll(state, *poll_id).await?;
+    }
+    Ok(closed.len())
+}
+
+#[derive(sqlx::FromRow)]
+struct PollMessage {
+    chat_id: Uuid,
+    poll_id: Uuid,
+}
+
+/// The poll behind a message the caller can see, or the response to give instead.
+async fn fetch_poll_message(
+    state: &AppState,
+    message_id: Uuid,
+    user_id: Uuid,
+) -> Result<Result<PollMessage, HttpResponse>, actix_web::Error> {
+    let target: Option<PollMessage> = sqlx::query_as(
+        "SELECT chat_id, poll_id FROM messages WHERE id = $1 AND poll_id IS NOT NULL AND is_deleted = FALSE",
+    )
+    .bind(message_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    let Some(target) = target else {
+        return Ok(Err(HttpResponse::NotFound().body("poll not found")));
+    };
+    if !ensure_member(&state.pool, target.chat_id, user_id).await? {
+        return Ok(Err(HttpResponse::Forbidden().finish()));
+    }
+    Ok(Ok(target))
+}
+
+async fn poll_response(state: &AppState, message_id: Uuid, poll_id: Uuid, user_id: Uuid) -> actix_web::Result<HttpResponse> {
+    let poll = load_polls(&state.pool, &[poll_id], Some(user_id))
+        .await
+        .map_err(internal_err)?
+        .remove(&poll_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("poll not found"))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message_id": message_id,
+        "poll": poll,
+    })))
+}
+
+/// Announce a changed poll and answer the caller with the poll as they see it. The change
+/// is already stored, so a failed broadcast is logged rather than reported.
+async fn poll_changed(state: &AppState, message_id: Uuid, poll_id: Uuid, user_id: Uuid) -> actix_web::Result<HttpResponse> {
+    if let Err(e) = broadcast_poll(state, poll_id).await {
+        error!(%poll_id, ?e, "poll_updated broadcast error");
+    }
+    poll_response(state, message_id, poll_id, user_id).await
+}
+
+#[post("/v1/api/chats/{chat_id}/polls")]
+#[instrument(skip(state, req, user))]
+pub async fn send_poll(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+    req: web::Json<SendPollReq>,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    let req = req.into_inner();
+    let mut msg = OutgoingMessage::new(chat_id, user.0, MessageBody::Poll(req.poll));
+    msg.reply_to_message_id = req.reply_to_message_id;
+    msg.thread_root_id = req.thread_root_id;
+    let sent = delivery::deliver(&state, msg).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"id": sent.message.id, "kind": "poll"})))
+}
+
+#[derive(sqlx::FromRow)]
+struct PollState {
+    allows_multiple: bool,
+    is_quiz: bool,
+    is_closed: bool,
+    option_count: i64,
+}
+
+/// Lock the poll row for the rest of `tx`, so a user's concurrent votes apply one at a time.
+async fn lock_poll(tx: &mut sqlx::Transaction<'_, Postgres>, poll_id: Uuid) -> Result<PollState, actix_web::Error> {
+    sqlx::query_as::<_, PollState>(
+        "SELECT allows_multiple, is_quiz, (closed_at IS NOT NULL OR COALESCE(close_at <= now(), FALSE)) AS is_closed,
+                (SELECT COUNT(*) FROM poll_options o WHERE o.poll_id = p.id) AS option_count
+         FROM polls p WHERE id = $1 FOR UPDATE",
+    )
+    .bind(poll_id)
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(internal_err)?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("poll not found"))
+}
+
+async fn my_votes(tx: &mut sqlx::Transaction<'_, Postgres>, poll_id: Uuid, user_id: Uuid) -> Result<Vec<i32>, actix_web::Error> {
+    sqlx::query_scalar("SELECT option_id FROM poll_votes WHERE poll_id = $1 AND user_id = $2 ORDER BY option_id")
+        .bind(poll_id)
+        .bind(user_id)
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(internal_err)
+}
+
+#[post("/v1/api/messages/{message_id}/poll/votes")]
+#[instrument(skip(state, req, user))]
+pub async fn vote(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<PollVoteReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let target = match fetch_poll_message(&state, message_id, user.0).await? {
+        Ok(target) => target,
+        Err(resp) => return Ok(resp),
+    };
+    let mut choice: Vec<i32> = req.option_ids.clone();
+    choice.sort_unstable();
+    let unique: HashSet<i32> = choice.iter().copied().collect();
+
+    let mut tx = state.pool.begin().await.map_err(internal_err)?;
+    let poll = lock_poll(&mut tx, target.poll_id).await?;
+    if poll.is_closed {
+        return Ok(HttpResponse::BadRequest().body("poll is closed"));
+    }
+    if choice.is_empty() || unique.len() != choice.len() || choice.iter().any(|&o| o < 0 || o as i64 >= poll.option_count) {
+        return Ok(HttpResponse::BadRequest().body("invalid option_ids"));
+    }
+    if !poll.allows_multiple && choice.len() > 1 {
+        return Ok(HttpResponse::BadRequest().body("poll allows a single choice"));
+    }
+    let current = my_votes(&mut tx, target.poll_id, user.0).await?;
+    if current == choice {
+        tx.rollback().await.map_err(internal_err)?;
+        return poll_response(&state, message_id, target.poll_id, user.0).await;
+    }
+    if poll.is_quiz && !current.is_empty() {
+        return Ok(HttpResponse::Conflict().body("quiz answers are final"));
+    }
+    sqlx::query("DELETE FROM poll_votes WHERE poll_id = $1 AND user_id = $2")
+        .bind(target.poll_id)
+        .bind(user.0)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_err)?;
+    sqlx::query("INSERT INTO poll_votes (poll_id, option_id, user_id) SELECT $1, o, $2 FROM UNNEST($3::int[]) AS o")
+        .bind(target.poll_id)
+        .bind(user.0)
+        .bind(&choice)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_err)?;
+    tx.commit().await.map_err(internal_err)?;
+    poll_changed(&state, message_id, target.poll_id, user.0).await
+}
+
+#[delete("/v1/api/messages/{message_id}/poll/votes")]
+#[instrument(skip(state, user))]
+pub async fn retract_vote(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let target = match fetch_poll_message(&state, message_id, user.0).await? {
+        Ok(target) => target,
+        Err(resp) => return Ok(resp),
+    };
+    let mut tx = state.pool.begin().await.map_err(internal_err)?;
+    let poll = lock_poll(&mut tx, target.poll_id).await?;
+    if poll.is_closed {
+        return Ok(HttpResponse::BadRequest().body("poll is closed"));
+    }
+    if poll.is_quiz {
+        return Ok(HttpResponse::BadRequest().body("quiz answers cannot be retracted"));
+    }
+    let removed = sqlx::query("DELETE FROM poll_votes WHERE poll_id = $1 AND user_id = $2")
+        .bind(target.poll_id)
+        .bind(user.0)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_err)?
+        .rows_affected();
+    tx.commit().await.map_err(internal_err)?;
+    if removed == 0 {
+        return poll_response(&state, message_id, target.poll_id, user.0).await;
+    }
+    poll_changed(&state, message_id, target.poll_id, user.0).await
+}
+
+#[post("/v1/api/messages/{message_id}/poll/close")]
+#[instrument(skip(state, user))]
+pub async fn close_poll(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let target = match fetch_poll_message(&state, message_id, user.0).await? {
+        Ok(target) => target,
+        Err(resp) => return Ok(resp),
+    };
+    let created_by: Uuid = sqlx::query_scalar("SELECT created_by FROM polls WHERE id = $1")
+        .bind(target.poll_id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    if created_by != user.0 {
+        return Ok(HttpResponse::Forbidden().body("only the poll's creator can close it"));
+    }
+    let closed = sqlx::query("UPDATE polls SET closed_at = LEAST(now(), COALESCE(close_at, now())) WHERE id = $1 AND closed_at IS NULL")
+        .bind(target.poll_id)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?
+        .rows_affected();
+    if closed == 0 {
+        return poll_response(&state, message_id, target.poll_id, user.0).await;
+    }
+    poll_changed(&state, message_id, target.poll_id, user.0).await
+}
+
+#[derive(Deserialize)]
+pub struct VotersQuery {
+    pub option_id: Option<i32>,
+    pub limit: Option<usize>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+#[get("/v1/api/messages/{message_id}/poll/voters")]
+#[instrument(skip(state, q, user))]
+pub async fn list_voters(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    q: web::Query<VotersQuery>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let target = match fetch_poll_message(&state, message_id, user.0).await? {
+        Ok(target) => target,
+        Err(resp) => return Ok(resp),
+    };
+    let is_anonymous: bool = sqlx::query_scalar("SELECT is_anonymous FROM polls WHERE id = $1")
+        .bind(target.poll_id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    if is_anonymous {
+        return Ok(HttpResponse::Forbidden().body("poll is anonymous"));
+    }
+
+    #[derive(sqlx::FromRow, serde::Serialize)]
+    struct VoterRow {
+        user_id: Uuid,
+        username: String,
+        option_ids: Vec<i32>,
+        voted_at: DateTime<Utc>,
+    }
+    let limit = q.limit.unwrap_or(50).clamp(1, 200) as i64;
+    let voters: Vec<VoterRow> = sqlx::query_as(
+        "SELECT v.user_id, u.username, ARRAY_AGG(v.option_id ORDER BY v.option_id) AS option_ids, MAX(v.voted_at) AS voted_at
+         FROM poll_votes v JOIN users u ON u.id = v.user_id
+         WHERE v.poll_id = $1 AND ($2::int IS NULL OR v.option_id = $2)
+         GROUP BY v.user_id, u.username
+         HAVING ($3::timestamptz IS NULL OR MAX(v.voted_at) < $3)
+         ORDER BY MAX(v.voted_at) DESC LIMIT $4",
+    )
+    .bind(target.poll_id)
+    .bind(q.option_id)
+    .bind(q.before)
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    let next_before = (voters.len() as i64 == limit)
+        .then(|| voters.last().map(|v| v.voted_at))
+        .flatten();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message_id": message_id,
+        "voters": voters,
+        "next_before": next_before,
+    })))
+}
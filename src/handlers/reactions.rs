@@ -0,0 +1,416 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::collections::{HashMap, HashSet};
+use tracing::{error, instrument};
+
+use crate::auth::{internal_err, AuthUser};
+use crate::chat_actions::{self, ChatAction, ChatActionType};
+use crate::events;
+use crate::handlers::chats::{has_perm, load_admin_perms};
+use crate::handlers::messages::ensure_member;
+use crate::models::{Reaction, ReactionCountDto, SetAllowedReactionsReq};
+use crate::pagination::Cursor;
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+
+pub const REACTION_MODES: [&str; 3] = ["all", "some", "none"];
+/// Distinct reactions one user may put on one message.
+const MAX_REACTIONS_PER_USER: i64 = 3;
+const MAX_ALLOWED_REACTIONS: usize = 100;
+
+#[derive(sqlx::FromRow)]
+struct TargetMessage {
+    chat_id: Uuid,
+    kind: String,
+    is_deleted: bool,
+    chat_type: Option<String>,
+    reactions_mode: String,
+    allowed_reactions: serde_json::Value,
+}
+
+async fn fetch_target(pool: &Pool<Postgres>, message_id: Uuid) -> Result<TargetMessage, actix_web::Error> {
+    sqlx::query_as::<_, TargetMessage>(
+        "SELECT m.chat_id, m.kind, m.is_deleted, c.chat_type, c.reactions_mode, c.allowed_reactions FROM messages m JOIN chats c ON c.id = m.chat_id WHERE m.id = $1",
+    )
+    .bind(message_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_err)?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))
+}
+
+/// Plain emoji are short and carry no letters or digits; custom ones must be a known sticker.
+async fn validate_reaction(pool: &Pool<Postgres>, reaction: &Reaction) -> Result<Option<&'static str>, actix_web::Error> {
+    match reaction {
+        Reaction::Emoji { emoji } => {
+            let len = emoji.chars().count();
+            if len == 0 || len > 16 || emoji.chars().any(|c| c.is_ascii_alphanumeric() || c.is_whitespace()) {
+                return Ok(Some("invalid emoji"));
+            }
+        }
+        Reaction::Custom { sticker_id } => {
+            let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM stickers WHERE id = $1)")
+                .bind(sticker_id)
+                .fetch_one(pool)
+                .await
+                .map_err(internal_err)?;
+            if !exists {
+                return Ok(Some("sticker not found"));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn is_allowed(target: &TargetMessage, reaction: &Reaction) -> bool {
+    match target.reactions_mode.as_str() {
+        "all" => true,
+        "some" => serde_json::from_value::<Vec<Reaction>>(target.allowed_reactions.clone())
+            .map(|allowed| allowed.contains(reaction))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// Reaction counts per message, most popular first. With a `viewer`, each count also says
+/// whether the viewer is among the reactors; without one `reacted_by_me` is left out.
+pub(crate) async fn load_reactions(
+    pool: &Pool<Postgres>,
+    ids: &[Uuid],
+    viewer: Option<Uuid>,
+) -> sqlx::Result<HashMap<Uuid, Vec<ReactionCountDto>>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    #[derive(sqlx::FromRow)]
+    struct Row {
+        message_id: Uuid,
+        emoji: Option<String>,
+        sticker_id: Option<Uuid>,
+        count: i64,
+        mine: Option<bool>,
+    }
+    let rows: Vec<Row> = sqlx::query_as(
+        "SELECT message_id, emoji, sticker_id, COUNT(*) AS count, BOOL_OR(user_id = $2) AS mine FROM message_reactions WHERE message_id = ANY($1) GROUP BY message_id, emoji, sticker_id ORDER BY COUNT(*) DESC, MIN(created_at) ASC",
+    )
+    .bind(ids)
+    .bind(viewer)
+    .fetch_all(pool)
+    .await?;
+    let mut map: HashMap<Uuid, Vec<ReactionCountDto>> = HashMap::new();
+    for row in rows {
+        let Some(reaction) = Reaction::from_columns(row.emoji, row.sticker_id) else {
+            continue;
+        };
+        map.entry(row.message_id).or_default().push(ReactionCountDto {
+            reaction,
+            count: row.count,
+            reacted_by_me: row.mine,
+        });
+    }
+    Ok(map)
+}
+
+/// Tell the chat about a reaction change and answer the reactor with the new counts.
+async fn reaction_changed(
+    state: &AppState,
+    chat_id: Uuid,
+    message_id: Uuid,
+    user_id: Uuid,
+    reaction: Reaction,
+    added: bool,
+) -> actix_web::Result<HttpResponse> {
+    let reactions = load_reactions(&state.pool, &[message_id], None)
+        .await
+        .map_err(internal_err)?
+        .remove(&message_id)
+        .unwrap_or_default();
+    let msg = ServerWsMsg::ReactionUpdated {
+        sequence_id: 0,
+        chat_id,
+        message_id,
+        user_id,
+        reaction,
+        added,
+        reactions,
+    };
+    if let Err(e) = events::publish_to_chat(state, chat_id, msg).await {
+        error!(%message_id, ?e, "reaction_updated broadcast error");
+    }
+    reactions_response(state, message_id, user_id).await
+}
+
+async fn reactions_response(state: &AppState, message_id: Uuid, user_id: Uuid) -> actix_web::Result<HttpResponse> {
+    let reactions = load_reactions(&state.pool, &[message_id], Some(user_id))
+        .await
+        .map_err(internal_err)?
+        .remove(&message_id)
+        .unwrap_or_default();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message_id": message_id,
+        "reactions": reactions,
+    })))
+}
+
+#[post("/v1/api/messages/{message_id}/reactions")]
+#[instrument(skip(state, req, user))]
+pub async fn add_reaction(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<Reaction>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let reaction = req.into_inner();
+    let target = fetch_target(&state.pool, message_id).await?;
+    if !ensure_member(&state.pool, target.chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if target.is_deleted || target.kind == "service" {
+        return Ok(HttpResponse::BadRequest().body("message cannot be reacted to"));
+    }
+    if let Some(reason) = validate_reaction(&state.pool, &reaction).await? {
+        return Ok(HttpResponse::BadRequest().body(reason));
+    }
+    if !is_allowed(&target, &reaction) {
+        return Ok(HttpResponse::Forbidden().body("reaction not allowed in this chat"));
+    }
+    let (emoji, sticker_id) = reaction.columns();
+    // the message row lock serializes reactions on it, so concurrent requests can't both pass the cap
+    let mut tx = state.pool.begin().await.map_err(internal_err)?;
+    sqlx::query("SELECT 1 FROM messages WHERE id = $1 FOR UPDATE")
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(internal_err)?;
+    let inserted = sqlx::query(
+        "INSERT INTO message_reactions (message_id, user_id, emoji, sticker_id)
+         SELECT $1, $2, $3, $4
+         WHERE (SELECT COUNT(*) FROM message_reactions WHERE message_id = $1 AND user_id = $2) < $5
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(message_id)
+    .bind(user.0)
+    .bind(emoji)
+    .bind(sticker_id)
+    .bind(MAX_REACTIONS_PER_USER)
+    .execute(&mut *tx)
+    .await
+    .map_err(internal_err)?
+    .rows_affected();
+    tx.commit().await.map_err(internal_err)?;
+    if inserted == 0 {
+        let already: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji IS NOT DISTINCT FROM $3 AND sticker_id IS NOT DISTINCT FROM $4)",
+        )
+        .bind(message_id)
+        .bind(user.0)
+        .bind(emoji)
+        .bind(sticker_id)
+        .fetch_one(&state.pool)
+        .await
+        .map_err(internal_err)?;
+        if !already {
+            return Ok(HttpResponse::UnprocessableEntity().body("too many reactions on this message"));
+        }
+        return reactions_response(&state, message_id, user.0).await;
+    }
+    reaction_changed(&state, target.chat_id, message_id, user.0, reaction, true).await
+}
+
+#[derive(Deserialize)]
+pub struct ReactionQuery {
+    pub emoji: Option<String>,
+    pub sticker_id: Option<Uuid>,
+}
+
+#[delete("/v1/api/messages/{message_id}/reactions")]
+#[instrument(skip(state, q, user))]
+pub async fn remove_reaction(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    q: web::Query<ReactionQuery>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let q = q.into_inner();
+    let Some(reaction) = Reaction::from_columns(q.emoji, q.sticker_id) else {
+        return Ok(HttpResponse::BadRequest().body("exactly one of emoji or sticker_id required"));
+    };
+    let target = fetch_target(&state.pool, message_id).await?;
+    if !ensure_member(&state.pool, target.chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let (emoji, sticker_id) = reaction.columns();
+    let removed = sqlx::query(
+        "DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji IS NOT DISTINCT FROM $3 AND sticker_id IS NOT DISTINCT FROM $4",
+    )
+    .bind(message_id)
+    .bind(user.0)
+    .bind(emoji)
+    .bind(sticker_id)
+    .execute(&state.pool)
+    .await
+    .map_err(internal_err)?
+    .rows_affected();
+    if removed == 0 {
+        return reactions_response(&state, message_id, user.0).await;
+    }
+    reaction_changed(&state, target.chat_id, message_id, user.0, reaction, false).await
+}
+
+#[derive(Deserialize)]
+pub struct ReactorsQuery {
+    pub emoji: Option<String>,
+    pub sticker_id: Option<Uuid>,
+    pub limit: Option<usize>,
+    pub before: Option<String>,
+}
+
+#[get("/v1/api/messages/{message_id}/reactions")]
+#[instrument(skip(state, q, user))]
+pub async fn list_reactors(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    q: web::Query<ReactorsQuery>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let message_id = path.into_inner();
+    let target = fetch_target(&state.pool, message_id).await?;
+    if target.chat_type.as_deref() == Some("channel") {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+    if !ensure_member(&state.pool, target.chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let participant_count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM chat_participants WHERE chat_id = $1")
+            .bind(target.chat_id)
+            .fetch_one(&state.pool)
+            .await
+            .map_err(internal_err)?;
+    if participant_count > 100 {
+        return Ok(HttpResponse::BadRequest().body("reaction details available only for small groups"));
+    }
+
+    #[derive(sqlx::FromRow, serde::Serialize)]
+    struct ReactorRow {
+        user_id: Uuid,
+        username: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        emoji: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sticker_id: Option<Uuid>,
+        reacted_at: DateTime<Utc>,
+    }
+    let before = match q.before.as_deref() {
+        Some(before) => Some(Cursor::decode(before).ok_or_else(|| actix_web::error::ErrorBadRequest("invalid before cursor"))?),
+        None => None,
+    };
+    let limit = q.limit.unwrap_or(50).clamp(1, 200) as i64;
+    // reactors sharing a timestamp are told apart by user, so a page boundary never skips one
+    let reactors: Vec<ReactorRow> = sqlx::query_as(
+        "SELECT r.user_id, u.username, r.emoji, r.sticker_id, r.created_at AS reacted_at FROM message_reactions r JOIN users u ON u.id = r.user_id
+         WHERE r.message_id = $1 AND ($2::text IS NULL OR r.emoji = $2) AND ($3::uuid IS NULL OR r.sticker_id = $3)
+         AND ($4::timestamptz IS NULL OR (r.created_at, r.user_id) < ($4, $5::uuid))
+         ORDER BY r.created_at DESC, r.user_id DESC LIMIT $6",
+    )
+    .bind(message_id)
+    .bind(&q.emoji)
+    .bind(q.sticker_id)
+    .bind(before.map(|c| c.created_at))
+    .bind(before.map(|c| c.id))
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    let next_before = (reactors.len() as i64 == limit)
+        .then(|| reactors.last().map(|r| Cursor::new(r.reacted_at, r.user_id).encode()))
+        .flatten();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message_id": message_id,
+        "reactors": reactors,
+        "next_before": next_before,
+    })))
+}
+
+#[get("/v1/api/chats/{chat_id}/allowed_reactions")]
+#[instrument(skip(state, user))]
+pub async fn get_allowed_reactions(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    if !ensure_member(&state.pool, chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    let (mode, reactions): (String, serde_json::Value) =
+        sqlx::query_as("SELECT reactions_mode, allowed_reactions FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .fetch_one(&state.pool)
+            .await
+            .map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(SetAllowedReactionsReq {
+        mode,
+        reactions: serde_json::from_value(reactions).unwrap_or_default(),
+    }))
+}
+
+#[post("/v1/api/chats/{chat_id}/allowed_reactions")]
+#[instrument(skip(state, req, user))]
+pub async fn set_allowed_reactions(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<SetAllowedReactionsReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    let mut req = req.into_inner();
+    let (is_direct, owner_id): (bool, Option<Uuid>) =
+        sqlx::query_as("SELECT is_direct, owner_id FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .fetch_optional(&state.pool)
+            .await
+            .map_err(internal_err)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("chat not found"))?;
+    if is_direct {
+        return Ok(HttpResponse::BadRequest().body("direct chats allow all reactions"));
+    }
+    let perms = load_admin_perms(&state.pool, chat_id, user.0).await?;
+    if owner_id != Some(user.0) && !has_perm(perms.as_ref(), |p| p.can_change_info) {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if !REACTION_MODES.contains(&req.mode.as_str()) {
+        return Ok(HttpResponse::BadRequest().body("invalid reactions mode"));
+    }
+    if req.mode != "some" {
+        req.reactions.clear();
+    }
+    if req.reactions.len() > MAX_ALLOWED_REACTIONS {
+        return Ok(HttpResponse::BadRequest().body("too many allowed reactions"));
+    }
+    let mut seen = HashSet::new();
+    req.reactions.retain(|r| seen.insert(r.clone()));
+    for reaction in &req.reactions {
+        if let Some(reason) = validate_reaction(&state.pool, reaction).await? {
+            return Ok(HttpResponse::BadRequest().body(reason));
+        }
+    }
+    sqlx::query("UPDATE chats SET reactions_mode = $1, allowed_reactions = $2 WHERE id = $3")
+        .bind(&req.mode)
+        .bind(serde_json::to_value(&req.reactions).map_err(internal_err)?)
+        .bind(chat_id)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    let action = ChatAction::new(chat_id, ChatActionType::ReactionsChanged, user.0)
+        .payload(serde_json::json!({ "mode": req.mode, "reactions": req.reactions }));
+    chat_actions::record(&state, action).await;
+    Ok(HttpResponse::Ok().json(req))
+}
@@ -0,0 +1,376 @@
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use tracing::instrument;
+
+use crate::auth::{internal_err, AuthUser};
+use crate::handlers::chats::{build_chat_dto, load_message_dtos_for};
+use crate::handlers::messages::{ensure_member, forward_into, Forwarded};
+use crate::models::{SaveMessageReq, SavedItemDto, SavedSourceDto, UpdateSavedItemReq};
+use crate::pagination::Cursor;
+use crate::state::AppState;
+
+/// `chats.chat_type` of a user's Saved Messages chat; its owner is its only participant.
+pub(crate) const SAVED_CHAT_TYPE: &str = "saved";
+const SAVED_CHAT_TITLE: &str = "Saved Messages";
+const MAX_TAG_LEN: usize = 32;
+const MAX_NOTE_LEN: usize = 1000;
+
+const SELECT_ITEMS: &str = "SELECT s.id, s.kind, s.message_id, m.chat_id AS message_chat_id, s.source_chat_id, s.source_message_id, s.source_sender_id, s.tag, s.note, s.created_at,
+    (m.id IS NULL OR m.is_deleted OR (m.expires_at IS NOT NULL AND m.expires_at <= now())) AS deleted,
+    EXISTS (SELECT 1 FROM chat_participants p WHERE p.chat_id = m.chat_id AND p.user_id = s.user_id) AS accessible
+    FROM saved_messages s LEFT JOIN messages m ON m.id = s.message_id";
+
+#[derive(sqlx::FromRow)]
+struct SavedRow {
+    id: Uuid,
+    kind: String,
+    message_id: Option<Uuid>,
+    message_chat_id: Option<Uuid>,
+    source_chat_id: Option<Uuid>,
+    source_message_id: Uuid,
+    source_sender_id: Option<Uuid>,
+    tag: Option<String>,
+    note: Option<String>,
+    created_at: DateTime<Utc>,
+    deleted: bool,
+    /// Whether the user can still read the chat the message is in
+    accessible: bool,
+}
+
+#[get("/v1/api/saved/chat")]
+#[instrument(skip(state, user))]
+pub async fn get_saved_chat(state: web::Data<AppState>, user: AuthUser) -> actix_web::Result<HttpResponse> {
+    let chat_id = ensure_saved_chat(&state.pool, user.0).await.map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(build_chat_dto(&state.pool, chat_id, user.0).await?))
+}
+
+#[derive(Deserialize)]
+pub struct SavedQuery {
+    pub q: Option<String>,
+    pub chat_id: Option<Uuid>,
+    pub tag: Option<String>,
+    pub limit: Option<usize>,
+    pub before: Option<String>,
+}
+
+#[get("/v1/api/saved")]
+#[instrument(skip(state, q, user))]
+pub async fn list_saved(
+    state: web::Data<AppState>,
+    q: web::Query<SavedQuery>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let before = match q.before.as_deref() {
+        Some(before) => Some(Cursor::decode(before).ok_or_else(|| actix_web::error::ErrorBadRequest("invalid before cursor"))?),
+        None => None,
+    };
+    let limit = q.limit.unwrap_or(50).clamp(1, 200) as i64;
+    let pattern = q.q.as_deref().map(str::trim).filter(|s| !s.is_empty()).map(|s| format!("%{}%", s));
+    let tag = q.tag.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    // message text only matches while the user can still see it
+    let rows: Vec<SavedRow> = sqlx::query_as(&format!(
+        "{SELECT_ITEMS}
+         WHERE s.user_id = $1
+           AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = s.message_id AND h.user_id = $1)
+           AND ($2::uuid IS NULL OR s.source_chat_id = $2)
+           AND ($3::text IS NULL OR s.tag = $3)
+           AND ($4::timestamptz IS NULL OR (s.created_at, s.id) < ($4, $5::uuid))
+           AND ($6::text IS NULL OR s.note ILIKE $6 OR s.tag ILIKE $6
+                OR (m.is_deleted = FALSE AND (m.expires_at IS NULL OR m.expires_at > now()) AND m.content ILIKE $6
+                    AND EXISTS (SELECT 1 FROM chat_participants p WHERE p.chat_id = m.chat_id AND p.user_id = s.user_id)))
+         ORDER BY s.created_at DESC, s.id DESC LIMIT $7"
+    ))
+    .bind(user.0)
+    .bind(q.chat_id)
+    .bind(tag)
+    .bind(before.map(|c| c.created_at))
+    .bind(before.map(|c| c.id))
+    .bind(pattern)
+    .bind(limit)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    let next_before = (rows.len() as i64 == limit)
+        .then(|| rows.last().map(|r| Cursor::new(r.created_at, r.id).encode()))
+        .flatten();
+    let items = hydrate(&state.pool, rows, user.0).await.map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "items": items,
+        "next_before": next_before,
+    })))
+}
+
+#[post("/v1/api/saved")]
+#[instrument(skip(state, req, user))]
+pub async fn save_message(
+    state: web::Data<AppState>,
+    req: web::Json<SaveMessageReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let req = req.into_inner();
+    let (tag, note) = match (clean(req.tag.as_deref(), MAX_TAG_LEN), clean(req.note.as_deref(), MAX_NOTE_LEN)) {
+        (Ok(tag), Ok(note)) => (tag, note),
+        (Err(_), _) => return Ok(HttpResponse::BadRequest().body("tag too long")),
+        (_, Err(_)) => return Ok(HttpResponse::BadRequest().body("note too long")),
+    };
+    #[derive(sqlx::FromRow)]
+    struct Source {
+        chat_id: Uuid,
+        sender_id: Uuid,
+        kind: String,
+        is_deleted: bool,
+    }
+    let Some(source) = sqlx::query_as::<_, Source>(
+        "SELECT m.chat_id, m.sender_id, m.kind, m.is_deleted FROM messages m WHERE m.id = $1 AND (m.expires_at IS NULL OR m.expires_at > now()) AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = m.id AND h.user_id = $2)",
+    )
+    .bind(req.message_id)
+    .bind(user.0)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(internal_err)?
+    else {
+        return Ok(HttpResponse::NotFound().body("message not found"));
+    };
+    if !ensure_member(&state.pool, source.chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if source.is_deleted || source.kind == "service" {
+        return Ok(HttpResponse::BadRequest().body("message cannot be saved"));
+    }
+
+    let id = if req.copy {
+        let saved_chat = ensure_saved_chat(&state.pool, user.0).await.map_err(internal_err)?;
+        let forwarded = forward_into(&state, saved_chat, user.0, source.chat_id, &[req.message_id]).await?;
+        let ids = record_copies(&state.pool, user.0, source.chat_id, &forwarded, tag.as_deref(), note.as_deref())
+            .await
+            .map_err(internal_err)?;
+        ids.into_iter().next().ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))?
+    } else {
+        let inserted: Option<Uuid> = sqlx::query_scalar(
+            "INSERT INTO saved_messages (id, user_id, kind, message_id, source_chat_id, source_message_id, source_sender_id, tag, note)
+             VALUES ($1, $2, 'reference', $3, $4, $3, $5, $6, $7)
+             ON CONFLICT (user_id, source_message_id) WHERE kind = 'reference' DO NOTHING
+             RETURNING id",
+        )
+        .bind(Uuid::new_v4())
+        .bind(user.0)
+        .bind(req.message_id)
+        .bind(source.chat_id)
+        .bind(source.sender_id)
+        .bind(&tag)
+        .bind(&note)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(internal_err)?;
+        match inserted {
+            Some(id) => id,
+            None => return Ok(HttpResponse::Conflict().body("already saved")),
+        }
+    };
+    let item = load_item(&state.pool, id, user.0)
+        .await
+        .map_err(internal_err)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("saved item not found"))?;
+    Ok(HttpResponse::Created().json(item))
+}
+
+#[patch("/v1/api/saved/{id}")]
+#[instrument(skip(state, req, user))]
+pub async fn update_saved(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    req: web::Json<UpdateSavedItemReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let id = path.into_inner();
+    let Ok(tag) = clean(req.tag.as_deref(), MAX_TAG_LEN) else {
+        return Ok(HttpResponse::BadRequest().body("tag too long"));
+    };
+    let Ok(note) = clean(req.note.as_deref(), MAX_NOTE_LEN) else {
+        return Ok(HttpResponse::BadRequest().body("note too long"));
+    };
+    // a field left out keeps its value; an empty one clears it
+    let updated = sqlx::query(
+        "UPDATE saved_messages SET tag = CASE WHEN $3 THEN $4 ELSE tag END, note = CASE WHEN $5 THEN $6 ELSE note END
+         WHERE id = $1 AND user_id = $2",
+    )
+    .bind(id)
+    .bind(user.0)
+    .bind(req.tag.is_some())
+    .bind(&tag)
+    .bind(req.note.is_some())
+    .bind(&note)
+    .execute(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    if updated.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().body("saved item not found"));
+    }
+    let item = load_item(&state.pool, id, user.0)
+        .await
+        .map_err(internal_err)?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("saved item not found"))?;
+    Ok(HttpResponse::Ok().json(item))
+}
+
+/// Remove the item from the list. A copy stays in the saved chat until deleted there.
+#[delete("/v1/api/saved/{id}")]
+#[instrument(skip(state, user))]
+pub async fn delete_saved(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let removed = sqlx::query("DELETE FROM saved_messages WHERE id = $1 AND user_id = $2")
+        .bind(path.into_inner())
+        .bind(user.0)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    if removed.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().body("saved item not found"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The user's Saved Messages chat, created on first use.
+pub(crate) async fn ensure_saved_chat(pool: &Pool<Postgres>, user_id: Uuid) -> sqlx::Result<Uuid> {
+    let mut tx = pool.begin().await?;
+    let chat_id = Uuid::new_v4();
+    let created = sqlx::query(
+        "INSERT INTO chats (id, is_direct, chat_type, owner_id, title) VALUES ($1, FALSE, $2, $3, $4)
+         ON CONFLICT (owner_id) WHERE chat_type = 'saved' DO NOTHING",
+    )
+    .bind(chat_id)
+    .bind(SAVED_CHAT_TYPE)
+    .bind(user_id)
+    .bind(SAVED_CHAT_TITLE)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if created {
+        sqlx::query("INSERT INTO chat_participants (chat_id, user_id) VALUES ($1, $2)")
+            .bind(chat_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO chat_members (chat_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(chat_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(chat_id);
+    }
+    tx.commit().await?;
+    sqlx::query_scalar("SELECT id FROM chats WHERE owner_id = $1 AND chat_type = $2")
+        .bind(user_id)
+        .bind(SAVED_CHAT_TYPE)
+        .fetch_one(pool)
+        .await
+}
+
+pub(crate) async fn is_saved_chat_of(pool: &Pool<Postgres>, chat_id: Uuid, user_id: Uuid) -> sqlx::Result<bool> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM chats WHERE id = $1 AND owner_id = $2 AND chat_type = $3)")
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(SAVED_CHAT_TYPE)
+        .fetch_one(pool)
+        .await
+}
+
+/// List copies just forwarded into the user's saved chat, keeping the originals' attribution.
+pub(crate) async fn record_copies(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    source_chat_id: Uuid,
+    copies: &[Forwarded],
+    tag: Option<&str>,
+    note: Option<&str>,
+) -> sqlx::Result<Vec<Uuid>> {
+    let mut ids = Vec::with_capacity(copies.len());
+    for copy in copies {
+        let id = Uuid::new_v4();
+        sqlx::query(
+            "INSERT INTO saved_messages (id, user_id, kind, message_id, source_chat_id, source_message_id, source_sender_id, tag, note)
+             VALUES ($1, $2, 'copy', $3, $4, $5, $6, $7, $8)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(copy.message_id)
+        .bind(source_chat_id)
+        .bind(copy.source_id)
+        .bind(copy.source_sender_id)
+        .bind(tag)
+        .bind(note)
+        .execute(pool)
+        .await?;
+        ids.push(id);
+    }
+    Ok(ids)
+}
+
+async fn load_item(pool: &Pool<Postgres>, id: Uuid, user_id: Uuid) -> sqlx::Result<Option<SavedItemDto>> {
+    let rows: Vec<SavedRow> = sqlx::query_as(&format!("{SELECT_ITEMS} WHERE s.id = $1 AND s.user_id = $2 AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = s.message_id AND h.user_id = $2)"))
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+    Ok(hydrate(pool, rows, user_id).await?.pop())
+}
+
+/// Attach the messages the user can still see; the rest become tombstones.
+async fn hydrate(pool: &Pool<Postgres>, rows: Vec<SavedRow>, user_id: Uuid) -> sqlx::Result<Vec<SavedItemDto>> {
+    let mut by_chat: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for row in rows.iter().filter(|r| !r.deleted && r.accessible) {
+        if let (Some(chat_id), Some(message_id)) = (row.message_chat_id, row.message_id) {
+            by_chat.entry(chat_id).or_default().push(message_id);
+        }
+    }
+    let mut messages = HashMap::new();
+    for ids in by_chat.values() {
+        for dto in load_message_dtos_for(pool, ids, Some(user_id)).await? {
+            messages.insert(dto.id, dto);
+        }
+    }
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let message = row.message_id.and_then(|id| messages.get(&id).cloned());
+            let status = if row.deleted {
+                "deleted"
+            } else if message.is_none() {
+                "unavailable"
+            } else {
+                "available"
+            };
+            SavedItemDto {
+                id: row.id,
+                kind: row.kind,
+                status: status.to_string(),
+                tag: row.tag,
+                note: row.note,
+                source: SavedSourceDto {
+                    message_id: row.source_message_id,
+                    chat_id: row.source_chat_id,
+                    sender_id: row.source_sender_id,
+                },
+                message,
+                created_at: row.created_at,
+            }
+        })
+        .collect())
+}
+
+/// Trim an optional tag or note; blank means none.
+fn clean(value: Option<&str>, max_len: usize) -> Result<Option<String>, ()> {
+    match value.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(v) if v.chars().count() > max_len => Err(()),
+        v => Ok(v.map(str::to_string)),
+    }
+}
@@ -0,0 +1,118 @@
+use actix_web::{delete, get, patch, web, HttpResponse};
+use sqlx::types::Uuid;
+use tracing::instrument;
+
+use crate::auth::{internal_err, AuthUser};
+use crate::delivery;
+use crate::models::{ScheduledMessageDto, UpdateScheduledMessageReq};
+use crate::scheduled::{self, COLUMNS};
+use crate::state::AppState;
+
+#[get("/v1/api/chats/{chat_id}/scheduled_messages")]
+#[instrument(skip(state, user))]
+pub async fn list_scheduled(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let chat_id = path.into_inner();
+    let rows: Vec<ScheduledMessageDto> = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM scheduled_messages WHERE chat_id = $1 AND sender_id = $2 ORDER BY scheduled_at ASC"
+    ))
+    .bind(chat_id)
+    .bind(user.0)
+    .fetch_all(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(rows))
+}
+
+/// The caller's scheduled message, as long as the dispatcher is not sending it right now.
+async fn fetch_editable(
+    state: &AppState,
+    chat_id: Uuid,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Result<ScheduledMessageDto, HttpResponse>, actix_web::Error> {
+    let row: Option<ScheduledMessageDto> = sqlx::query_as(&format!(
+        "SELECT {COLUMNS} FROM scheduled_messages WHERE id = $1 AND chat_id = $2 AND sender_id = $3"
+    ))
+    .bind(id)
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    Ok(match row {
+        None => Err(HttpResponse::NotFound().body("scheduled message not found")),
+        Some(row) if row.status == "sending" => Err(HttpResponse::Conflict().body("message is being sent")),
+        Some(row) => Ok(row),
+    })
+}
+
+#[patch("/v1/api/chats/{chat_id}/scheduled_messages/{id}")]
+#[instrument(skip(state, req, user))]
+pub async fn update_scheduled(
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, Uuid)>,
+    req: web::Json<UpdateScheduledMessageReq>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let (chat_id, id) = path.into_inner();
+    let current = match fetch_editable(&state, chat_id, id, user.0).await? {
+        Ok(row) => row,
+        Err(resp) => return Ok(resp),
+    };
+    let (content, entities) = match (&req.content, &req.entities) {
+        (Some(c), _) if c.trim().is_empty() => return Ok(HttpResponse::BadRequest().body("content required")),
+        (Some(c), entities) => {
+            let entities = delivery::normalize_entities(c, entities.as_deref().unwrap_or_default())?;
+            (c.trim().to_string(), entities)
+        }
+        (None, Some(entities)) => {
+            let entities = delivery::normalize_entities(&current.content, entities)?;
+            (current.content, entities)
+        }
+        (None, None) => (current.content, current.entities),
+    };
+    let scheduled_at = req.scheduled_at.unwrap_or(current.scheduled_at);
+    scheduled::validate_time(scheduled_at)?;
+    // an edit re-arms a message the dispatcher had to give up on
+    let updated: Option<ScheduledMessageDto> = sqlx::query_as(&format!(
+        "UPDATE scheduled_messages SET content = $1, entities = $2, scheduled_at = $3, status = 'pending', error = NULL
+         WHERE id = $4 AND status <> 'sending' RETURNING {COLUMNS}"
+    ))
+    .bind(&content)
+    .bind(sqlx::types::Json(&entities))
+    .bind(scheduled_at)
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    match updated {
+        Some(row) => Ok(HttpResponse::Ok().json(row)),
+        None => Ok(HttpResponse::Conflict().body("message is being sent")),
+    }
+}
+
+#[delete("/v1/api/chats/{chat_id}/scheduled_messages/{id}")]
+#[instrument(skip(state, user))]
+pub async fn cancel_scheduled(
+    state: web::Data<AppState>,
+    path: web::Path<(Uuid, Uuid)>,
+    user: AuthUser,
+) -> actix_web::Result<HttpResponse> {
+    let (chat_id, id) = path.into_inner();
+    if let Err(resp) = fetch_editable(&state, chat_id, id, user.0).await? {
+        return Ok(resp);
+    }
+    let res = sqlx::query("DELETE FROM scheduled_messages WHERE id = $1 AND status <> 'sending'")
+        .bind(id)
+        .execute(&state.pool)
+        .await
+        .map_err(internal_err)?;
+    if res.rows_affected() == 0 {
+        return Ok(HttpResponse::Conflict().body("message is being sent"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -3,11 +3,10 @@ use sqlx::types::Uuid;
 use tracing::instrument;
 
 use crate::auth::{internal_err, AuthUser};
+use crate::delivery::{self, MessageBody, OutgoingMessage};
 use crate::models::{SendStickerReq, StickerCreateReq, StickerPackCreateReq};
 use crate::state::AppState;
 
-use super::messages::ensure_can_send;
-
 #[post("/v1/api/sticker_packs")]
 #[instrument(skip(state, req, user))]
 pub async fn create_pack(
@@ -164,46 +163,8 @@ pub async fn send_sticker(
     req: web::Json<SendStickerReq>,
 ) -> actix_web::Result<HttpResponse> {
     let chat_id = path.into_inner();
-    ensure_can_send(&state, chat_id, user.0).await?;
-
-    #[derive(sqlx::FromRow)]
-    struct StickerMeta {
-        pack_id: Uuid,
-        created_by: Uuid,
-    }
-    let sticker = sqlx::query_as::<_, StickerMeta>(
-        "SELECT s.pack_id, sp.created_by FROM stickers s JOIN sticker_packs sp ON sp.id = s.pack_id WHERE s.id = $1",
-    )
-    .bind(req.sticker_id)
-    .fetch_optional(&state.pool)
-    .await
-    .map_err(internal_err)?
-    .ok_or_else(|| actix_web::error::ErrorNotFound("sticker not found"))?;
-
-    if sticker.created_by != user.0 {
-        let installed = sqlx::query_scalar::<_, Option<i32>>(
-            "SELECT 1 FROM user_sticker_packs WHERE user_id = $1 AND pack_id = $2",
-        )
-        .bind(user.0)
-        .bind(sticker.pack_id)
-        .fetch_one(&state.pool)
-        .await
-        .map_err(internal_err)?
-        .is_some();
-        if !installed {
-            return Ok(HttpResponse::Forbidden().body("install pack first"));
-        }
-    }
-
-    let id = Uuid::new_v4();
-    sqlx::query("INSERT INTO messages (id, chat_id, sender_id, content, kind, sticker_id) VALUES ($1,$2,$3,'', 'sticker',$4)")
-        .bind(id)
-        .bind(chat_id)
-        .bind(user.0)
-        .bind(req.sticker_id)
-        .execute(&state.pool)
-        .await
-        .map_err(internal_err)?;
+    let msg = OutgoingMessage::new(chat_id, user.0, MessageBody::Sticker { sticker_id: req.sticker_id });
+    let sent = delivery::deliver(&state, msg).await?;
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({"id": id, "kind": "sticker"})))
+    Ok(HttpResponse::Ok().json(serde_json::json!({"id": sent.message.id, "kind": "sticker"})))
 }
@@ -0,0 +1,155 @@
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::types::Uuid;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use tracing::instrument;
+
+use crate::auth::{internal_err, AuthUser};
+use crate::handlers::chats::load_message_dtos_for;
+use crate::handlers::messages::ensure_member;
+use crate::models::{SimpleUserDto, ThreadInfoDto};
+use crate::pagination::{self, Anchor, Cursor};
+use crate::state::AppState;
+
+/// Reply summaries for the thread roots among `ids`. With a `viewer`, each summary also
+/// counts the replies by others posted after the viewer's thread read position.
+pub(crate) async fn load_threads(
+    pool: &Pool<Postgres>,
+    ids: &[Uuid],
+    viewer: Option<Uuid>,
+) -> sqlx::Result<HashMap<Uuid, ThreadInfoDto>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    #[derive(sqlx::FromRow)]
+    struct Row {
+        root_id: Uuid,
+        reply_count: i64,
+        last_reply_at: DateTime<Utc>,
+        last_sender_id: Uuid,
+        last_sender_username: String,
+        unread_count: Option<i64>,
+    }
+    let rows: Vec<Row> = sqlx::query_as(
+        "SELECT r.thread_root_id AS root_id, COUNT(*) AS reply_count, MAX(r.created_at) AS last_reply_at,
+                (ARRAY_AGG(r.sender_id ORDER BY r.created_at DESC))[1] AS last_sender_id,
+                (ARRAY_AGG(u.username ORDER BY r.created_at DESC))[1] AS last_sender_username,
+                CASE WHEN $2::uuid IS NULL THEN NULL
+                     ELSE COUNT(*) FILTER (WHERE r.sender_id <> $2 AND (lr.created_at IS NULL OR r.created_at > lr.created_at)
+                                                AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = r.id AND h.user_id = $2)) END AS unread_count
+         FROM messages r
+         JOIN users u ON u.id = r.sender_id
+         LEFT JOIN thread_reads tr ON tr.root_message_id = r.thread_root_id AND tr.user_id = $2
+         LEFT JOIN messages lr ON lr.id = tr.last_read_message_id
+         WHERE r.thread_root_id = ANY($1) AND r.is_deleted = FALSE
+         GROUP BY r.thread_root_id",
+    )
+    .bind(ids)
+    .bind(viewer)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let info = ThreadInfoDto {
+                reply_count: r.reply_count,
+                last_reply_at: r.last_reply_at,
+                last_replier: SimpleUserDto { id: r.last_sender_id, username: r.last_sender_username },
+                unread_count: r.unread_count,
+            };
+            (r.root_id, info)
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+pub struct ThreadQuery {
+    pub limit: Option<usize>,
+    /// A `next` cursor, or an RFC 3339 timestamp.
+    pub before: Option<String>,
+    /// A `prev` cursor.
+    pub after: Option<String>,
+    /// A reply id to centre the page on.
+    pub around: Option<Uuid>,
+}
+
+#[get("/v1/api/messages/{message_id}/thread")]
+#[instrument(skip(state, user, q))]
+pub async fn get_thread(
+    state: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user: AuthUser,
+    q: web::Query<ThreadQuery>,
+) -> actix_web::Result<HttpResponse> {
+    let root_id = path.into_inner();
+    let (chat_id, parent): (Uuid, Option<Uuid>) =
+        sqlx::query_as("SELECT chat_id, thread_root_id FROM messages WHERE id = $1")
+            .bind(root_id)
+            .fetch_optional(&state.pool)
+            .await
+            .map_err(internal_err)?
+            .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))?;
+    if !ensure_member(&state.pool, chat_id, user.0).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+    if parent.is_some() {
+        return Ok(HttpResponse::BadRequest().body("message is a thread reply, not a thread root"));
+    }
+
+    let anchor = Anchor::from_query(q.before.as_deref(), q.after.as_deref(), q.around)?;
+    let around = match anchor.around() {
+        Some(id) => Some(locate_reply(&state.pool, root_id, user.0, id).await?),
+        None => None,
+    };
+    let limit = pagination::limit(q.limit, 50);
+    let pool = &state.pool;
+    let viewer = user.0;
+    let page = pagination::load(anchor, around, limit, |r: &(Uuid, DateTime<Utc>)| Cursor::new(r.1, r.0), |w| {
+        let sql = format!(
+            "SELECT id, created_at FROM messages WHERE thread_root_id = $1 AND (expires_at IS NULL OR expires_at > now()) AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = messages.id AND h.user_id = $2) {}",
+            w.sql("created_at", "id", 3)
+        );
+        async move { w.bind(sqlx::query_as(&sql).bind(root_id).bind(viewer)).fetch_all(pool).await }
+    })
+    .await
+    .map_err(internal_err)?;
+    let reply_ids: Vec<Uuid> = page.items.iter().map(|r| r.0).collect();
+
+    let ids: Vec<Uuid> = std::iter::once(root_id).chain(reply_ids.iter().copied()).collect();
+    let mut dtos = load_message_dtos_for(&state.pool, &ids, Some(user.0))
+        .await
+        .map_err(internal_err)?;
+    let root_pos = dtos
+        .iter()
+        .position(|m| m.id == root_id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))?;
+    let root = dtos.remove(root_pos);
+    // newest first, like GET /chats/{chat_id}/messages; equal timestamps in page order
+    let order: HashMap<Uuid, usize> = reply_ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    dtos.sort_by_key(|m| order.get(&m.id).copied());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "root": root,
+        "messages": dtos,
+        "next": page.next,
+        "prev": page.prev,
+    })))
+}
+
+/// Where a reply visible to `user_id` sits in the thread, for `around=`.
+async fn locate_reply(pool: &Pool<Postgres>, root_id: Uuid, user_id: Uuid, reply_id: Uuid) -> actix_web::Result<Cursor> {
+    let created_at: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT created_at FROM messages WHERE id = $1 AND thread_root_id = $2 AND (expires_at IS NULL OR expires_at > now()) AND NOT EXISTS (SELECT 1 FROM hidden_messages h WHERE h.message_id = messages.id AND h.user_id = $3)",
+    )
+    .bind(reply_id)
+    .bind(root_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(internal_err)?;
+    created_at
+        .map(|t| Cursor::new(t, reply_id))
+        .ok_or_else(|| actix_web::error::ErrorNotFound("message not found"))
+}
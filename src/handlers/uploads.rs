@@ -59,46 +59,39 @@ pub async fn upload_files(
             (buf, ct)
         };
 
-        // compute sha256
-        let mut hasher = Sha256::new();
-        hasher.update(&bytes);
-        let sha256 = format!("{:x}", hasher.finalize());
+        saved.push(store_file(&state, &bytes, &content_type).await.map_err(internal_err)?);
+    }
+    Ok(HttpResponse::Ok().json(saved))
+}
 
-        // check if exists
-        #[derive(sqlx::FromRow)]
-        struct Exists {
-            id: Uuid,
-            content_type: String,
-            created_at: chrono::DateTime<chrono::Utc>,
-        }
-        if let Some(ex) = sqlx::query_as::<_, Exists>(
-            "SELECT id, content_type, created_at FROM storage_files WHERE sha256 = $1",
-        )
-        .bind(&sha256)
-        .fetch_optional(&state.pool)
-        .await
-        .map_err(internal_err)?
-        {
-            saved.push(FileDto {
-                id: ex.id,
-                content_type: ex.content_type,
-                created_at: ex.created_at,
-            });
-            continue;
-        }
+/// Store `bytes` in `storage_files`, named by their SHA-256; identical content already
+/// stored is reused instead of written again.
+pub(crate) async fn store_file(state: &AppState, bytes: &[u8], content_type: &str) -> anyhow::Result<FileDto> {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    let sha256 = format!("{:x}", hasher.finalize());
 
-        let id = Uuid::new_v4();
-        let mut out_path = state.storage_dir.as_ref().clone();
-        out_path.push(&sha256);
-        tokio::fs::write(&out_path, &bytes)
-            .await
-            .map_err(internal_err)?;
-        let size = bytes.len() as i64;
-        let rec = sqlx::query_as::<_, FileDto>(
-            "INSERT INTO storage_files (id, path, content_type, sha256, size) VALUES ($1,$2,$3,$4,$5) RETURNING id, content_type, created_at"
-        ).bind(id).bind(out_path.to_string_lossy().to_string()).bind(content_type).bind(&sha256).bind(size)
-        .fetch_one(&state.pool).await.map_err(internal_err)?;
-        saved.push(rec);
+    if let Some(existing) = sqlx::query_as::<_, FileDto>(
+        "SELECT id, content_type, created_at FROM storage_files WHERE sha256 = $1",
+    )
+    .bind(&sha256)
+    .fetch_optional(&state.pool)
+    .await?
+    {
+        return Ok(existing);
     }
-    Ok(HttpResponse::Ok().json(saved))
+
+    let mut out_path = state.storage_dir.as_ref().clone();
+    out_path.push(&sha256);
+    tokio::fs::write(&out_path, bytes).await?;
+    Ok(sqlx::query_as::<_, FileDto>(
+        "INSERT INTO storage_files (id, path, content_type, sha256, size) VALUES ($1,$2,$3,$4,$5) RETURNING id, content_type, created_at",
+    )
+    .bind(Uuid::new_v4())
+    .bind(out_path.to_string_lossy().to_string())
+    .bind(content_type)
+    .bind(&sha256)
+    .bind(bytes.len() as i64)
+    .fetch_one(&state.pool)
+    .await?)
 }
@@ -1,5 +1,6 @@
 use crate::auth::{internal_err, AuthUser};
-use crate::models::User;
+use crate::models::{SetOnlineStatusVisibilityReq, User};
+use crate::presence;
 use crate::state::AppState;
 use crate::upload::{save_part, CompressOpts};
 use actix_multipart::Multipart;
@@ -93,7 +94,7 @@ pub async fn list_avatars(
 #[get("/v1/api/users/search")]
 pub async fn search_users(
     state: web::Data<AppState>,
-    _user: AuthUser, // authenticated
+    user: AuthUser,
     q: web::Query<std::collections::HashMap<String, String>>,
 ) -> actix_web::Result<HttpResponse> {
     let query = q.get("username").map_or("", |v| v).trim();
@@ -101,7 +102,7 @@ pub async fn search_users(
         return Ok(HttpResponse::Ok().json(Vec::<User>::new()));
     }
     let limit = q.get("limit").and_then(|v| v.parse().ok()).unwrap_or(20);
-    let rows = sqlx::query_as::<_, User>(
+    let mut rows = sqlx::query_as::<_, User>(
         "SELECT id, username, bio, is_online, last_seen_at, online_status_visibility, created_at FROM users WHERE username ILIKE $1 ORDER BY username LIMIT $2"
     )
     .bind(format!("{}%", query))
@@ -109,5 +110,29 @@ pub async fn search_users(
     .fetch_all(&state.pool)
     .await
     .map_err(internal_err)?;
+    presence::mask_users(&state.pool, user.0, rows.iter_mut()).await.map_err(internal_err)?;
     Ok(HttpResponse::Ok().json(rows))
 }
+
+#[post("/v1/api/users/me/online_status_visibility")]
+pub async fn set_online_status_visibility(
+    state: web::Data<AppState>,
+    user: AuthUser,
+    req: web::Json<SetOnlineStatusVisibilityReq>,
+) -> actix_web::Result<HttpResponse> {
+    let visibility = req.online_status_visibility.trim().to_lowercase();
+    if !presence::VISIBILITIES.contains(&visibility.as_str()) {
+        return Ok(HttpResponse::BadRequest().body("online_status_visibility must be everyone, contacts or nobody"));
+    }
+    let updated = sqlx::query_as::<_, User>(
+        "UPDATE users SET online_status_visibility = $2 WHERE id = $1 \
+         RETURNING id, username, bio, is_online, last_seen_at, online_status_visibility, created_at"
+    )
+    .bind(user.0)
+    .bind(&visibility)
+    .fetch_one(&state.pool)
+    .await
+    .map_err(internal_err)?;
+    presence::announce(&state, &updated).await.map_err(internal_err)?;
+    Ok(HttpResponse::Ok().json(updated))
+}
@@ -1,9 +1,21 @@
 pub mod auth;
+pub mod chat_actions;
+pub mod codec;
 pub mod config;
+pub mod delivery;
+pub mod events;
+pub mod expiry;
+pub mod fanout;
 pub mod gif;
 pub mod handlers;
+pub mod link_preview;
 pub mod models;
+pub mod pagination;
+pub mod presence;
+pub mod rpc;
+pub mod scheduled;
 pub mod state;
+pub mod typing;
 pub mod upload;
 pub mod ws;
 
@@ -0,0 +1,469 @@
+use crate::events;
+use crate::handlers::uploads::store_file;
+use crate::models::{LinkPreviewDto, LinkPreviewImageDto, MessageEntity, MessageEntityType};
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use anyhow::{bail, Context};
+use dashmap::DashMap;
+use regex::Regex;
+use reqwest::{redirect, Client, Url};
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{info, warn};
+
+const MAX_REDIRECTS: usize = 3;
+const MAX_URL_LEN: usize = 2048;
+const MAX_TITLE_CHARS: usize = 256;
+const MAX_DESCRIPTION_CHARS: usize = 1024;
+const MAX_SITE_NAME_CHARS: usize = 128;
+/// Thumbnails are scaled down to fit this square.
+const THUMBNAIL_SIZE: u32 = 320;
+/// Images declaring a larger width or height are not decoded.
+const MAX_IMAGE_SIDE: u32 = 8192;
+/// What decoding one image may allocate; a small file can declare a huge canvas.
+const MAX_DECODE_ALLOC: u64 = 64 * 1024 * 1024;
+
+/// The URL a new or edited text message should preview: the first link in its text,
+/// otherwise the first `text_link` entity. Fragments are dropped so one page has one
+/// cache entry.
+pub fn preview_url(content: &str, entities: &[MessageEntity]) -> Option<String> {
+    static URL_RE: OnceLock<Regex> = OnceLock::new();
+    let re = URL_RE.get_or_init(|| Regex::new(r#"(?i)\bhttps?://[^\s<>"']+"#).unwrap());
+    let in_text = re
+        .find_iter(content)
+        .map(|m| m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '}']));
+    let linked = entities
+        .iter()
+        .filter(|e| e.kind == MessageEntityType::TextLink)
+        .filter_map(|e| e.url.as_deref());
+    in_text.chain(linked).find_map(normalize_url)
+}
+
+fn normalize_url(raw: &str) -> Option<String> {
+    let mut url = Url::parse(raw).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return None;
+    }
+    url.set_fragment(None);
+    let url = url.to_string();
+    (url.len() <= MAX_URL_LEN).then_some(url)
+}
+
+/// Outbound preview fetches of this process: at most `link_preview.max_concurrent_fetches`
+/// run at once, and messages that link the same URL share one fetch.
+pub struct PreviewFetches {
+    slots: Semaphore,
+    in_flight: DashMap<String, Arc<Mutex<()>>>,
+}
+
+impl PreviewFetches {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self { slots: Semaphore::new(max_concurrent.max(1)), in_flight: DashMap::new() }
+    }
+}
+
+/// Fetch the preview of `url` for `message_id` in the background. Once it is ready, and
+/// the message still points at `url`, participants get `link_preview_updated`.
+/// `shown` is whether the message already went out carrying a preview from the cache.
+pub fn spawn_attach(state: AppState, chat_id: Uuid, message_id: Uuid, url: String, shown: bool) {
+    tokio::spawn(async move {
+        if let Err(e) = attach(&state, chat_id, message_id, &url, shown).await {
+            warn!(%message_id, %url, ?e, "link preview error");
+        }
+    });
+}
+
+async fn attach(state: &AppState, chat_id: Uuid, message_id: Uuid, url: &str, shown: bool) -> anyhow::Result<()> {
+    // later messages for the URL wait here for the first one's fetch, then find it cached
+    let fetches = &state.link_previews;
+    let lock = fetches.in_flight.entry(url.to_string()).or_default().clone();
+    let cached = {
+        let _claim = lock.lock().await;
+        ensure_cached(state, url, shown).await
+    };
+    drop(lock);
+    fetches.in_flight.remove_if(url, |_, lock| Arc::strong_count(lock) == 1);
+    if !cached? {
+        return Ok(());
+    }
+
+    let still_linked: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM messages WHERE id = $1 AND link_preview_url = $2 AND is_deleted = FALSE)",
+    )
+    .bind(message_id)
+    .bind(url)
+    .fetch_one(&state.pool)
+    .await?;
+    let preview = load_previews(&state.pool, &[url.to_string()]).await?.remove(url);
+    if still_linked && preview.is_some() {
+        let msg = ServerWsMsg::LinkPreviewUpdated { sequence_id: 0, chat_id, message_id, link_preview: preview.map(Box::new) };
+        events::publish_to_chat(state, chat_id, msg).await?;
+    }
+    Ok(())
+}
+
+/// Make sure a fresh preview of `url` is cached, fetching it if needed; false when there
+/// is nothing to announce (no preview, or the message already carried the cached one).
+async fn ensure_cached(state: &AppState, url: &str, shown: bool) -> anyhow::Result<bool> {
+    let ttl = state.config.link_preview.cache_ttl_secs as f64;
+    let fresh: Option<bool> = sqlx::query_scalar(
+        "SELECT status = 'ok' FROM link_previews WHERE url = $1 AND fetched_at > now() - make_interval(secs => $2)",
+    )
+    .bind(url)
+    .bind(ttl)
+    .fetch_optional(&state.pool)
+    .await?;
+    match fresh {
+        Some(true) => Ok(!shown),
+        Some(false) => Ok(false),
+        None => {
+            let budget = Duration::from_millis(state.config.link_preview.timeout_ms);
+            // a full queue skips this message's preview without caching a failure for the URL
+            let Ok(slot) = tokio::time::timeout(budget, state.link_previews.slots.acquire()).await else {
+                info!(%url, "link preview skipped, too many fetches in flight");
+                return Ok(false);
+            };
+            let _slot = slot?;
+            let fetched = match tokio::time::timeout(budget, fetch_preview(state, url)).await {
+                Ok(Ok(preview)) => Some(preview),
+                Ok(Err(e)) => {
+                    info!(%url, ?e, "link preview unavailable");
+                    None
+                }
+                Err(_) => {
+                    info!(%url, "link preview timed out");
+                    None
+                }
+            };
+            store(&state.pool, url, fetched.as_ref()).await?;
+            Ok(fetched.is_some())
+        }
+    }
+}
+
+/// Tell participants that `message_id` no longer has a preview, e.g. after an edit removed the link.
+pub async fn announce_removed(state: &AppState, chat_id: Uuid, message_id: Uuid) -> anyhow::Result<()> {
+    let msg = ServerWsMsg::LinkPreviewUpdated { sequence_id: 0, chat_id, message_id, link_preview: None };
+    events::publish_to_chat(state, chat_id, msg).await?;
+    Ok(())
+}
+
+/// Previews of `urls` that were fetched successfully, keyed by URL.
+pub(crate) async fn load_previews(pool: &PgPool, urls: &[String]) -> sqlx::Result<HashMap<String, LinkPreviewDto>> {
+    #[derive(sqlx::FromRow)]
+    struct Row {
+        url: String,
+        title: Option<String>,
+        description: Option<String>,
+        site_name: Option<String>,
+        image_file_id: Option<Uuid>,
+        image_width: Option<i32>,
+        image_height: Option<i32>,
+    }
+    if urls.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let rows: Vec<Row> = sqlx::query_as(
+        "SELECT url, title, description, site_name, image_file_id, image_width, image_height FROM link_previews WHERE url = ANY($1) AND status = 'ok'",
+    )
+    .bind(urls)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let image = match (r.image_file_id, r.image_width, r.image_height) {
+                (Some(file_id), Some(width), Some(height)) => Some(LinkPreviewImageDto { file_id, width, height }),
+                _ => None,
+            };
+            let dto = LinkPreviewDto {
+                url: r.url.clone(),
+                title: r.title,
+                description: r.description,
+                site_name: r.site_name,
+                image,
+            };
+            (r.url, dto)
+        })
+        .collect())
+}
+
+struct FetchedPreview {
+    title: Option<String>,
+    description: Option<String>,
+    site_name: Option<String>,
+    image: Option<(Uuid, u32, u32)>,
+}
+
+async fn store(pool: &PgPool, url: &str, preview: Option<&FetchedPreview>) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO link_previews (url, status, title, description, site_name, image_file_id, image_width, image_height, fetched_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, now())
+         ON CONFLICT (url) DO UPDATE SET status = EXCLUDED.status, title = EXCLUDED.title, description = EXCLUDED.description,
+             site_name = EXCLUDED.site_name, image_file_id = EXCLUDED.image_file_id, image_width = EXCLUDED.image_width,
+             image_height = EXCLUDED.image_height, fetched_at = EXCLUDED.fetched_at",
+    )
+    .bind(url)
+    .bind(if preview.is_some() { "ok" } else { "failed" })
+    .bind(preview.and_then(|p| p.title.clone()))
+    .bind(preview.and_then(|p| p.description.clone()))
+    .bind(preview.and_then(|p| p.site_name.clone()))
+    .bind(preview.and_then(|p| p.image).map(|(id, _, _)| id))
+    .bind(preview.and_then(|p| p.image).map(|(_, w, _)| w as i32))
+    .bind(preview.and_then(|p| p.image).map(|(_, _, h)| h as i32))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn fetch_preview(state: &AppState, url: &str) -> anyhow::Result<FetchedPreview> {
+    let cfg = &state.config.link_preview;
+    let page = fetch(state, Url::parse(url)?, cfg.max_page_bytes, false).await?;
+    if !matches!(page.content_type.as_str(), "text/html" | "application/xhtml+xml") {
+        bail!("not an html page: {}", page.content_type);
+    }
+    let html = String::from_utf8_lossy(&page.body);
+    let meta = parse_meta(&html);
+    let title = meta.title.map(|t| clip(&t, MAX_TITLE_CHARS));
+    let description = meta.description.map(|d| clip(&d, MAX_DESCRIPTION_CHARS));
+    let site_name = meta
+        .site_name
+        .or_else(|| page.url.host_str().map(str::to_string))
+        .map(|s| clip(&s, MAX_SITE_NAME_CHARS));
+
+    let image_url = meta.image.and_then(|src| page.url.join(&src).ok());
+    let image = match image_url {
+        Some(image_url) => match thumbnail(state, image_url).await {
+            Ok(image) => Some(image),
+            Err(e) => {
+                info!(%url, ?e, "link preview image skipped");
+                None
+            }
+        },
+        None => None,
+    };
+    if title.is_none() && description.is_none() && image.is_none() {
+        bail!("nothing to preview");
+    }
+    Ok(FetchedPreview { title, description, site_name, image })
+}
+
+/// Download an image, scale it to a JPEG thumbnail and store it deduplicated.
+async fn thumbnail(state: &AppState, url: Url) -> anyhow::Result<(Uuid, u32, u32)> {
+    let fetched = fetch(state, url, state.config.link_preview.max_image_bytes, true).await?;
+    if !fetched.content_type.starts_with("image/") {
+        bail!("not an image: {}", fetched.content_type);
+    }
+    let (bytes, width, height) = tokio::task::spawn_blocking(move || -> anyhow::Result<(Vec<u8>, u32, u32)> {
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(MAX_IMAGE_SIDE);
+        limits.max_image_height = Some(MAX_IMAGE_SIDE);
+        limits.max_alloc = Some(MAX_DECODE_ALLOC);
+        let mut reader = image::ImageReader::new(std::io::Cursor::new(&fetched.body)).with_guessed_format()?;
+        reader.limits(limits);
+        let img = reader.decode()?;
+        let thumb = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+        let mut out = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 80).encode(
+            &thumb,
+            thumb.width(),
+            thumb.height(),
+            image::ColorType::Rgb8.into(),
+        )?;
+        Ok((out, thumb.width(), thumb.height()))
+    })
+    .await??;
+    let file = store_file(state, &bytes, "image/jpeg").await?;
+    Ok((file.id, width, height))
+}
+
+struct Fetched {
+    /// Where the body came from after redirects
+    url: Url,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+/// GET `url` following up to [`MAX_REDIRECTS`] redirects. Every hop is resolved and
+/// checked against [`is_public`] first, and the connection is pinned to the checked
+/// addresses so a second DNS answer cannot point it somewhere else. Bodies longer than
+/// `max_bytes` are cut off, or rejected when `exact` (images are useless truncated).
+async fn fetch(state: &AppState, mut url: Url, max_bytes: usize, exact: bool) -> anyhow::Result<Fetched> {
+    let cfg = &state.config.link_preview;
+    for _ in 0..=MAX_REDIRECTS {
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!("unsupported scheme {}", url.scheme());
+        }
+        let host = url.host_str().context("url without host")?.to_string();
+        let port = url.port_or_known_default().context("url without port")?;
+        let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+        let addrs: Vec<SocketAddr> = match literal {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host.as_str(), port)).await?.collect(),
+        };
+        if addrs.is_empty() {
+            bail!("{host} does not resolve");
+        }
+        if !cfg.allow_private_networks && addrs.iter().any(|a| !is_public(a.ip())) {
+            bail!("{host} resolves to a private address");
+        }
+        let client = Client::builder()
+            .redirect(redirect::Policy::none())
+            .timeout(Duration::from_millis(cfg.timeout_ms))
+            .user_agent("qbychat-link-preview/1.0")
+            .resolve_to_addrs(&host, &addrs)
+            .build()?;
+        let mut resp = client.get(url.clone()).send().await?;
+        if resp.status().is_redirection() {
+            let location = resp
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .context("redirect without location")?;
+            url = url.join(location)?;
+            continue;
+        }
+        let resp_status = resp.status();
+        if !resp_status.is_success() {
+            bail!("status {resp_status}");
+        }
+        if exact && resp.content_length().is_some_and(|len| len as usize > max_bytes) {
+            bail!("body too large");
+        }
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > max_bytes {
+                if exact {
+                    bail!("body too large");
+                }
+                body.truncate(max_bytes);
+                break;
+            }
+        }
+        return Ok(Fetched { url, content_type, body });
+    }
+    bail!("too many redirects")
+}
+
+/// Whether `ip` is a globally routable address, i.e. not loopback, private, link-local,
+/// CGNAT, multicast, documentation or otherwise reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                || v4.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 198 && (18..20).contains(&b))
+                || (a == 192 && b == 0 && v4.octets()[2] == 0))
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && v6.segments()[1] == 0x0db8)
+                || first == 0x0064)
+        }
+    }
+}
+
+#[derive(Default)]
+struct PageMeta {
+    title: Option<String>,
+    description: Option<String>,
+    site_name: Option<String>,
+    image: Option<String>,
+}
+
+/// OpenGraph and Twitter card fields of a page, falling back to `<title>` and the plain
+/// `description` meta tag.
+fn parse_meta(html: &str) -> PageMeta {
+    static META_RE: OnceLock<Regex> = OnceLock::new();
+    static ATTR_RE: OnceLock<Regex> = OnceLock::new();
+    static TITLE_RE: OnceLock<Regex> = OnceLock::new();
+    let meta_re = META_RE.get_or_init(|| Regex::new(r"(?is)<meta\b([^>]*)>").unwrap());
+    let attr_re = ATTR_RE.get_or_init(|| {
+        Regex::new(r#"(?s)([A-Za-z_:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap()
+    });
+    let title_re = TITLE_RE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+    let mut tags: HashMap<String, String> = HashMap::new();
+    for meta in meta_re.captures_iter(html) {
+        let mut key = None;
+        let mut content = None;
+        for attr in attr_re.captures_iter(&meta[1]) {
+            let value = attr.get(2).or(attr.get(3)).or(attr.get(4)).map_or("", |m| m.as_str());
+            match attr[1].to_ascii_lowercase().as_str() {
+                "property" | "name" => key = Some(value.to_ascii_lowercase()),
+                "content" => content = Some(value),
+                _ => {}
+            }
+        }
+        if let (Some(key), Some(content)) = (key, content) {
+            let content = clean_text(content);
+            if !content.is_empty() {
+                // the first occurrence wins, as in most crawlers
+                tags.entry(key).or_insert(content);
+            }
+        }
+    }
+    let pick = |keys: &[&str]| keys.iter().find_map(|k| tags.get(*k).cloned());
+    PageMeta {
+        title: pick(&["og:title", "twitter:title"]).or_else(|| {
+            title_re
+                .captures(html)
+                .map(|c| clean_text(&c[1]))
+                .filter(|t| !t.is_empty())
+        }),
+        description: pick(&["og:description", "twitter:description", "description"]),
+        site_name: pick(&["og:site_name", "application-name"]),
+        image: pick(&["og:image:secure_url", "og:image", "og:image:url", "twitter:image", "twitter:image:src"]),
+    }
+}
+
+/// Decode the common HTML entities and collapse whitespace.
+fn clean_text(raw: &str) -> String {
+    let decoded = raw
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+    decoded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn clip(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}…", text[..idx].trim_end()),
+        None => text.to_string(),
+    }
+}
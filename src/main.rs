@@ -6,9 +6,10 @@ use tracing::info;
 
 use qbychat_vibe_coding::config::AppConfig;
 use qbychat_vibe_coding::gif::GifProvider;
+use qbychat_vibe_coding::link_preview::PreviewFetches;
 use qbychat_vibe_coding::run_migrations;
 use qbychat_vibe_coding::state::AppState;
-use qbychat_vibe_coding::{handlers, ws};
+use qbychat_vibe_coding::{events, expiry, fanout, handlers, presence, scheduled, typing, ws};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -21,6 +22,9 @@ async fn main() -> anyhow::Result<()> {
 
     let pool = PgPool::connect(&config.database.url).await?;
     run_migrations(&pool).await?;
+    if config.fanout.backend == "local" {
+        presence::reset_stale(&pool).await?;
+    }
 
     tokio::fs::create_dir_all(&config.storage.dir).await.ok();
     let redis = config
@@ -31,9 +35,13 @@ async fn main() -> anyhow::Result<()> {
 
     let gif_provider = GifProvider::from_config(&config.gif).map(Arc::new);
 
+    let clients = Arc::new(dashmap::DashMap::new());
+    let fanout = fanout::from_config(&config.fanout, redis.as_ref(), clients.clone())?;
+    let typing = typing::from_config(&config.fanout, config.websocket.typing_timeout_secs, redis.as_ref())?;
+
     let state = AppState {
         pool,
-        clients: Arc::new(dashmap::DashMap::new()),
+        clients,
         config: config.clone(),
         jwt_secret: Arc::new(config.auth.jwt_secret.clone()),
         storage_dir: Arc::new(std::path::PathBuf::from(&config.storage.dir)),
@@ -41,10 +49,18 @@ async fn main() -> anyhow::Result<()> {
         gif_provider,
         download_token_ttl: config.download.token_ttl_secs,
         admin_token: Arc::new(config.admin.token.clone()),
-        typing: Arc::new(dashmap::DashMap::new()),
+        typing,
         presence: Arc::new(dashmap::DashMap::new()),
-        sequence_counter: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+        fanout,
+        link_previews: Arc::new(PreviewFetches::new(config.link_preview.max_concurrent_fetches)),
     };
+    typing::spawn_sweeper(state.clone());
+    scheduled::spawn_dispatcher(state.clone());
+    expiry::spawn_reaper(state.clone());
+    events::spawn_purger(state.clone());
+    if config.fanout.backend != "local" {
+        presence::spawn_sweeper(state.clone());
+    }
 
     info!("listening on {}", config.server.bind_addr);
     HttpServer::new(move || {
@@ -57,7 +73,6 @@ async fn main() -> anyhow::Result<()> {
             )
     })
     .bind(&config.server.bind_addr)?
-    .workers(1)
     .run()
     .await?;
 
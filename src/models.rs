@@ -1,3 +1,4 @@
+use crate::chat_actions::ChatActionType;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{types::Uuid, FromRow};
@@ -11,6 +12,10 @@ pub struct User {
     pub last_seen_at: Option<DateTime<Utc>>,
     pub online_status_visibility: String, // "everyone", "contacts", "nobody"
     pub created_at: DateTime<Utc>,
+    /// Coarse last-seen ("recently", "within_week", ...) set when the viewer may not see the exact presence
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -87,7 +92,7 @@ pub struct TransferOwnershipReq {
 #[derive(Debug, Serialize, Clone)]
 pub struct ChatDto {
     pub id: Uuid,
-    pub r#type: String, // "direct", "group", "channel"
+    pub r#type: String, // "direct", "group", "channel", "saved"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -101,6 +106,9 @@ pub struct ChatDto {
     pub pinned_message: Option<MessageDto>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// Timer applied to new messages; see `POST /chats/{chat_id}/auto_delete`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_delete_seconds: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -162,13 +170,25 @@ pub struct MessageRow {
     pub chat_id: Uuid,
     pub sender_id: Uuid,
     pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[sqlx(json)]
+    pub entities: Vec<MessageEntity>,
     pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub revision: i32,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ListQuery {
     pub limit: Option<usize>,
-    pub before: Option<DateTime<Utc>>,
+    /// A `next` cursor, or an RFC 3339 timestamp.
+    pub before: Option<String>,
+    /// A `prev` cursor.
+    pub after: Option<String>,
+    /// A message id to centre the page on.
+    pub around: Option<Uuid>,
     pub include_reads: Option<bool>,
 }
 
@@ -178,6 +198,24 @@ pub struct ForwardMessagesReq {
     pub message_ids: Vec<Uuid>,
 }
 
+/// Who a deletion applies to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeleteScope {
+    /// Hide the messages from the caller's own history only
+    Me,
+    #[default]
+    Everyone,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteMessagesReq {
+    pub chat_id: Uuid,
+    pub message_ids: Vec<Uuid>,
+    #[serde(default)]
+    pub scope: DeleteScope,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct StickerPackCreateReq {
     pub title: String,
@@ -195,7 +233,7 @@ pub struct SendStickerReq {
     pub sticker_id: Uuid,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct GifSendReq {
     pub gif_id: String,
     pub gif_url: String,
@@ -203,19 +241,210 @@ pub struct GifSendReq {
     pub provider: String,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageEntityType {
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    Spoiler,
+    Code,
+    Pre,
+    TextLink,
+    TextMention,
+}
+
+/// Formatting over a range of a message's `content`. `offset` and `length` count UTF-16
+/// code units, like JavaScript string indices.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MessageEntity {
+    #[serde(rename = "type")]
+    pub kind: MessageEntityType,
+    pub offset: i32,
+    pub length: i32,
+    /// Target of a `text_link`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Mentioned user of a `text_mention`; survives username changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<Uuid>,
+    /// Language of a `pre` block
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollSendReq {
+    pub question: String,
+    pub options: Vec<String>,
+    #[serde(default = "default_true")]
+    pub is_anonymous: bool,
+    #[serde(default)]
+    pub allows_multiple: bool,
+    /// Makes the poll a quiz with this option as the right answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correct_option_id: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close_at: Option<DateTime<Utc>>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendPollReq {
+    #[serde(flatten)]
+    pub poll: PollSendReq,
+    pub reply_to_message_id: Option<Uuid>,
+    pub thread_root_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PollVoteReq {
+    pub option_ids: Vec<i32>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SetVisibilityReq {
     pub is_public: bool,
     pub public_handle: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetAutoDeleteReq {
+    /// `null` turns auto-delete off
+    pub seconds: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetTitleReq {
+    pub title: String,
+}
+
+/// A message waiting in `scheduled_messages`; only its sender can see it.
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct ScheduledMessageDto {
+    pub id: Uuid,
+    pub chat_id: Uuid,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[sqlx(json)]
+    pub entities: Vec<MessageEntity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to_message_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_root_id: Option<Uuid>,
+    pub attachment_ids: Vec<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<i32>,
+    #[serde(default)]
+    pub disable_link_preview: bool,
+    pub scheduled_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub status: String, // "pending", "sending", "failed"
+    /// Why the dispatcher could not send it (left the chat, muted, ...)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateScheduledMessageReq {
+    pub content: Option<String>,
+    /// Replaces the entities; changing `content` without them clears them
+    pub entities: Option<Vec<MessageEntity>>,
+    pub scheduled_at: Option<DateTime<Utc>>,
+}
+
+/// Unsent composer text kept per (user, chat) so another device can pick it up.
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct DraftDto {
+    pub chat_id: Uuid,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[sqlx(json)]
+    pub entities: Vec<MessageEntity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to_message_id: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveDraftReq {
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub entities: Vec<MessageEntity>,
+    pub reply_to_message_id: Option<Uuid>,
+}
+
+/// An entry in the user's Saved Messages list.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedItemDto {
+    pub id: Uuid,
+    pub kind: String,   // "copy", "reference"
+    pub status: String, // "available", "deleted", "unavailable"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub source: SavedSourceDto,
+    /// The copy or the original; absent unless `status` is "available"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<MessageDto>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Where a saved item came from; the chat and sender are gone when those were deleted.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SavedSourceDto {
+    pub message_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveMessageReq {
+    pub message_id: Uuid,
+    /// Forward a copy into the saved chat instead of bookmarking the original
+    #[serde(default)]
+    pub copy: bool,
+    pub tag: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateSavedItemReq {
+    /// An empty string clears it
+    pub tag: Option<String>,
+    /// An empty string clears it
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetAllowedReactionsReq {
+    pub mode: String, // "all", "some", "none"
+    #[serde(default)]
+    pub reactions: Vec<Reaction>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetOnlineStatusVisibilityReq {
+    pub online_status_visibility: String, // "everyone", "contacts", "nobody"
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageAttachmentDto {
     pub id: Uuid,
     pub content_type: Option<String>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageReplyDto {
     pub id: Uuid,
     pub content: String,
@@ -223,13 +452,13 @@ pub struct MessageReplyDto {
     pub created_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageMentionDto {
     pub user_id: Uuid,
     pub username: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageReadReceiptDto {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub read_count: Option<i64>,
@@ -239,20 +468,20 @@ pub struct MessageReadReceiptDto {
     pub last_read_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ForwardedChatDto {
     pub id: Uuid,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SimpleUserDto {
     pub id: Uuid,
     pub username: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ForwardedFromDto {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub chat: Option<ForwardedChatDto>,
@@ -260,7 +489,7 @@ pub struct ForwardedFromDto {
     pub sender: Option<SimpleUserDto>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StickerMessageDto {
     pub id: Uuid,
     pub pack_id: Uuid,
@@ -270,7 +499,7 @@ pub struct StickerMessageDto {
     pub file_id: Uuid,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GifMessageDto {
     pub id: String,
     pub url: String,
@@ -278,16 +507,21 @@ pub struct GifMessageDto {
     pub provider: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MessageDto {
     pub id: Uuid,
     pub chat_id: Uuid,
     pub sender: SimpleUserDto,
     pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<MessageEntity>,
     pub kind: String,
     pub created_at: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub edited_at: Option<DateTime<Utc>>,
+    /// Starts at 1 and grows with every edit; see `message_revisions`
+    #[serde(default)]
+    pub revision: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reply_to: Option<MessageReplyDto>,
     pub attachments: Vec<MessageAttachmentDto>,
@@ -301,4 +535,138 @@ pub struct MessageDto {
     pub sticker: Option<StickerMessageDto>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gif: Option<GifMessageDto>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<ServiceActionDto>,
+    #[serde(default)]
+    pub reactions: Vec<ReactionCountDto>,
+    /// Set on replies posted inside a thread
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_root_id: Option<Uuid>,
+    /// Set on thread roots that have replies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<ThreadInfoDto>,
+    /// Set on self-destructing messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<MessageTtlDto>,
+    /// Set on `poll` messages; forwarded copies show the same poll
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollDto>,
+    /// Filled in shortly after sending; `link_preview_updated` announces it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_preview: Option<LinkPreviewDto>,
+}
+
+/// A web page preview generated for a URL in a message.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LinkPreviewDto {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<LinkPreviewImageDto>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LinkPreviewImageDto {
+    pub file_id: Uuid,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PollDto {
+    pub id: Uuid,
+    pub question: String,
+    pub options: Vec<PollOptionDto>,
+    pub total_voters: i64,
+    pub is_anonymous: bool,
+    pub allows_multiple: bool,
+    pub is_quiz: bool,
+    /// Quiz answer; revealed to voters, the creator, and everyone once the poll is closed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correct_option_id: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub close_at: Option<DateTime<Utc>>,
+    pub is_closed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PollOptionDto {
+    pub id: i32,
+    pub text: String,
+    pub voter_count: i64,
+    /// Whether the requesting user picked this option; absent in broadcasts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chosen: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageTtlDto {
+    pub ttl_seconds: i32,
+    /// When the countdown started; absent while one-time media waits for its first read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub starts_on_read: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ThreadInfoDto {
+    pub reply_count: i64,
+    pub last_reply_at: DateTime<Utc>,
+    pub last_replier: SimpleUserDto,
+    /// Replies by others the requesting user has not read; absent in broadcasts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unread_count: Option<i64>,
+}
+
+/// A reaction: a plain emoji or a custom one backed by a sticker.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum Reaction {
+    Emoji { emoji: String },
+    Custom { sticker_id: Uuid },
+}
+
+impl Reaction {
+    /// Build from the `(emoji, sticker_id)` column pair used by `message_reactions`.
+    pub fn from_columns(emoji: Option<String>, sticker_id: Option<Uuid>) -> Option<Self> {
+        match (emoji, sticker_id) {
+            (Some(emoji), None) => Some(Reaction::Emoji { emoji }),
+            (None, Some(sticker_id)) => Some(Reaction::Custom { sticker_id }),
+            _ => None,
+        }
+    }
+
+    pub fn columns(&self) -> (Option<&str>, Option<Uuid>) {
+        match self {
+            Reaction::Emoji { emoji } => (Some(emoji.as_str()), None),
+            Reaction::Custom { sticker_id } => (None, Some(*sticker_id)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReactionCountDto {
+    pub reaction: Reaction,
+    pub count: i64,
+    /// Whether the requesting user chose this reaction; absent in `reaction_updated` events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reacted_by_me: Option<bool>,
+}
+
+/// What a `kind = "service"` message records; the actor is the message's sender.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceActionDto {
+    pub action: ChatActionType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<SimpleUserDto>,
+    pub payload: serde_json::Value,
 }
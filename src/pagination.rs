@@ -0,0 +1,226 @@
+//! Keyset pagination over `(created_at, id)`.
+//!
+//! Lists are returned newest first. A page carries `next` (pass it as `before` to load
+//! older items) and `prev` (pass it as `after` to load newer ones); either is `null`
+//! when there is nothing more in that direction. Cursors are opaque to clients.
+
+use actix_web::error::ErrorBadRequest;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryAs;
+use sqlx::types::Uuid;
+use sqlx::Postgres;
+use std::future::Future;
+
+pub const MAX_LIMIT: usize = 200;
+
+/// The sort key of one item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn new(created_at: DateTime<Utc>, id: Uuid) -> Self {
+        Self { created_at, id }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut bytes = self.created_at.timestamp_micros().to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.id.as_bytes());
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(s: &str) -> Option<Self> {
+        if s.len() != 48 || !s.is_ascii() {
+            return None;
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let micros = i64::from_be_bytes(bytes[..8].try_into().ok()?);
+        Some(Self {
+            created_at: DateTime::from_timestamp_micros(micros)?,
+            id: Uuid::from_slice(&bytes[8..]).ok()?,
+        })
+    }
+}
+
+/// Where the client asked a page to start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    Latest,
+    Before(Cursor),
+    After(Cursor),
+    /// Centred on one item, which the handler looks up itself.
+    Around(Uuid),
+}
+
+impl Anchor {
+    /// At most one of the modes may be given. `before` also takes a plain RFC 3339
+    /// timestamp, as it did before cursors existed.
+    pub fn from_query(
+        before: Option<&str>,
+        after: Option<&str>,
+        around: Option<Uuid>,
+    ) -> actix_web::Result<Self> {
+        let given = [before.is_some(), after.is_some(), around.is_some()];
+        if given.iter().filter(|g| **g).count() > 1 {
+            return Err(ErrorBadRequest("use only one of before, after and around"));
+        }
+        if let Some(before) = before {
+            let cursor = Cursor::decode(before)
+                .or_else(|| {
+                    DateTime::parse_from_rfc3339(before)
+                        .ok()
+                        .map(|t| Cursor::new(t.with_timezone(&Utc), Uuid::nil()))
+                })
+                .ok_or_else(|| ErrorBadRequest("invalid before cursor"))?;
+            return Ok(Self::Before(cursor));
+        }
+        if let Some(after) = after {
+            let cursor = Cursor::decode(after).ok_or_else(|| ErrorBadRequest("invalid after cursor"))?;
+            return Ok(Self::After(cursor));
+        }
+        Ok(around.map_or(Self::Latest, Self::Around))
+    }
+
+    pub fn around(&self) -> Option<Uuid> {
+        match self {
+            Self::Around(id) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+/// Clamp a requested page size to `1..=MAX_LIMIT`.
+pub fn limit(requested: Option<usize>, default: usize) -> i64 {
+    requested.unwrap_or(default).clamp(1, MAX_LIMIT) as i64
+}
+
+/// One query's worth of rows on one side of a cursor.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub cursor: Option<Cursor>,
+    pub newer: bool,
+    pub inclusive: bool,
+    pub limit: i64,
+}
+
+impl Window {
+    /// `AND <keyset condition> ORDER BY … LIMIT …` over the given columns, with the
+    /// cursor and limit bound at `$first`, `$first + 1` and `$first + 2` (see [`Window::bind`]).
+    pub fn sql(&self, created_at: &str, id: &str, first: usize) -> String {
+        let (op, dir) = match (self.newer, self.inclusive) {
+            (true, true) => (">=", "ASC"),
+            (true, false) => (">", "ASC"),
+            (false, true) => ("<=", "DESC"),
+            (false, false) => ("<", "DESC"),
+        };
+        format!(
+            "AND (${t}::timestamptz IS NULL OR ({c}, {i}) {op} (${t}, ${u}::uuid)) ORDER BY {c} {dir}, {i} {dir} LIMIT ${l}",
+            c = created_at,
+            i = id,
+            t = first,
+            u = first + 1,
+            l = first + 2,
+        )
+    }
+
+    pub fn bind<'q, O>(
+        &self,
+        query: QueryAs<'q, Postgres, O, PgArguments>,
+    ) -> QueryAs<'q, Postgres, O, PgArguments> {
+        query
+            .bind(self.cursor.map(|c| c.created_at))
+            .bind(self.cursor.map(|c| c.id))
+            .bind(self.limit)
+    }
+}
+
+/// A page of items, newest first.
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<String>,
+    pub prev: Option<String>,
+}
+
+impl<T> Page<T> {
+    fn new(
+        items: Vec<T>,
+        has_older: bool,
+        has_newer: bool,
+        fallback: Option<Cursor>,
+        key: impl Fn(&T) -> Cursor,
+    ) -> Self {
+        let next = has_older
+            .then(|| items.last().map(&key).or(fallback))
+            .flatten()
+            .map(|c| c.encode());
+        let prev = has_newer
+            .then(|| items.first().map(&key).or(fallback))
+            .flatten()
+            .map(|c| c.encode());
+        Self { items, next, prev }
+    }
+}
+
+/// Drop the look-ahead row, reporting whether there was one.
+fn truncate<T>(items: &mut Vec<T>, limit: i64) -> bool {
+    let more = items.len() as i64 > limit;
+    items.truncate(limit as usize);
+    more
+}
+
+/// Load one page. `fetch` runs a [`Window`] and returns its rows in the window's order;
+/// for [`Anchor::Around`], `around` is where the handler found that item.
+pub async fn load<T, E, F, Fut>(
+    anchor: Anchor,
+    around: Option<Cursor>,
+    limit: i64,
+    key: impl Fn(&T) -> Cursor,
+    mut fetch: F,
+) -> Result<Page<T>, E>
+where
+    F: FnMut(Window) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, E>>,
+{
+    let window = |cursor, newer, inclusive, limit: i64| Window {
+        cursor,
+        newer,
+        inclusive,
+        limit: limit + 1,
+    };
+    match (anchor, around) {
+        (Anchor::Around(_), Some(at)) => {
+            // the item itself opens the newer half
+            let older_limit = limit / 2;
+            let newer_limit = limit - older_limit;
+            let mut items = fetch(window(Some(at), true, true, newer_limit)).await?;
+            let mut older = fetch(window(Some(at), false, false, older_limit)).await?;
+            let has_newer = truncate(&mut items, newer_limit);
+            let has_older = truncate(&mut older, older_limit);
+            items.reverse();
+            items.extend(older);
+            Ok(Page::new(items, has_older, has_newer, Some(at), key))
+        }
+        (Anchor::After(cursor), _) => {
+            let mut items = fetch(window(Some(cursor), true, false, limit)).await?;
+            let has_newer = truncate(&mut items, limit);
+            items.reverse();
+            Ok(Page::new(items, true, has_newer, Some(cursor), key))
+        }
+        (Anchor::Before(cursor), _) => {
+            let mut items = fetch(window(Some(cursor), false, false, limit)).await?;
+            let has_older = truncate(&mut items, limit);
+            Ok(Page::new(items, has_older, true, Some(cursor), key))
+        }
+        _ => {
+            let mut items = fetch(window(None, false, false, limit)).await?;
+            let has_older = truncate(&mut items, limit);
+            Ok(Page::new(items, has_older, false, None, key))
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::events;
+use crate::models::User;
+use crate::state::{AppState, PresenceStatus};
+use crate::ws::ServerWsMsg;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::types::Uuid;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use tracing::{error, info};
+
+pub const VISIBILITIES: [&str; 3] = ["everyone", "contacts", "nobody"];
+
+/// Whether viewer `v` may see the exact presence of subject `s` (both aliases of `users`
+/// rows): "everyone" minus anyone `s` blocked, "contacts" means `s`'s friends, and
+/// "nobody" means no one but `s`.
+const CAN_SEE: &str = r#"(s.id = v.id
+    OR (s.online_status_visibility = 'everyone' AND NOT EXISTS (
+        SELECT 1 FROM contacts c WHERE c.user_id = s.id AND c.contact_user_id = v.id AND c.status = 'blocked'))
+    OR (s.online_status_visibility = 'contacts' AND EXISTS (
+        SELECT 1 FROM contacts c WHERE c.user_id = s.id AND c.contact_user_id = v.id AND c.status = 'friend')))"#;
+
+/// Coarse, Telegram-style stand-in for a hidden `last_seen_at`.
+pub fn coarse_last_seen(is_online: bool, last_seen_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> &'static str {
+    if is_online {
+        return "recently";
+    }
+    match last_seen_at.map(|at| now - at) {
+        Some(ago) if ago <= Duration::days(3) => "recently",
+        Some(ago) if ago <= Duration::days(7) => "within_week",
+        Some(ago) if ago <= Duration::days(30) => "within_month",
+        _ => "long_ago",
+    }
+}
+
+/// Record the user's first session: persist `is_online` and tell whoever may see it.
+pub async fn set_online(state: &AppState, user_id: Uuid) -> anyhow::Result<()> {
+    sqlx::query("UPDATE users SET is_online = TRUE WHERE id = $1")
+        .bind(user_id)
+        .execute(&state.pool)
+        .await?;
+    state.presence.insert(user_id, PresenceStatus { status: "online".to_string(), last_seen_at: None });
+    let msg = ServerWsMsg::PresenceUpdate {
+        sequence_id: 0,
+        user_id,
+        status: "online".to_string(),
+        last_seen_at: None,
+        last_seen: None,
+    };
+    let viewers = visible_to(&state.pool, user_id, &common_chat_users(state, user_id).await?).await?;
+    events::publish(state, &viewers, msg).await?;
+    Ok(())
+}
+
+/// Record the user's last session closing: persist `last_seen_at` and tell whoever may see it.
+/// Nothing is sent if the user was already offline, e.g. taken offline by [`sweep_stale`].
+pub async fn set_offline(state: &AppState, user_id: Uuid) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let changed = sqlx::query("UPDATE users SET is_online = FALSE, last_seen_at = $2 WHERE id = $1 AND is_online")
+        .bind(user_id)
+        .bind(now)
+        .execute(&state.pool)
+        .await?
+        .rows_affected();
+    if changed > 0 {
+        went_offline(state, user_id, now).await?;
+    }
+    Ok(())
+}
+
+async fn went_offline(state: &AppState, user_id: Uuid, now: DateTime<Utc>) -> anyhow::Result<()> {
+    state.presence.insert(user_id, PresenceStatus { status: "offline".to_string(), last_seen_at: Some(now) });
+    let msg = ServerWsMsg::PresenceUpdate {
+        sequence_id: 0,
+        user_id,
+        status: "offline".to_string(),
+        last_seen_at: Some(now.to_rfc3339()),
+        last_seen: None,
+    };
+    let viewers = visible_to(&state.pool, user_id, &common_chat_users(state, user_id).await?).await?;
+    events::publish(state, &viewers, msg).await?;
+    Ok(())
+}
+
+/// Clear `is_online` left behind by a node that died with sessions open. Only safe when
+/// this process is the whole cluster (the local fanout backend); clusters rely on
+/// [`spawn_sweeper`] instead.
+pub async fn reset_stale(pool: &PgPool) -> anyhow::Result<()> {
+    sqlx::query("UPDATE users SET is_online = FALSE, last_seen_at = now() WHERE is_online")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Take users offline whose sessions all lived on nodes that have since stopped refreshing
+/// them; returns how many were flipped. Every node sweeps, but each user is flipped and
+/// announced once.
+pub async fn sweep_stale(state: &AppState) -> anyhow::Result<usize> {
+    let marked: Vec<Uuid> = sqlx::query_scalar("SELECT id FROM users WHERE is_online")
+        .fetch_all(&state.pool)
+        .await?;
+    if marked.is_empty() {
+        return Ok(0);
+    }
+    let live = state.fanout.online(marked.clone()).await?;
+    let mut swept = 0;
+    for user_id in marked.into_iter().filter(|id| !live.contains(id)) {
+        if sweep_user(state, user_id).await? {
+            swept += 1;
+        }
+    }
+    Ok(swept)
+}
+
+/// Take one user offline unless they are still marked offline already or have a live
+/// session again by now.
+async fn sweep_user(state: &AppState, user_id: Uuid) -> anyhow::Result<bool> {
+    let mut tx = state.pool.begin().await?;
+    // sessions are counted on the bus before set_online writes the row, so while this lock
+    // holds a reconnect either shows up in the check below or flips the row back afterwards
+    let marked: Option<Uuid> = sqlx::query_scalar("SELECT id FROM users WHERE id = $1 AND is_online FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if marked.is_none() || !state.fanout.online(vec![user_id]).await?.is_empty() {
+        return Ok(false);
+    }
+    let now = Utc::now();
+    sqlx::query("UPDATE users SET is_online = FALSE, last_seen_at = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    went_offline(state, user_id, now).await?;
+    Ok(true)
+}
+
+/// Run [`sweep_stale`] once per session TTL until the process exits.
+pub fn spawn_sweeper(state: AppState) {
+    let interval = std::time::Duration::from_secs(state.config.fanout.session_ttl_secs.max(1));
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            match sweep_stale(&state).await {
+                Ok(0) => {}
+                Ok(swept) => info!(swept, "took users of dead nodes offline"),
+                Err(e) => error!(?e, "presence sweep error"),
+            }
+        }
+    });
+}
+
+/// After a visibility change, re-announce the user's presence to everyone sharing a chat:
+/// exact to those who may see it, coarse (`offline` with `last_seen`) to the rest.
+/// Hidden peers get nothing on later online/offline flips, so this is their last update.
+pub async fn announce(state: &AppState, user: &User) -> anyhow::Result<()> {
+    let peers = common_chat_users(state, user.id).await?;
+    let visible: HashSet<Uuid> = visible_to(&state.pool, user.id, &peers).await?.into_iter().collect();
+    let (exact, hidden): (Vec<Uuid>, Vec<Uuid>) = peers.into_iter().partition(|id| visible.contains(id));
+    let exact_msg = ServerWsMsg::PresenceUpdate {
+        sequence_id: 0,
+        user_id: user.id,
+        status: if user.is_online { "online" } else { "offline" }.to_string(),
+        last_seen_at: user.last_seen_at.filter(|_| !user.is_online).map(|at| at.to_rfc3339()),
+        last_seen: None,
+    };
+    let hidden_msg = ServerWsMsg::PresenceUpdate {
+        sequence_id: 0,
+        user_id: user.id,
+        status: "offline".to_string(),
+        last_seen_at: None,
+        last_seen: Some(coarse_last_seen(user.is_online, user.last_seen_at, Utc::now()).to_string()),
+    };
+    events::publish(state, &exact, exact_msg).await?;
+    events::publish(state, &hidden, hidden_msg).await?;
+    Ok(())
+}
+
+/// Mask the presence of every user in `users` that `viewer` may not see: `is_online` and
+/// `last_seen_at` are cleared and `last_seen` carries the coarse value instead.
+pub async fn mask_users<'a>(
+    pool: &PgPool,
+    viewer: Uuid,
+    users: impl IntoIterator<Item = &'a mut User>,
+) -> anyhow::Result<()> {
+    let mut users: Vec<&mut User> = users.into_iter().collect();
+    let ids: Vec<Uuid> = users.iter().map(|u| u.id).collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+    let hidden: HashSet<Uuid> = sqlx::query_scalar::<_, Uuid>(&format!(
+        "SELECT s.id FROM users s, users v WHERE v.id = $1 AND s.id = ANY($2) AND NOT {CAN_SEE}"
+    ))
+    .bind(viewer)
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .collect();
+    let now = Utc::now();
+    for user in users.iter_mut().filter(|u| hidden.contains(&u.id)) {
+        user.last_seen = Some(coarse_last_seen(user.is_online, user.last_seen_at, now).to_string());
+        user.is_online = false;
+        user.last_seen_at = None;
+    }
+    Ok(())
+}
+
+/// The subset of `viewers` allowed to see `subject`'s exact presence.
+async fn visible_to(pool: &PgPool, subject: Uuid, viewers: &[Uuid]) -> anyhow::Result<Vec<Uuid>> {
+    if viewers.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(sqlx::query_scalar::<_, Uuid>(&format!(
+        "SELECT v.id FROM users s, users v WHERE s.id = $1 AND v.id = ANY($2) AND {CAN_SEE}"
+    ))
+    .bind(subject)
+    .bind(viewers)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Users who share a direct chat or a small group (<=100 members) with `user_id`.
+async fn common_chat_users(state: &AppState, user_id: Uuid) -> anyhow::Result<Vec<Uuid>> {
+    Ok(sqlx::query_scalar(
+        "SELECT DISTINCT cp2.user_id FROM chat_participants cp1
+         JOIN chat_participants cp2 ON cp1.chat_id = cp2.chat_id
+         JOIN chats c ON c.id = cp1.chat_id
+         LEFT JOIN (SELECT chat_id, COUNT(*) as cnt FROM chat_participants GROUP BY chat_id) pc ON pc.chat_id = c.id
+         WHERE cp1.user_id = $1 AND cp2.user_id != $1
+         AND (c.is_direct = TRUE OR pc.cnt <= 100)",
+    )
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?)
+}
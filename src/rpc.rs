@@ -0,0 +1,145 @@
+use crate::auth::SessionUser;
+use crate::handlers;
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use actix_http::{HttpMessage, Payload, Request};
+use actix_web::body::{self, BoxBody};
+use actix_service::IntoServiceFactory;
+use actix_web::dev::{AppConfig, Service, ServiceFactory, ServiceResponse};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::{web, App};
+use futures_util::future::LocalBoxFuture;
+use sqlx::types::Uuid;
+use std::rc::Rc;
+
+type CallFn = dyn Fn(Request) -> LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, actix_web::Error>>;
+
+/// Runs REST endpoints in-process for a websocket session, so a client can use the
+/// whole HTTP API over its socket. Requests go through the same routes and handlers
+/// as `handlers::config`, authenticated as the session's user.
+pub struct RpcDispatcher {
+    call: Box<CallFn>,
+}
+
+impl RpcDispatcher {
+    pub async fn new(state: &AppState) -> anyhow::Result<Self> {
+        let app = App::new()
+            .app_data(web::Data::new(state.clone()))
+            .configure(handlers::config);
+        let service = app
+            .into_factory()
+            .new_service(AppConfig::default())
+            .await
+            .map_err(|_| anyhow::anyhow!("rpc service init failed"))?;
+        let service = Rc::new(service);
+        Ok(Self {
+            call: Box::new(move |req| {
+                let service = service.clone();
+                Box::pin(async move { service.call(req).await.map(|res| res.map_into_boxed_body()) })
+            }),
+        })
+    }
+
+    /// Run `method` ("GET /v1/api/chats") with `params` as the query string (GET, DELETE)
+    /// or JSON body (everything else), answering with `rpc_result` or `rpc_error`.
+    pub async fn call(
+        &self,
+        user_id: Uuid,
+        method: &str,
+        params: Option<serde_json::Value>,
+        request_id: Uuid,
+    ) -> ServerWsMsg {
+        let request = match build_request(user_id, method, params) {
+            Ok(request) => request,
+            Err(message) => return rpc_error(request_id, StatusCode::BAD_REQUEST, message),
+        };
+        let response = match (self.call)(request).await {
+            Ok(response) => response,
+            Err(e) => return rpc_error(request_id, e.as_response_error().status_code(), e.to_string()),
+        };
+        let status = response.status();
+        let bytes = match body::to_bytes(response.into_body()).await {
+            Ok(bytes) => bytes,
+            Err(_) => return rpc_error(request_id, StatusCode::INTERNAL_SERVER_ERROR, "response body error".into()),
+        };
+        if !status.is_success() {
+            return rpc_error(request_id, status, String::from_utf8_lossy(&bytes).into_owned());
+        }
+        let result = if bytes.is_empty() {
+            serde_json::Value::Null
+        } else if let Ok(json) = serde_json::from_slice(&bytes) {
+            json
+        } else if let Ok(text) = std::str::from_utf8(&bytes) {
+            serde_json::Value::String(text.to_string())
+        } else {
+            return ServerWsMsg::RpcError {
+                sequence_id: 0,
+                request_id,
+                status: status.as_u16(),
+                code: "binary_response".into(),
+                message: "binary responses are only available over HTTP".into(),
+            };
+        };
+        ServerWsMsg::RpcResult { sequence_id: 0, request_id, status: status.as_u16(), result }
+    }
+}
+
+fn build_request(user_id: Uuid, method: &str, params: Option<serde_json::Value>) -> Result<Request, String> {
+    let (verb, path) = method
+        .split_once(' ')
+        .ok_or_else(|| "method must look like \"GET /v1/api/...\"".to_string())?;
+    let verb = Method::from_bytes(verb.trim().to_ascii_uppercase().as_bytes()).map_err(|_| "unknown http method".to_string())?;
+    let path = path.trim();
+    if !path.starts_with("/v1/api/") {
+        return Err("path must start with /v1/api/".into());
+    }
+
+    let mut uri = path.to_string();
+    let mut body = None;
+    match params {
+        None | Some(serde_json::Value::Null) => {}
+        Some(params) if verb == Method::GET || verb == Method::DELETE => {
+            let query = serde_urlencoded::to_string(&params)
+                .map_err(|_| "params must be a flat object for GET and DELETE".to_string())?;
+            if !query.is_empty() {
+                uri.push(if uri.contains('?') { '&' } else { '?' });
+                uri.push_str(&query);
+            }
+        }
+        Some(params) => body = Some(serde_json::to_vec(&params).map_err(|e| e.to_string())?),
+    }
+
+    let body_len = body.as_ref().map(Vec::len);
+    let mut request = Request::with_payload(Payload::from(body.unwrap_or_default()));
+    let head = request.head_mut();
+    head.method = verb;
+    head.uri = uri.parse().map_err(|_| "invalid path".to_string())?;
+    if let Some(len) = body_len {
+        head.headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("application/json"));
+        head.headers.insert(header::CONTENT_LENGTH, header::HeaderValue::from(len));
+    }
+    request.extensions_mut().insert(SessionUser(user_id));
+    Ok(request)
+}
+
+/// Refusal for an `rpc` sent while the session already has its limit of calls running.
+pub fn busy(request_id: Uuid) -> ServerWsMsg {
+    rpc_error(request_id, StatusCode::TOO_MANY_REQUESTS, "too many rpc calls in flight".into())
+}
+
+fn rpc_error(request_id: Uuid, status: StatusCode, message: String) -> ServerWsMsg {
+    let code = match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        s if s.is_server_error() => "internal",
+        _ => "error",
+    };
+    ServerWsMsg::RpcError { sequence_id: 0, request_id, status: status.as_u16(), code: code.into(), message }
+}
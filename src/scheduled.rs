@@ -0,0 +1,149 @@
+use crate::delivery::{self, DeliveryError, MessageBody, OutgoingMessage};
+use crate::models::{MessageEntity, ScheduledMessageDto};
+use crate::state::AppState;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::types::Uuid;
+use tracing::{error, info, warn};
+
+/// Columns of `scheduled_messages` that make up a [`ScheduledMessageDto`].
+pub const COLUMNS: &str = "id, chat_id, content, entities, reply_to_message_id, thread_root_id, attachment_ids, expires_in, disable_link_preview, scheduled_at, created_at, status, error";
+/// Pending messages one user may hold per chat.
+const MAX_PER_CHAT: i64 = 100;
+
+/// `scheduled_at` must be in the future and at most a year ahead.
+pub fn validate_time(at: DateTime<Utc>) -> Result<(), DeliveryError> {
+    let now = Utc::now();
+    if at <= now {
+        return Err(DeliveryError::BadRequest("scheduled_at must be in the future"));
+    }
+    if at > now + Duration::days(365) {
+        return Err(DeliveryError::BadRequest("scheduled_at is too far ahead"));
+    }
+    Ok(())
+}
+
+/// Store `msg` to be sent at `at`. It is checked now like an immediate send, and again
+/// by the dispatcher when it fires.
+pub async fn schedule(state: &AppState, msg: OutgoingMessage, at: DateTime<Utc>) -> Result<ScheduledMessageDto, DeliveryError> {
+    let MessageBody::Text { content, entities } = &msg.body else {
+        return Err(DeliveryError::BadRequest("only text messages can be scheduled"));
+    };
+    validate_time(at)?;
+    let thread_root_id = delivery::check(state, &msg).await?;
+    let entities = delivery::normalize_entities(content, entities)?;
+    let pending: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM scheduled_messages WHERE sender_id = $1 AND chat_id = $2")
+        .bind(msg.sender_id)
+        .bind(msg.chat_id)
+        .fetch_one(&state.pool)
+        .await?;
+    if pending >= MAX_PER_CHAT {
+        return Err(DeliveryError::BadRequest("too many scheduled messages in this chat"));
+    }
+    Ok(sqlx::query_as::<_, ScheduledMessageDto>(&format!(
+        "INSERT INTO scheduled_messages (id, chat_id, sender_id, content, entities, reply_to_message_id, thread_root_id, attachment_ids, expires_in, disable_link_preview, scheduled_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING {COLUMNS}"
+    ))
+    .bind(Uuid::new_v4())
+    .bind(msg.chat_id)
+    .bind(msg.sender_id)
+    .bind(content.trim())
+    .bind(sqlx::types::Json(&entities))
+    .bind(msg.reply_to_message_id)
+    .bind(thread_root_id)
+    .bind(&msg.attachment_ids)
+    .bind(msg.expires_in)
+    .bind(msg.disable_link_preview)
+    .bind(at)
+    .fetch_one(&state.pool)
+    .await?)
+}
+
+/// Poll for due scheduled messages and send them until the process exits.
+pub fn spawn_dispatcher(state: AppState) {
+    let interval = std::time::Duration::from_millis(state.config.scheduler.poll_interval_ms.max(50));
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            if let Err(e) = dispatch_due(&state).await {
+                error!(?e, "scheduled dispatch error");
+            }
+        }
+    });
+}
+
+/// Claim and send one batch of due messages; returns how many were claimed.
+///
+/// Claims use `FOR UPDATE SKIP LOCKED`, so instances sharing the database never pick the
+/// same row. Each send carries the client id `scheduled:<id>`: if a node dies between
+/// sending and deleting the row, the retry after `claim_timeout_secs` finds the stored
+/// message instead of sending it twice.
+pub async fn dispatch_due(state: &AppState) -> anyhow::Result<usize> {
+    #[derive(sqlx::FromRow)]
+    struct Claimed {
+        id: Uuid,
+        chat_id: Uuid,
+        sender_id: Uuid,
+        content: String,
+        #[sqlx(json)]
+        entities: Vec<MessageEntity>,
+        reply_to_message_id: Option<Uuid>,
+        thread_root_id: Option<Uuid>,
+        attachment_ids: Vec<Uuid>,
+        expires_in: Option<i32>,
+        disable_link_preview: bool,
+    }
+    let claimed: Vec<Claimed> = sqlx::query_as(
+        "UPDATE scheduled_messages SET status = 'sending', claimed_at = now()
+         WHERE id IN (
+             SELECT id FROM scheduled_messages
+             WHERE scheduled_at <= now()
+               AND (status = 'pending' OR (status = 'sending' AND claimed_at < now() - make_interval(secs => $2)))
+             ORDER BY scheduled_at
+             LIMIT $1
+             FOR UPDATE SKIP LOCKED)
+         RETURNING id, chat_id, sender_id, content, entities, reply_to_message_id, thread_root_id, attachment_ids, expires_in, disable_link_preview",
+    )
+    .bind(state.config.scheduler.batch_size.max(1))
+    .bind(state.config.scheduler.claim_timeout_secs as f64)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let count = claimed.len();
+    for row in claimed {
+        let mut msg = OutgoingMessage::new(row.chat_id, row.sender_id, MessageBody::Text { content: row.content, entities: row.entities });
+        msg.reply_to_message_id = row.reply_to_message_id;
+        msg.thread_root_id = row.thread_root_id;
+        msg.attachment_ids = row.attachment_ids;
+        msg.expires_in = row.expires_in;
+        msg.disable_link_preview = row.disable_link_preview;
+        msg.client_message_id = Some(format!("scheduled:{}", row.id));
+        msg.clears_draft = false;
+        match delivery::deliver(state, msg).await {
+            Ok(sent) => {
+                info!(scheduled_id = %row.id, message_id = %sent.message.id, "scheduled message sent");
+                sqlx::query("DELETE FROM scheduled_messages WHERE id = $1")
+                    .bind(row.id)
+                    .execute(&state.pool)
+                    .await?;
+            }
+            Err(DeliveryError::Database(e)) => {
+                error!(scheduled_id = %row.id, ?e, "scheduled send error, will retry");
+                sqlx::query("UPDATE scheduled_messages SET status = 'pending', claimed_at = NULL WHERE id = $1")
+                    .bind(row.id)
+                    .execute(&state.pool)
+                    .await?;
+            }
+            // no longer allowed (left, muted, ...): keep it for the sender to edit or cancel
+            Err(e) => {
+                warn!(scheduled_id = %row.id, error = %e, "scheduled message rejected");
+                sqlx::query("UPDATE scheduled_messages SET status = 'failed', claimed_at = NULL, error = $2 WHERE id = $1")
+                    .bind(row.id)
+                    .bind(e.to_string())
+                    .execute(&state.pool)
+                    .await?;
+            }
+        }
+    }
+    Ok(count)
+}
@@ -1,12 +1,16 @@
 use crate::config::AppConfig;
+use crate::fanout::FanoutBus;
 use crate::gif::GifProvider;
+use crate::link_preview::PreviewFetches;
+use crate::typing::TypingLeases;
 use crate::ws::ServerWsMsg;
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
 use sqlx::PgPool;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PresenceStatus {
@@ -14,10 +18,37 @@ pub struct PresenceStatus {
     pub last_seen_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+/// Outbound side of one websocket session: a bounded queue plus a signal the
+/// session watches to drop itself when it falls too far behind.
+#[derive(Clone)]
+pub struct SessionHandle {
+    tx: mpsc::Sender<ServerWsMsg>,
+    overflow: Arc<Notify>,
+}
+
+impl SessionHandle {
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<ServerWsMsg>, Arc<Notify>) {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        let overflow = Arc::new(Notify::new());
+        (Self { tx, overflow: overflow.clone() }, rx, overflow)
+    }
+
+    /// Queue `msg` without waiting; a full queue tells the session to disconnect
+    /// rather than buffer without bound.
+    pub fn send(&self, msg: ServerWsMsg) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(msg) {
+            self.overflow.notify_one();
+        }
+    }
+}
+
+/// Live websocket sessions of one user, keyed by a per-connection session id.
+pub type UserSessions = HashMap<Uuid, SessionHandle>;
+
 #[derive(Clone)]
 pub struct AppState {
     pub pool: PgPool,
-    pub clients: Arc<DashMap<Uuid, mpsc::UnboundedSender<ServerWsMsg>>>,
+    pub clients: Arc<DashMap<Uuid, UserSessions>>, // user_id -> every live session of that user
     pub config: Arc<AppConfig>,
     pub jwt_secret: Arc<String>,
     pub storage_dir: Arc<std::path::PathBuf>,
@@ -25,7 +56,8 @@ pub struct AppState {
     pub gif_provider: Option<Arc<GifProvider>>,
     pub download_token_ttl: u64,
     pub admin_token: Arc<String>,
-    pub typing: Arc<DashMap<(Uuid, Uuid), tokio::time::Instant>>, // (chat_id, user_id) -> typing start time
+    pub typing: Arc<dyn TypingLeases>,
     pub presence: Arc<DashMap<Uuid, PresenceStatus>>,
-    pub sequence_counter: Arc<std::sync::atomic::AtomicU64>,
+    pub fanout: Arc<dyn FanoutBus>,
+    pub link_previews: Arc<PreviewFetches>,
 }
@@ -0,0 +1,339 @@
+use crate::config::FanoutConfig;
+use crate::events;
+use crate::state::AppState;
+use crate::ws::ServerWsMsg;
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::time::Instant;
+use tracing::error;
+
+/// `chat_action` type sent when a user stops an action, explicitly or by timeout.
+pub const TYPING_STOPPED: &str = "typing_stopped";
+
+/// What a user is currently doing in a chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TypingAction {
+    Typing,
+    UploadPhoto,
+    RecordVoice,
+    ChooseSticker,
+}
+
+impl TypingAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TypingAction::Typing => "typing",
+            TypingAction::UploadPhoto => "upload_photo",
+            TypingAction::RecordVoice => "record_voice",
+            TypingAction::ChooseSticker => "choose_sticker",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "typing" => Some(TypingAction::Typing),
+            "upload_photo" => Some(TypingAction::UploadPhoto),
+            "record_voice" => Some(TypingAction::RecordVoice),
+            "choose_sticker" => Some(TypingAction::ChooseSticker),
+            _ => None,
+        }
+    }
+}
+
+/// Who is doing what in which chat. A lease lasts `websocket.typing_timeout_secs` unless
+/// refreshed; whoever releases or claims it first reports the stop, so each stop goes out once.
+pub trait TypingLeases: Send + Sync {
+    /// Take out or refresh the lease of `user_id` in `chat_id`.
+    fn start(&self, chat_id: Uuid, user_id: Uuid, action: TypingAction) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// Release the lease; returns its action unless someone else already released or claimed it.
+    fn stop(&self, chat_id: Uuid, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<TypingAction>>>;
+    /// Claim the leases that ran out, as `(chat_id, user_id, action)`.
+    fn expired(&self) -> BoxFuture<'_, anyhow::Result<Vec<(Uuid, Uuid, TypingAction)>>>;
+}
+
+/// Build the lease store matching `fanout.backend`, so typing is shared across the same
+/// nodes that share sessions.
+pub fn from_config(
+    config: &FanoutConfig,
+    timeout_secs: u64,
+    redis: Option<&redis::Client>,
+) -> anyhow::Result<Arc<dyn TypingLeases>> {
+    let timeout = Duration::from_secs(timeout_secs.max(1));
+    match config.backend.as_str() {
+        "redis" => {
+            let client = redis
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("fanout backend redis requires redis.url"))?;
+            Ok(Arc::new(RedisTyping::new(client, &config.channel, timeout)))
+        }
+        _ => Ok(Arc::new(LocalTyping::new(timeout))),
+    }
+}
+
+/// Lease held by this process.
+#[derive(Debug, Clone, Copy)]
+struct TypingStatus {
+    action: TypingAction,
+    updated_at: Instant,
+}
+
+/// Single-node leases kept in memory.
+pub struct LocalTyping {
+    timeout: Duration,
+    leases: DashMap<(Uuid, Uuid), TypingStatus>,
+}
+
+impl LocalTyping {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout, leases: DashMap::new() }
+    }
+}
+
+impl TypingLeases for LocalTyping {
+    fn start(&self, chat_id: Uuid, user_id: Uuid, action: TypingAction) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.leases.insert((chat_id, user_id), TypingStatus { action, updated_at: Instant::now() });
+        Box::pin(async { Ok(()) })
+    }
+
+    fn stop(&self, chat_id: Uuid, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<TypingAction>>> {
+        let action = self.leases.remove(&(chat_id, user_id)).map(|(_, status)| status.action);
+        Box::pin(async move { Ok(action) })
+    }
+
+    fn expired(&self) -> BoxFuture<'_, anyhow::Result<Vec<(Uuid, Uuid, TypingAction)>>> {
+        let keys: Vec<(Uuid, Uuid)> = self
+            .leases
+            .iter()
+            .filter(|e| e.value().updated_at.elapsed() >= self.timeout)
+            .map(|e| *e.key())
+            .collect();
+        let expired = keys
+            .into_iter()
+            .filter_map(|key| {
+                // a start that raced the scan refreshed the entry and keeps it alive
+                let (_, status) = self.leases.remove_if(&key, |_, s| s.updated_at.elapsed() >= self.timeout)?;
+                Some((key.0, key.1, status.action))
+            })
+            .collect();
+        Box::pin(async move { Ok(expired) })
+    }
+}
+
+/// Cluster-wide leases in Redis. Each lease is `{channel}:typing:{chat_id}:{user_id}`, set
+/// with the timeout as its TTL; `{channel}:typing` maps every lease not yet reported as
+/// stopped to its action, so a lease that expired can still be claimed once.
+pub struct RedisTyping {
+    client: redis::Client,
+    registry: String,
+    timeout: Duration,
+    conn: OnceCell<redis::aio::MultiplexedConnection>,
+}
+
+/// Drop a lease and its registry entry; returns the action if this call removed the entry.
+///
+/// KEYS[1]: the lease, KEYS[2]: the registry. ARGV[1]: the registry field.
+const STOP_SCRIPT: &str = r#"
+local action = redis.call('HGET', KEYS[2], ARGV[1])
+redis.call('DEL', KEYS[1])
+if redis.call('HDEL', KEYS[2], ARGV[1]) == 1 then
+    return action
+end
+return false
+"#;
+
+/// Remove and return, as field/action pairs, the registry entries whose lease is gone.
+///
+/// KEYS[1]: the registry. ARGV[1]: the lease key prefix.
+const EXPIRED_SCRIPT: &str = r#"
+local expired = {}
+local entries = redis.call('HGETALL', KEYS[1])
+for i = 1, #entries, 2 do
+    if redis.call('EXISTS', ARGV[1] .. entries[i]) == 0 then
+        redis.call('HDEL', KEYS[1], entries[i])
+        table.insert(expired, entries[i])
+        table.insert(expired, entries[i + 1])
+    end
+end
+return expired
+"#;
+
+impl RedisTyping {
+    pub fn new(client: redis::Client, channel: &str, timeout: Duration) -> Self {
+        Self { client, registry: format!("{}:typing", channel), timeout, conn: OnceCell::new() }
+    }
+
+    async fn conn(&self) -> anyhow::Result<redis::aio::MultiplexedConnection> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| self.client.get_multiplexed_async_connection())
+            .await?;
+        Ok(conn.clone())
+    }
+
+    fn field(chat_id: Uuid, user_id: Uuid) -> String {
+        format!("{}:{}", chat_id, user_id)
+    }
+
+    fn lease_key(&self, field: &str) -> String {
+        format!("{}:{}", self.registry, field)
+    }
+}
+
+impl TypingLeases for RedisTyping {
+    fn start(&self, chat_id: Uuid, user_id: Uuid, action: TypingAction) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let field = Self::field(chat_id, user_id);
+            let mut conn = self.conn().await?;
+            redis::pipe()
+                .atomic()
+                .set_ex(self.lease_key(&field), action.as_str(), self.timeout.as_secs())
+                .ignore()
+                .hset(&self.registry, &field, action.as_str())
+                .ignore()
+                .query_async::<_, ()>(&mut conn)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, chat_id: Uuid, user_id: Uuid) -> BoxFuture<'_, anyhow::Result<Option<TypingAction>>> {
+        Box::pin(async move {
+            let field = Self::field(chat_id, user_id);
+            let mut conn = self.conn().await?;
+            let action: Option<String> = redis::Script::new(STOP_SCRIPT)
+                .key(self.lease_key(&field))
+                .key(&self.registry)
+                .arg(&field)
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(action.as_deref().and_then(TypingAction::parse))
+        })
+    }
+
+    fn expired(&self) -> BoxFuture<'_, anyhow::Result<Vec<(Uuid, Uuid, TypingAction)>>> {
+        Box::pin(async move {
+            let mut conn = self.conn().await?;
+            let entries: Vec<String> = redis::Script::new(EXPIRED_SCRIPT)
+                .key(&self.registry)
+                .arg(format!("{}:", self.registry))
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(entries
+                .chunks_exact(2)
+                .filter_map(|pair| {
+                    let (chat_id, user_id) = pair[0].split_once(':')?;
+                    Some((chat_id.parse().ok()?, user_id.parse().ok()?, TypingAction::parse(&pair[1])?))
+                })
+                .collect())
+        })
+    }
+}
+
+/// Typing actions are transient `chat_action`s: they are never logged or replayed.
+pub fn is_typing_action(action_type: &str) -> bool {
+    matches!(
+        action_type,
+        "typing" | "upload_photo" | "record_voice" | "choose_sticker" | TYPING_STOPPED
+    )
+}
+
+/// Record that `user_id` is doing `action` in `chat_id` and tell the other participants.
+/// Repeating the call refreshes the timeout. Non-members and muted users are ignored.
+pub async fn start(state: &AppState, chat_id: Uuid, user_id: Uuid, action: TypingAction) -> anyhow::Result<()> {
+    if !can_type(state, chat_id, user_id).await? {
+        return Ok(());
+    }
+    state.typing.start(chat_id, user_id, action).await?;
+    broadcast(state, chat_id, user_id, action.as_str(), action).await
+}
+
+/// Clear the user's action in the chat, broadcasting `typing_stopped` if one was active.
+pub async fn stop(state: &AppState, chat_id: Uuid, user_id: Uuid) -> anyhow::Result<()> {
+    if let Some(action) = state.typing.stop(chat_id, user_id).await? {
+        broadcast(state, chat_id, user_id, TYPING_STOPPED, action).await?;
+    }
+    Ok(())
+}
+
+/// Report actions that were not refreshed within `websocket.typing_timeout_secs` as stopped.
+pub fn spawn_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            tick.tick().await;
+            let expired = match state.typing.expired().await {
+                Ok(expired) => expired,
+                Err(e) => {
+                    error!(?e, "typing expiry error");
+                    continue;
+                }
+            };
+            for (chat_id, user_id, action) in expired {
+                if let Err(e) = broadcast(&state, chat_id, user_id, TYPING_STOPPED, action).await {
+                    error!(%chat_id, %user_id, ?e, "typing expiry broadcast error");
+                }
+            }
+        }
+    });
+}
+
+async fn can_type(state: &AppState, chat_id: Uuid, user_id: Uuid) -> anyhow::Result<bool> {
+    let allowed = sqlx::query_scalar::<_, bool>(
+        r#"SELECT NOT EXISTS (
+               SELECT 1 FROM chat_mutes m
+               WHERE m.chat_id = cp.chat_id AND m.user_id = cp.user_id AND m.muted_until > now()
+           )
+           FROM chat_participants cp WHERE cp.chat_id = $1 AND cp.user_id = $2"#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(&state.pool)
+    .await?;
+    Ok(allowed.unwrap_or(false))
+}
+
+/// Send a typing `chat_action` to the other participants, skipping anyone who blocked the user.
+async fn broadcast(
+    state: &AppState,
+    chat_id: Uuid,
+    user_id: Uuid,
+    action_type: &str,
+    action: TypingAction,
+) -> anyhow::Result<()> {
+    let recipients = sqlx::query_scalar::<_, Uuid>(
+        r#"SELECT cp.user_id FROM chat_participants cp
+           WHERE cp.chat_id = $1 AND cp.user_id != $2
+           AND NOT EXISTS (
+               SELECT 1 FROM contacts c
+               WHERE c.user_id = cp.user_id AND c.contact_user_id = $2 AND c.status = 'blocked'
+           )"#,
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_all(&state.pool)
+    .await?;
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&state.pool)
+        .await?;
+    let msg = ServerWsMsg::ChatAction {
+        sequence_id: 0,
+        chat_id,
+        action_type: action_type.to_string(),
+        data: serde_json::json!({
+            "user": { "id": user_id, "username": username },
+            "action": action,
+        }),
+    };
+    events::publish(state, &recipients, msg).await?;
+    Ok(())
+}
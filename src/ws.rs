@@ -1,37 +1,88 @@
 use crate::auth::{decode_token, extract_bearer};
-use crate::models::MessageRow;
-use crate::state::{AppState, PresenceStatus};
+use crate::codec::{WsCodec, WsFrame};
+use crate::events;
+use crate::delivery::{self, DeliveryError, GifPayload, MessageBody, OutgoingMessage};
+use crate::models::{DraftDto, GifSendReq, LinkPreviewDto, MessageDto, MessageEntity, MessageRow, PollDto, PollSendReq, Reaction, ReactionCountDto, ScheduledMessageDto};
+use crate::rpc::{self, RpcDispatcher};
+use crate::presence;
+use crate::scheduled;
+use crate::state::{AppState, SessionHandle};
+use crate::typing::{self, TypingAction};
+use actix_web::http::header;
 use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
-use actix_ws::{Message, MessageStream, Session};
+use actix_ws::{CloseCode, CloseReason, Message, MessageStream, Session};
+use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
 use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Uuid;
-use std::sync::atomic::Ordering;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientWsMsg {
     #[serde(rename = "send_message")]
-    SendMessage { chat_id: Uuid, content: String, request_id: Option<Uuid> },
+    SendMessage {
+        chat_id: Uuid,
+        content: Option<String>,
+        /// Formatting over `content`, as in `POST /chats/{chat_id}/messages`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        entities: Vec<MessageEntity>,
+        reply_to_message_id: Option<Uuid>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        thread_root_id: Option<Uuid>,
+        #[serde(default)]
+        attachment_ids: Vec<Uuid>,
+        sticker_id: Option<Uuid>,
+        gif: Option<Box<GifSendReq>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        poll: Option<Box<PollSendReq>>,
+        client_message_id: Option<String>,
+        /// Self-destruct timer in seconds, as in `POST /chats/{chat_id}/messages`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_in: Option<i32>,
+        /// Send without generating a preview for links in `content`.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        disable_link_preview: bool,
+        /// Store and send at this time instead of now; the ack then carries `scheduled`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scheduled_at: Option<DateTime<Utc>>,
+        request_id: Option<Uuid>,
+    },
     #[serde(rename = "start_typing")]
-    StartTyping { chat_id: Uuid, request_id: Option<Uuid> },
+    StartTyping { chat_id: Uuid, action: Option<TypingAction>, request_id: Option<Uuid> },
+    #[serde(rename = "stop_typing")]
+    StopTyping { chat_id: Uuid, request_id: Option<Uuid> },
     #[serde(rename = "mark_as_read")]
     MarkAsRead { chat_id: Uuid, last_read_message_id: Uuid, request_id: Option<Uuid> },
     #[serde(rename = "sync")]
-    Sync { last_sequence_id: u64 },
+    Sync { last_sequence_id: Option<u64>, limit: Option<u32> },
+    #[serde(rename = "ack_events")]
+    AckEvents { sequence_id: u64 },
+    /// Call a REST endpoint over the socket, e.g. `method: "GET /v1/api/chats"`.
+    #[serde(rename = "rpc")]
+    Rpc { method: String, params: Option<serde_json::Value>, request_id: Uuid },
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum ServerWsMsg {
     #[serde(rename = "new_message")]
-    NewMessage { sequence_id: u64, message: MessageRow },
+    NewMessage { sequence_id: u64, message: Box<MessageDto> },
     #[serde(rename = "error")]
-    Error { sequence_id: u64, code: String, message: String },
-    #[serde(rename = "typing_indicator")]
-    TypingIndicator { sequence_id: u64, chat_id: Uuid, user: UserInfo },
+    Error {
+        sequence_id: u64,
+        code: String,
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<Uuid>,
+    },
     #[serde(rename = "message_edited")]
     MessageEdited { sequence_id: u64, message: MessageRow },
     #[serde(rename = "message_deleted")]
@@ -39,28 +90,117 @@ pub enum ServerWsMsg {
     #[serde(rename = "messages_read")]
     MessagesRead { sequence_id: u64, chat_id: Uuid, reader_user_id: Uuid, last_read_message_id: Uuid, read_count: Option<i32>, is_read_by_peer: Option<bool> },
     #[serde(rename = "presence_update")]
-    PresenceUpdate { sequence_id: u64, user_id: Uuid, status: String, last_seen_at: Option<String> },
+    PresenceUpdate {
+        sequence_id: u64,
+        user_id: Uuid,
+        status: String,
+        last_seen_at: Option<String>,
+        /// Coarse "recently" / "within_week" / ... for peers who may not see the exact value
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_seen: Option<String>,
+    },
     #[serde(rename = "chat_action")]
     ChatAction { sequence_id: u64, chat_id: Uuid, action_type: String, data: serde_json::Value },
+    #[serde(rename = "reaction_updated")]
+    ReactionUpdated {
+        sequence_id: u64,
+        chat_id: Uuid,
+        message_id: Uuid,
+        /// Who added or removed `reaction`
+        user_id: Uuid,
+        reaction: Reaction,
+        added: bool,
+        /// The message's reaction counts after the change
+        reactions: Vec<ReactionCountDto>,
+    },
+    /// A vote, retraction or closure changed the poll shown by `message_id`.
+    #[serde(rename = "poll_updated")]
+    PollUpdated { sequence_id: u64, chat_id: Uuid, message_id: Uuid, poll: Box<PollDto> },
+    /// The preview of a link in `message_id` is ready; `None` after an edit removed the link.
+    #[serde(rename = "link_preview_updated")]
+    LinkPreviewUpdated {
+        sequence_id: u64,
+        chat_id: Uuid,
+        message_id: Uuid,
+        #[serde(default)]
+        link_preview: Option<Box<LinkPreviewDto>>,
+    },
+    /// The user's draft in `chat_id` changed on one of their devices; `None` once it is cleared.
+    #[serde(rename = "draft_updated")]
+    DraftUpdated {
+        sequence_id: u64,
+        chat_id: Uuid,
+        #[serde(default)]
+        draft: Option<Box<DraftDto>>,
+    },
     #[serde(rename = "sync_response")]
-    SyncResponse { sequence_id: u64, events: Vec<ServerWsMsg> },
+    SyncResponse { sequence_id: u64, events: Vec<ServerWsMsg>, has_more: bool, resync_required: bool },
     #[serde(rename = "ack")]
-    Ack { sequence_id: u64, request_id: Uuid },
+    Ack {
+        sequence_id: u64,
+        request_id: Uuid,
+        /// Set when acknowledging a `send_message`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<SentMessageAck>,
+        /// Set when acknowledging a `send_message` with `scheduled_at`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scheduled: Option<ScheduledMessageDto>,
+    },
+    #[serde(rename = "rpc_result")]
+    RpcResult { sequence_id: u64, request_id: Uuid, status: u16, result: serde_json::Value },
+    #[serde(rename = "rpc_error")]
+    RpcError { sequence_id: u64, request_id: Uuid, status: u16, code: String, message: String },
 }
 
+/// What a `send_message` ack tells the sender about the stored message.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct UserInfo {
-    pub id: Uuid,
-    pub username: String,
+pub struct SentMessageAck {
+    pub message_id: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_message_id: Option<String>,
+    /// Sequence id of the matching `new_message` in the sender's stream; absent when the
+    /// send repeated a `client_message_id` that was already stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_sequence_id: Option<u64>,
 }
 
-fn next_sequence_id(state: &AppState) -> u64 {
-    state.sequence_counter.fetch_add(1, Ordering::SeqCst)
-}
+impl ServerWsMsg {
+    /// Durable events are written to the per-user event log and replayed on `sync`;
+    /// the rest (typing, presence, acks, errors) only make sense to a live connection.
+    pub fn is_durable(&self) -> bool {
+        match self {
+            ServerWsMsg::ChatAction { action_type, .. } => !typing::is_typing_action(action_type),
+            ServerWsMsg::NewMessage { .. }
+            | ServerWsMsg::MessageEdited { .. }
+            | ServerWsMsg::MessageDeleted { .. }
+            | ServerWsMsg::MessagesRead { .. }
+            | ServerWsMsg::ReactionUpdated { .. }
+            | ServerWsMsg::PollUpdated { .. }
+            | ServerWsMsg::LinkPreviewUpdated { .. }
+            | ServerWsMsg::DraftUpdated { .. } => true,
+            _ => false,
+        }
+    }
 
-#[derive(sqlx::FromRow)]
-struct UserIdRow {
-    user_id: Uuid,
+    pub fn set_sequence_id(&mut self, seq: u64) {
+        match self {
+            ServerWsMsg::NewMessage { sequence_id, .. }
+            | ServerWsMsg::Error { sequence_id, .. }
+            | ServerWsMsg::MessageEdited { sequence_id, .. }
+            | ServerWsMsg::MessageDeleted { sequence_id, .. }
+            | ServerWsMsg::MessagesRead { sequence_id, .. }
+            | ServerWsMsg::PresenceUpdate { sequence_id, .. }
+            | ServerWsMsg::ChatAction { sequence_id, .. }
+            | ServerWsMsg::ReactionUpdated { sequence_id, .. }
+            | ServerWsMsg::PollUpdated { sequence_id, .. }
+            | ServerWsMsg::LinkPreviewUpdated { sequence_id, .. }
+            | ServerWsMsg::DraftUpdated { sequence_id, .. }
+            | ServerWsMsg::SyncResponse { sequence_id, .. }
+            | ServerWsMsg::Ack { sequence_id, .. }
+            | ServerWsMsg::RpcResult { sequence_id, .. }
+            | ServerWsMsg::RpcError { sequence_id, .. } => *sequence_id = seq,
+        }
+    }
 }
 
 #[get("/ws")]
@@ -69,240 +209,300 @@ pub async fn ws_route(
     req: HttpRequest,
     body: web::Payload,
 ) -> actix_web::Result<impl Responder> {
-    let token = req
+    let query = req
         .uri()
         .query()
-        .and_then(|q| {
-            web::Query::<std::collections::HashMap<String, String>>::from_query(q)
-                .ok()?
-                .get("token")
-                .cloned()
-        })
-        .or_else(|| extract_bearer(req.headers()).ok());
+        .and_then(|q| web::Query::<HashMap<String, String>>::from_query(q).ok())
+        .map(|q| q.into_inner())
+        .unwrap_or_default();
+    let token = query
+        .get("token")
+        .cloned()
+        .or_else(|| extract_bearer(req.headers()));
     let Some(token) = token else {
         return Ok(HttpResponse::Unauthorized().finish());
     };
     let user_id = decode_token(&token, &state.jwt_secret)
         .map_err(|_| actix_web::error::ErrorUnauthorized("invalid token"))?;
+    let device_id = query.get("device_id").cloned();
+    if device_id.as_ref().is_some_and(|d| d.is_empty() || d.len() > 128) {
+        return Ok(HttpResponse::BadRequest().body("invalid device_id"));
+    }
+
+    let offered = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok());
+    let codec = WsCodec::negotiate(offered);
 
-    let (res, session, stream) = actix_ws::handle(&req, body)?;
+    let (mut res, session, stream) = actix_ws::handle(&req, body)?;
+    if let Some(codec) = codec {
+        res.headers_mut().insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            header::HeaderValue::from_static(codec.protocol()),
+        );
+    }
     let state_cloned = state.get_ref().clone();
-    actix_web::rt::spawn(ws_session(state_cloned, user_id, session, stream));
+    actix_web::rt::spawn(ws_session(state_cloned, user_id, device_id, codec.unwrap_or_default(), session, stream));
     Ok(res)
 }
 
+/// Per-connection state: which session this is and how far it has acknowledged the event log.
+struct SessionCtx {
+    session_id: Uuid,
+    /// Stable client-chosen device id; when set, the ack cursor survives reconnects.
+    device_id: Option<String>,
+    acked_sequence_id: u64,
+    /// Built on the session's first `rpc` call.
+    rpc: Option<Rc<RpcDispatcher>>,
+    /// One permit per `rpc` call allowed to run at once.
+    rpc_slots: Arc<Semaphore>,
+}
+
+impl SessionCtx {
+    async fn open(state: &AppState, user_id: Uuid, device_id: Option<String>) -> Self {
+        let mut acked_sequence_id = 0;
+        if let Some(device_id) = &device_id {
+            acked_sequence_id = sqlx::query_scalar::<_, i64>(
+                "SELECT last_acked_sequence_id FROM user_session_cursors WHERE user_id = $1 AND device_id = $2",
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .fetch_optional(&state.pool)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or(0) as u64;
+        }
+        Self {
+            session_id: Uuid::new_v4(),
+            device_id,
+            acked_sequence_id,
+            rpc: None,
+            rpc_slots: Arc::new(Semaphore::new(state.config.websocket.max_inflight_rpc.max(1))),
+        }
+    }
+
+    async fn rpc(&mut self, state: &AppState) -> anyhow::Result<Rc<RpcDispatcher>> {
+        if self.rpc.is_none() {
+            self.rpc = Some(Rc::new(RpcDispatcher::new(state).await?));
+        }
+        Ok(self.rpc.clone().expect("rpc dispatcher initialized above"))
+    }
+
+    async fn ack(&mut self, state: &AppState, user_id: Uuid, sequence_id: u64) -> anyhow::Result<()> {
+        if sequence_id <= self.acked_sequence_id {
+            return Ok(());
+        }
+        self.acked_sequence_id = sequence_id;
+        if let Some(device_id) = &self.device_id {
+            sqlx::query(
+                r#"INSERT INTO user_session_cursors (user_id, device_id, last_acked_sequence_id)
+                   VALUES ($1, $2, $3)
+                   ON CONFLICT (user_id, device_id) DO UPDATE
+                   SET last_acked_sequence_id = GREATEST(user_session_cursors.last_acked_sequence_id, EXCLUDED.last_acked_sequence_id),
+                       updated_at = now()"#,
+            )
+            .bind(user_id)
+            .bind(device_id)
+            .bind(sequence_id as i64)
+            .execute(&state.pool)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Registers a session and reports whether it is the user's first live one on this node.
+fn register_session(state: &AppState, user_id: Uuid, session_id: Uuid, handle: SessionHandle) -> bool {
+    let mut sessions = state.clients.entry(user_id).or_default();
+    sessions.insert(session_id, handle);
+    sessions.len() == 1
+}
+
+/// Removes a session and reports whether it was the user's last live one on this node.
+fn unregister_session(state: &AppState, user_id: Uuid, session_id: Uuid) -> bool {
+    match state.clients.entry(user_id) {
+        Entry::Occupied(mut sessions) => {
+            sessions.get_mut().remove(&session_id);
+            if sessions.get().is_empty() {
+                sessions.remove();
+                true
+            } else {
+                false
+            }
+        }
+        Entry::Vacant(_) => true,
+    }
+}
+
 async fn ws_session(
     state: AppState,
     user_id: Uuid,
+    device_id: Option<String>,
+    codec: WsCodec,
     mut session: Session,
     mut stream: MessageStream,
 ) {
-    let (tx, mut rx) = mpsc::unbounded_channel::<ServerWsMsg>();
-    state.clients.insert(user_id, tx.clone());
-    info!(%user_id, "ws connected");
+    let mut ctx = SessionCtx::open(&state, user_id, device_id).await;
+    let session_id = ctx.session_id;
+    let (handle, mut rx, overflow) = SessionHandle::new(state.config.websocket.outbound_queue_size);
+    let first_local = register_session(&state, user_id, session_id, handle);
+    info!(%user_id, %session_id, "ws connected");
 
-    // Set presence online
-    state.presence.insert(user_id, PresenceStatus {
-        status: "online".to_string(),
-        last_seen_at: None,
+    // Presence is cluster-wide: only the user's first session on any node flips it online
+    let first_session = state.fanout.session_opened(user_id).await.unwrap_or_else(|e| {
+        error!(%user_id, ?e, "session count error");
+        first_local
     });
 
-    // Broadcast presence update to users with common chats
-    let common_users = get_common_chat_users(&state, user_id).await;
-    let presence_msg = ServerWsMsg::PresenceUpdate {
-        sequence_id: next_sequence_id(&state),
-        user_id: user_id.clone(),
-        status: "online".to_string(),
-        last_seen_at: None,
-    };
-    for uid in common_users {
-        if let Some(tx) = state.clients.get(&uid) {
-            let _ = tx.send(presence_msg.clone());
+    if first_session {
+        if let Err(e) = presence::set_online(&state, user_id).await {
+            error!(%user_id, ?e, "presence online error");
         }
     }
 
-    let mut closed = false;
-    while !closed {
+    let ws_config = &state.config.websocket;
+    let idle_timeout = Duration::from_secs(ws_config.idle_timeout_secs.max(1));
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(ws_config.ping_interval_secs.max(1)));
+    let mut last_heard = Instant::now();
+    let mut close_reason = None;
+    loop {
         tokio::select! {
-            Some(msg) = stream.next() => {
+            msg = stream.next() => {
+                let Some(msg) = msg else { break };
+                last_heard = Instant::now();
                 match msg {
+                    // text frames are always JSON; binary frames use the negotiated codec
                     Ok(Message::Text(txt)) => {
-                        if let Err(e) = handle_ws_text(&state, user_id, &txt).await { error!(%user_id, ?e, "handle ws text error"); }
+                        if let Err(e) = handle_ws_frame(&state, user_id, &mut ctx, WsCodec::Json, txt.as_bytes()).await { error!(%user_id, ?e, "handle ws text error"); }
                     }
-                    Ok(Message::Close(_)) => { closed = true; }
-                    Ok(Message::Ping(data)) => { let _ = session.pong(&data); }
+                    Ok(Message::Binary(bin)) => {
+                        if let Err(e) = handle_ws_frame(&state, user_id, &mut ctx, codec, &bin).await { error!(%user_id, ?e, "handle ws binary error"); }
+                    }
+                    Ok(Message::Close(_)) => break,
+                    Ok(Message::Ping(data)) => { let _ = session.pong(&data).await; }
                     Ok(Message::Pong(_)) => {}
-                    Ok(Message::Binary(_)) => {}
                     Ok(Message::Continuation(_)) | Ok(Message::Nop) => {}
-                    Err(e) => { error!(?e, "ws error"); closed = true; }
+                    Err(e) => { error!(?e, "ws error"); break; }
                 }
             }
             Some(server_msg) = rx.recv() => {
-                let txt = serde_json::to_string(&server_msg).unwrap_or_else(|_| "{\"type\":\"error\",\"message\":\"serialization\"}".into());
-                if session.text(txt).await.is_err() { closed = true; }
+                let frame = match codec.encode(&server_msg) {
+                    Ok(frame) => frame,
+                    Err(e) => { error!(%user_id, ?e, "ws encode error"); continue; }
+                };
+                let write = match frame {
+                    WsFrame::Text(txt) => tokio::time::timeout(idle_timeout, session.text(txt)).await,
+                    WsFrame::Binary(bin) => tokio::time::timeout(idle_timeout, session.binary(bin)).await,
+                };
+                // a peer that stopped reading stalls the write; give up on it like an idle one
+                if !matches!(write, Ok(Ok(()))) { break; }
+            }
+            _ = overflow.notified() => {
+                warn!(%user_id, %session_id, "ws outbound queue overflow, disconnecting");
+                // everything dropped here is in the event log; the client catches up with sync
+                close_reason = Some(CloseReason { code: CloseCode::Again, description: Some("resync_required".into()) });
+                break;
+            }
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() >= idle_timeout {
+                    info!(%user_id, %session_id, "ws idle timeout");
+                    close_reason = Some(CloseReason { code: CloseCode::Away, description: Some("idle_timeout".into()) });
+                    break;
+                }
+                if session.ping(b"").await.is_err() { break; }
             }
-            else => { closed = true; }
         }
     }
 
-    state.clients.remove(&user_id);
-
-    // Set presence offline
-    let now = chrono::Utc::now();
-    state.presence.insert(user_id, PresenceStatus {
-        status: "offline".to_string(),
-        last_seen_at: Some(now),
+    let last_local = unregister_session(&state, user_id, session_id);
+    let _ = tokio::time::timeout(Duration::from_secs(1), session.close(close_reason)).await;
+    let last_session = state.fanout.session_closed(user_id).await.unwrap_or_else(|e| {
+        error!(%user_id, ?e, "session count error");
+        last_local
     });
 
-    // Broadcast presence update
-    let common_users = get_common_chat_users(&state, user_id).await;
-    let presence_msg = ServerWsMsg::PresenceUpdate {
-        sequence_id: next_sequence_id(&state),
-        user_id,
-        status: "offline".to_string(),
-        last_seen_at: Some(now.to_rfc3339()),
-    };
-    for uid in common_users {
-        if let Some(tx) = state.clients.get(&uid) {
-            let _ = tx.send(presence_msg.clone());
+    if last_session {
+        if let Err(e) = presence::set_offline(&state, user_id).await {
+            error!(%user_id, ?e, "presence offline error");
         }
     }
 
-    info!(%user_id, "ws disconnected");
+    info!(%user_id, %session_id, "ws disconnected");
 }
 
-async fn handle_ws_text(state: &AppState, user_id: Uuid, txt: &str) -> anyhow::Result<()> {
-    let msg: ClientWsMsg = serde_json::from_str(txt)?;
+async fn handle_ws_frame(state: &AppState, user_id: Uuid, ctx: &mut SessionCtx, codec: WsCodec, bytes: &[u8]) -> anyhow::Result<()> {
+    let msg: ClientWsMsg = codec.decode(bytes)?;
     match msg {
-        ClientWsMsg::SendMessage { chat_id, content, request_id } => {
-            let is_member =
-                sqlx::query("SELECT 1 FROM chat_participants WHERE chat_id = $1 AND user_id = $2")
-                    .bind(chat_id)
-                    .bind(user_id)
-                    .fetch_optional(&state.pool)
-                    .await?;
-            if is_member.is_none() {
-                send_ws_err(state, user_id, "forbidden", "not a member");
-                return Ok(());
-            }
-
-            // check chat type for permissions
-            #[derive(sqlx::FromRow)]
-            struct Meta {
-                chat_type: String,
-                owner_id: Option<Uuid>,
-            }
-            let meta =
-                sqlx::query_as::<_, Meta>("SELECT chat_type, owner_id FROM chats WHERE id = $1")
-                    .bind(chat_id)
-                    .fetch_optional(&state.pool)
-                    .await?
-                    .ok_or_else(|| anyhow::anyhow!("chat not found"))?;
-            if meta.chat_type == "channel" && meta.owner_id != Some(user_id) {
-                send_ws_err(state, user_id, "forbidden", "only owner can send in channel");
+        ClientWsMsg::SendMessage {
+            chat_id,
+            content,
+            entities,
+            reply_to_message_id,
+            thread_root_id,
+            attachment_ids,
+            sticker_id,
+            gif,
+            poll,
+            client_message_id,
+            expires_in,
+            disable_link_preview,
+            scheduled_at,
+            request_id,
+        } => {
+            let body = match message_body(content, entities, sticker_id, gif, poll, &attachment_ids) {
+                Ok(body) => body,
+                Err(e) => return send_ws_err(state, user_id, ctx.session_id, request_id, e.code(), &e.to_string()).await,
+            };
+            let mut msg = OutgoingMessage::new(chat_id, user_id, body);
+            msg.reply_to_message_id = reply_to_message_id;
+            msg.thread_root_id = thread_root_id;
+            msg.attachment_ids = attachment_ids;
+            msg.expires_in = expires_in;
+            msg.disable_link_preview = disable_link_preview;
+            if let Some(at) = scheduled_at {
+                match scheduled::schedule(state, msg, at).await {
+                    Ok(scheduled) => {
+                        if let Some(rid) = request_id {
+                            let msg = ServerWsMsg::Ack { sequence_id: 0, request_id: rid, message: None, scheduled: Some(scheduled) };
+                            events::reply(state, user_id, ctx.session_id, msg).await?;
+                        }
+                    }
+                    Err(e) => {
+                        send_ws_err(state, user_id, ctx.session_id, request_id, e.code(), &e.to_string()).await?;
+                    }
+                }
                 return Ok(());
             }
-
-            let mid = Uuid::new_v4();
-            // check mute
-            #[derive(sqlx::FromRow)]
-            struct MuteRow {
-                muted_until: Option<chrono::DateTime<chrono::Utc>>,
-            }
-            if let Some(m) = sqlx::query_as::<_, MuteRow>(
-                "SELECT muted_until FROM chat_mutes WHERE chat_id = $1 AND user_id = $2",
-            )
-            .bind(chat_id)
-            .bind(user_id)
-            .fetch_optional(&state.pool)
-            .await?
-            {
-                if let Some(until) = m.muted_until {
-                    if until > chrono::Utc::now() {
-                        send_ws_err(state, user_id, "forbidden", "muted");
-                        return Ok(());
+            msg.client_message_id = client_message_id.clone();
+            match delivery::deliver(state, msg).await {
+                Ok(sent) => {
+                    if let Some(rid) = request_id {
+                        let ack = SentMessageAck {
+                            message_id: sent.message.id,
+                            client_message_id,
+                            message_sequence_id: sent.sender_sequence_id,
+                        };
+                        let msg = ServerWsMsg::Ack { sequence_id: 0, request_id: rid, message: Some(ack), scheduled: None };
+                        events::reply(state, user_id, ctx.session_id, msg).await?;
                     }
                 }
-            }
-
-            let saved = sqlx::query_as::<_, MessageRow>(
-                r#"INSERT INTO messages (id, chat_id, sender_id, content)
-                   VALUES ($1, $2, $3, $4)
-                   RETURNING id, chat_id, sender_id, content, created_at"#,
-            )
-            .bind(mid)
-            .bind(chat_id)
-            .bind(user_id)
-            .bind(content)
-            .fetch_one(&state.pool)
-            .await?;
-
-
-            let participants = sqlx::query_as::<_, UserIdRow>(
-                "SELECT user_id FROM chat_participants WHERE chat_id = $1",
-            )
-            .bind(chat_id)
-            .fetch_all(&state.pool)
-            .await?;
-
-            let server_msg = ServerWsMsg::NewMessage {
-                sequence_id: next_sequence_id(&state),
-                message: saved,
-            };
-            for uid in participants {
-                if let Some(tx) = state.clients.get(&uid.user_id) {
-                    let _ = tx.send(server_msg.clone());
+                Err(e) => {
+                    send_ws_err(state, user_id, ctx.session_id, request_id, e.code(), &e.to_string()).await?;
                 }
             }
+        }
+        ClientWsMsg::StartTyping { chat_id, action, request_id } => {
+            typing::start(state, chat_id, user_id, action.unwrap_or(TypingAction::Typing)).await?;
             if let Some(rid) = request_id {
-                if let Some(tx) = state.clients.get(&user_id) {
-                    let _ = tx.send(ServerWsMsg::Ack { sequence_id: next_sequence_id(&state), request_id: rid });
-                }
+                send_ack(state, user_id, ctx.session_id, rid).await?;
             }
         }
-        ClientWsMsg::StartTyping { chat_id, request_id } => {
-            // Check if member
-            let is_member = sqlx::query("SELECT 1 FROM chat_participants WHERE chat_id = $1 AND user_id = $2")
-                .bind(chat_id)
-                .bind(user_id)
-                .fetch_optional(&state.pool)
-                .await?;
-            if is_member.is_none() {
-                return Ok(());
-            }
-
-            // Record typing
-            state.typing.insert((chat_id, user_id), tokio::time::Instant::now());
-
-            // Get username
-            #[derive(sqlx::FromRow)]
-            struct UserRow {
-                username: String,
-            }
-            let user_row = sqlx::query_as::<_, UserRow>("SELECT username FROM users WHERE id = $1")
-                .bind(user_id)
-                .fetch_one(&state.pool)
-                .await?;
-            let user_info = UserInfo { id: user_id, username: user_row.username };
-
-            // Broadcast to other members
-            let participants = sqlx::query_as::<_, UserIdRow>(
-                "SELECT user_id FROM chat_participants WHERE chat_id = $1 AND user_id != $2",
-            )
-            .bind(chat_id)
-            .bind(user_id)
-            .fetch_all(&state.pool)
-            .await?;
-            let typing_msg = ServerWsMsg::TypingIndicator { sequence_id: next_sequence_id(&state), chat_id, user: user_info };
-            for p in participants {
-                if let Some(tx) = state.clients.get(&p.user_id) {
-                    let _ = tx.send(typing_msg.clone());
-                }
-            }
+        ClientWsMsg::StopTyping { chat_id, request_id } => {
+            typing::stop(state, chat_id, user_id).await?;
             if let Some(rid) = request_id {
-                if let Some(tx) = state.clients.get(&user_id) {
-                    let _ = tx.send(ServerWsMsg::Ack { sequence_id: next_sequence_id(&state), request_id: rid });
-                }
+                send_ack(state, user_id, ctx.session_id, rid).await?;
             }
         }
         ClientWsMsg::MarkAsRead { chat_id, last_read_message_id, request_id } => {
@@ -326,12 +526,12 @@ async fn handle_ws_text(state: &AppState, user_id: Uuid, txt: &str) -> anyhow::R
 
             // Broadcast messages_read
             // Logic depends on chat type
-            let chat_type = sqlx::query_scalar::<_, String>("SELECT chat_type FROM chats WHERE id = $1")
+            let is_direct = sqlx::query_scalar::<_, bool>("SELECT is_direct FROM chats WHERE id = $1")
                 .bind(chat_id)
                 .fetch_one(&state.pool)
                 .await?;
-            let is_read_by_peer = chat_type == "direct";
-            if chat_type == "direct" {
+            let is_read_by_peer = is_direct;
+            if is_direct {
                 // Find peer
                 let peer_id = sqlx::query_scalar::<_, Uuid>(
                     "SELECT user_id FROM chat_participants WHERE chat_id = $1 AND user_id != $2",
@@ -340,96 +540,144 @@ async fn handle_ws_text(state: &AppState, user_id: Uuid, txt: &str) -> anyhow::R
                 .bind(user_id)
                 .fetch_one(&state.pool)
                 .await?;
-                // Send to peer
-                if let Some(tx) = state.clients.get(&peer_id) {
-                    let _ = tx.send(ServerWsMsg::MessagesRead {
-                        sequence_id: next_sequence_id(&state),
-                        chat_id,
-                        reader_user_id: user_id.clone(),
-                        last_read_message_id,
-                        read_count: None,
-                        is_read_by_peer: None,
-                    });
-                }
+                // Send to peer and to the reader's other devices
+                let read_msg = ServerWsMsg::MessagesRead {
+                    sequence_id: 0,
+                    chat_id,
+                    reader_user_id: user_id,
+                    last_read_message_id,
+                    read_count: None,
+                    is_read_by_peer: None,
+                };
+                events::publish(state, &[peer_id, user_id], read_msg).await?;
             } else {
-                // Group/channel: send to sender of the message
+                // Group/channel: send to sender of the message and the reader's other devices
                 let sender_id = sqlx::query_scalar::<_, Uuid>("SELECT sender_id FROM messages WHERE id = $1")
                     .bind(last_read_message_id)
                     .fetch_one(&state.pool)
                     .await?;
-                if let Some(tx) = state.clients.get(&sender_id) {
-                    // Calculate read_count if small group
-                    let participant_count = sqlx::query_scalar::<_, i64>(
-                        "SELECT COUNT(*) FROM chat_participants WHERE chat_id = $1",
+                // Calculate read_count if small group
+                let participant_count = sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM chat_participants WHERE chat_id = $1",
+                )
+                .bind(chat_id)
+                .fetch_one(&state.pool)
+                .await?;
+                let read_count = if participant_count <= 100 {
+                    Some(sqlx::query_scalar::<_, i64>(
+                        "SELECT COUNT(*) FROM message_reads_small WHERE message_id = $1",
                     )
-                    .bind(chat_id)
+                    .bind(last_read_message_id)
                     .fetch_one(&state.pool)
-                    .await?;
-                    let read_count = if participant_count <= 100 {
-                        Some(sqlx::query_scalar::<_, i32>(
-                            "SELECT COUNT(*) FROM message_reads_small WHERE message_id = $1",
-                        )
-                        .bind(last_read_message_id)
-                        .fetch_one(&state.pool)
-                        .await?)
-                    } else {
-                        None
-                    };
-                    let _ = tx.send(ServerWsMsg::MessagesRead {
-                        sequence_id: next_sequence_id(&state),
-                        chat_id,
-                        reader_user_id: user_id.clone(),
-                        last_read_message_id,
-                        read_count: read_count.map(|c| c as i32),
-                        is_read_by_peer: Some(is_read_by_peer),
-                    });
-                }
+                    .await?)
+                } else {
+                    None
+                };
+                let read_msg = ServerWsMsg::MessagesRead {
+                    sequence_id: 0,
+                    chat_id,
+                    reader_user_id: user_id,
+                    last_read_message_id,
+                    read_count: read_count.map(|c| c as i32),
+                    is_read_by_peer: Some(is_read_by_peer),
+                };
+                events::publish(state, &[sender_id, user_id], read_msg).await?;
             }
             if let Some(rid) = request_id {
-                if let Some(tx) = state.clients.get(&user_id) {
-                    let _ = tx.send(ServerWsMsg::Ack { sequence_id: next_sequence_id(&state), request_id: rid });
-                }
+                send_ack(state, user_id, ctx.session_id, rid).await?;
             }
         }
-        ClientWsMsg::Sync { last_sequence_id } => {
-            // For sync, we need to collect all events since last_sequence_id
-            // But since we don't store events, we can send an empty array for now
-            // In a real implementation, you'd have an event log or replay mechanism
-            let events = vec![]; // Placeholder: implement proper event replay
+        ClientWsMsg::Sync { last_sequence_id, limit } => {
+            let limit = limit.unwrap_or(state.config.events.sync_page_size);
+            let from = last_sequence_id.unwrap_or(ctx.acked_sequence_id);
+            let page = events::replay(state, user_id, from, limit).await?;
             let sync_msg = ServerWsMsg::SyncResponse {
-                sequence_id: next_sequence_id(&state),
-                events,
+                sequence_id: page.last_sequence_id,
+                events: page.events,
+                has_more: page.has_more,
+                resync_required: page.resync_required,
             };
-            if let Some(tx) = state.clients.get(&user_id) {
-                let _ = tx.send(sync_msg);
-            }
+            events::send_to_session(state, user_id, ctx.session_id, sync_msg);
+        }
+        ClientWsMsg::AckEvents { sequence_id } => {
+            ctx.ack(state, user_id, sequence_id).await?;
+        }
+        ClientWsMsg::Rpc { method, params, request_id } => {
+            let session_id = ctx.session_id;
+            let Ok(slot) = ctx.rpc_slots.clone().try_acquire_owned() else {
+                return events::reply(state, user_id, session_id, rpc::busy(request_id)).await;
+            };
+            let dispatcher = ctx.rpc(state).await?;
+            let state = state.clone();
+            // handlers can be slow; running them off the session loop keeps it draining
+            // the outbound queue and answering heartbeats meanwhile
+            actix_web::rt::spawn(async move {
+                let reply = dispatcher.call(user_id, &method, params, request_id).await;
+                if let Err(e) = events::reply(&state, user_id, session_id, reply).await {
+                    error!(%user_id, ?e, "rpc reply error");
+                }
+                drop(slot);
+            });
         }
     }
     Ok(())
 }
 
-fn send_ws_err(state: &AppState, user_id: Uuid, code: &str, msg: &str) {
-    if let Some(tx) = state.clients.get(&user_id) {
-        let _ = tx.send(ServerWsMsg::Error {
-            sequence_id: next_sequence_id(&state),
-            code: code.to_string(),
-            message: msg.to_string(),
-        });
+/// Pick the message kind from a `send_message` frame: a poll, a sticker, a GIF, or text with optional attachments.
+fn message_body(
+    content: Option<String>,
+    entities: Vec<MessageEntity>,
+    sticker_id: Option<Uuid>,
+    gif: Option<Box<GifSendReq>>,
+    poll: Option<Box<PollSendReq>>,
+    attachment_ids: &[Uuid],
+) -> Result<MessageBody, DeliveryError> {
+    let has_content = content.as_deref().is_some_and(|c| !c.trim().is_empty()) || !entities.is_empty();
+    if let Some(poll) = poll {
+        if sticker_id.is_some() || gif.is_some() || has_content || !attachment_ids.is_empty() {
+            return Err(DeliveryError::BadRequest("polls cannot carry content, attachments, stickers or gifs"));
+        }
+        return Ok(MessageBody::Poll(*poll));
     }
+    match (sticker_id, gif.map(|g| *g)) {
+        (Some(_), Some(_)) => Err(DeliveryError::BadRequest("sticker_id and gif are exclusive")),
+        (Some(_), None) | (None, Some(_)) if has_content || !attachment_ids.is_empty() => Err(
+            DeliveryError::BadRequest("stickers and gifs cannot carry content or attachments"),
+        ),
+        (Some(sticker_id), None) => Ok(MessageBody::Sticker { sticker_id }),
+        (None, Some(gif)) => Ok(MessageBody::Gif(GifPayload {
+            id: gif.gif_id,
+            url: gif.gif_url,
+            preview_url: gif.gif_preview_url,
+            provider: gif.provider,
+        })),
+        (None, None) => Ok(MessageBody::Text { content: content.unwrap_or_default(), entities }),
+    }
+}
+
+async fn send_ack(state: &AppState, user_id: Uuid, session_id: Uuid, request_id: Uuid) -> anyhow::Result<()> {
+    let msg = ServerWsMsg::Ack { sequence_id: 0, request_id, message: None, scheduled: None };
+    events::reply(state, user_id, session_id, msg).await
 }
 
-async fn get_common_chat_users(state: &AppState, user_id: Uuid) -> Vec<Uuid> {
-    // Only broadcast to users in direct chats or small groups (<=100 members)
-    sqlx::query_scalar(
-        "SELECT DISTINCT cp2.user_id FROM chat_participants cp1
-         JOIN chat_participants cp2 ON cp1.chat_id = cp2.chat_id
-         JOIN chats c ON c.id = cp1.chat_id
-         LEFT JOIN (SELECT chat_id, COUNT(*) as cnt FROM chat_participants GROUP BY chat_id) pc ON pc.chat_id = c.id
-         WHERE cp1.user_id = $1 AND cp2.user_id != $1
-         AND (c.is_direct = TRUE OR pc.cnt <= 100)",
+async fn send_ws_err(
+    state: &AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+    request_id: Option<Uuid>,
+    code: &str,
+    msg: &str,
+) -> anyhow::Result<()> {
+    events::reply(
+        state,
+        user_id,
+        session_id,
+        ServerWsMsg::Error {
+            sequence_id: 0,
+            code: code.to_string(),
+            message: msg.to_string(),
+            request_id,
+        },
     )
-    .bind(user_id)
-    .fetch_all(&state.pool)
     .await
-    .unwrap_or_default()
 }
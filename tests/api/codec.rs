@@ -0,0 +1,506 @@
+use super::helpers::TestApp;
+use chrono::Utc;
+use futures_util::{SinkExt, StreamExt};
+use qbychat_vibe_coding::chat_actions::ChatActionType;
+use qbychat_vibe_coding::codec::{WsCodec, WsFrame};
+use qbychat_vibe_coding::models::{
+    DraftDto, ForwardedChatDto, ForwardedFromDto, GifMessageDto, GifSendReq, LinkPreviewDto, LinkPreviewImageDto, MessageAttachmentDto, MessageDto,
+    MessageEntity, MessageEntityType, MessageMentionDto, MessageReadReceiptDto, MessageReplyDto, MessageRow, MessageTtlDto, PollDto, PollOptionDto, PollSendReq, Reaction, ReactionCountDto, ScheduledMessageDto,
+    ServiceActionDto, SimpleUserDto, StickerMessageDto, ThreadInfoDto,
+};
+use qbychat_vibe_coding::typing::TypingAction;
+use qbychat_vibe_coding::ws::{ClientWsMsg, SentMessageAck, ServerWsMsg};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use uuid::Uuid;
+
+// Exhaustive on purpose: a new variant fails to compile here until it is added to the samples below.
+fn client_variant(msg: &ClientWsMsg) -> &'static str {
+    match msg {
+        ClientWsMsg::SendMessage { .. } => "send_message",
+        ClientWsMsg::StartTyping { .. } => "start_typing",
+        ClientWsMsg::StopTyping { .. } => "stop_typing",
+        ClientWsMsg::MarkAsRead { .. } => "mark_as_read",
+        ClientWsMsg::Sync { .. } => "sync",
+        ClientWsMsg::AckEvents { .. } => "ack_events",
+        ClientWsMsg::Rpc { .. } => "rpc",
+    }
+}
+
+fn server_variant(msg: &ServerWsMsg) -> &'static str {
+    match msg {
+        ServerWsMsg::NewMessage { .. } => "new_message",
+        ServerWsMsg::Error { .. } => "error",
+        ServerWsMsg::MessageEdited { .. } => "message_edited",
+        ServerWsMsg::MessageDeleted { .. } => "message_deleted",
+        ServerWsMsg::MessagesRead { .. } => "messages_read",
+        ServerWsMsg::PresenceUpdate { .. } => "presence_update",
+        ServerWsMsg::ChatAction { .. } => "chat_action",
+        ServerWsMsg::ReactionUpdated { .. } => "reaction_updated",
+        ServerWsMsg::PollUpdated { .. } => "poll_updated",
+        ServerWsMsg::LinkPreviewUpdated { .. } => "link_preview_updated",
+        ServerWsMsg::DraftUpdated { .. } => "draft_updated",
+        ServerWsMsg::SyncResponse { .. } => "sync_response",
+        ServerWsMsg::Ack { .. } => "ack",
+        ServerWsMsg::RpcResult { .. } => "rpc_result",
+        ServerWsMsg::RpcError { .. } => "rpc_error",
+    }
+}
+
+fn user() -> SimpleUserDto {
+    SimpleUserDto { id: Uuid::new_v4(), username: "alice".into() }
+}
+
+fn full_message() -> MessageDto {
+    MessageDto {
+        id: Uuid::new_v4(),
+        chat_id: Uuid::new_v4(),
+        sender: user(),
+        content: "hello @bob".into(),
+        entities: vec![
+            MessageEntity { kind: MessageEntityType::Bold, offset: 0, length: 5, url: None, user_id: None, language: None },
+            MessageEntity {
+                kind: MessageEntityType::TextMention,
+                offset: 6,
+                length: 4,
+                url: None,
+                user_id: Some(Uuid::new_v4()),
+                language: None,
+            },
+        ],
+        kind: "text".into(),
+        created_at: Utc::now(),
+        edited_at: Some(Utc::now()),
+        revision: 2,
+        reply_to: Some(MessageReplyDto {
+            id: Uuid::new_v4(),
+            content: "hi".into(),
+            sender: user(),
+            created_at: Utc::now(),
+        }),
+        attachments: vec![MessageAttachmentDto { id: Uuid::new_v4(), content_type: Some("image/png".into()) }],
+        mentions: vec![MessageMentionDto { user_id: Uuid::new_v4(), username: "bob".into() }],
+        read_receipt: Some(MessageReadReceiptDto {
+            read_count: Some(2),
+            is_read_by_peer: Some(true),
+            last_read_at: Some(Utc::now()),
+        }),
+        is_pinned: true,
+        forwarded_from: Some(ForwardedFromDto {
+            chat: Some(ForwardedChatDto { id: Uuid::new_v4(), title: Some("general".into()) }),
+            sender: Some(user()),
+        }),
+        sticker: Some(StickerMessageDto {
+            id: Uuid::new_v4(),
+            pack_id: Uuid::new_v4(),
+            pack_short_name: "cats".into(),
+            emoji: Some("🐱".into()),
+            file_id: Uuid::new_v4(),
+        }),
+        gif: Some(GifMessageDto {
+            id: "g1".into(),
+            url: "https://example.com/g.gif".into(),
+            preview_url: "https://example.com/g.png".into(),
+            provider: "tenor".into(),
+        }),
+        service: Some(ServiceActionDto {
+            action: ChatActionType::UserAdded,
+            target: Some(user()),
+            payload: serde_json::json!({}),
+        }),
+        reactions: vec![
+            ReactionCountDto { reaction: Reaction::Emoji { emoji: "👍".into() }, count: 2, reacted_by_me: Some(true) },
+            ReactionCountDto { reaction: Reaction::Custom { sticker_id: Uuid::new_v4() }, count: 1, reacted_by_me: None },
+        ],
+        thread_root_id: Some(Uuid::new_v4()),
+        thread: Some(ThreadInfoDto {
+            reply_count: 4,
+            last_reply_at: Utc::now(),
+            last_replier: user(),
+            unread_count: Some(2),
+        }),
+        ttl: Some(MessageTtlDto {
+            ttl_seconds: 30,
+            started_at: Some(Utc::now()),
+            expires_at: Some(Utc::now()),
+            starts_on_read: true,
+        }),
+        poll: Some(poll()),
+        link_preview: Some(link_preview()),
+    }
+}
+
+fn link_preview() -> LinkPreviewDto {
+    LinkPreviewDto {
+        url: "https://example.com/post".into(),
+        title: Some("A post".into()),
+        description: Some("About things".into()),
+        site_name: Some("Example".into()),
+        image: Some(LinkPreviewImageDto { file_id: Uuid::new_v4(), width: 320, height: 180 }),
+    }
+}
+
+fn poll() -> PollDto {
+    PollDto {
+        id: Uuid::new_v4(),
+        question: "lunch?".into(),
+        options: vec![
+            PollOptionDto { id: 0, text: "pizza".into(), voter_count: 2, chosen: Some(true) },
+            PollOptionDto { id: 1, text: "sushi".into(), voter_count: 0, chosen: None },
+        ],
+        total_voters: 2,
+        is_anonymous: false,
+        allows_multiple: false,
+        is_quiz: true,
+        correct_option_id: Some(0),
+        explanation: Some("it is friday".into()),
+        close_at: Some(Utc::now()),
+        is_closed: false,
+    }
+}
+
+fn client_samples() -> Vec<ClientWsMsg> {
+    let chat_id = Uuid::new_v4();
+    vec![
+        ClientWsMsg::SendMessage {
+            chat_id,
+            content: Some("hi".into()),
+            entities: vec![MessageEntity {
+                kind: MessageEntityType::TextLink,
+                offset: 0,
+                length: 2,
+                url: Some("https://example.com".into()),
+                user_id: None,
+                language: None,
+            }],
+            reply_to_message_id: Some(Uuid::new_v4()),
+            thread_root_id: Some(Uuid::new_v4()),
+            attachment_ids: vec![Uuid::new_v4()],
+            sticker_id: None,
+            gif: Some(Box::new(GifSendReq {
+                gif_id: "g1".into(),
+                gif_url: "https://example.com/g.gif".into(),
+                gif_preview_url: "https://example.com/g.png".into(),
+                provider: "tenor".into(),
+            })),
+            poll: None,
+            client_message_id: Some("c-1".into()),
+            expires_in: Some(60),
+            disable_link_preview: true,
+            scheduled_at: Some(Utc::now()),
+            request_id: Some(Uuid::new_v4()),
+        },
+        ClientWsMsg::SendMessage {
+            chat_id,
+            content: None,
+            entities: vec![],
+            reply_to_message_id: None,
+            thread_root_id: None,
+            attachment_ids: vec![],
+            sticker_id: Some(Uuid::new_v4()),
+            gif: None,
+            poll: Some(Box::new(PollSendReq {
+                question: "lunch?".into(),
+                options: vec!["pizza".into(), "sushi".into()],
+                is_anonymous: true,
+                allows_multiple: true,
+                correct_option_id: None,
+                explanation: None,
+                close_at: Some(Utc::now()),
+            })),
+            client_message_id: None,
+            expires_in: None,
+            disable_link_preview: false,
+            scheduled_at: None,
+            request_id: None,
+        },
+        ClientWsMsg::StartTyping { chat_id, action: Some(TypingAction::RecordVoice), request_id: None },
+        ClientWsMsg::StopTyping { chat_id, request_id: Some(Uuid::new_v4()) },
+        ClientWsMsg::MarkAsRead { chat_id, last_read_message_id: Uuid::new_v4(), request_id: None },
+        ClientWsMsg::Sync { last_sequence_id: Some(42), limit: None },
+        ClientWsMsg::AckEvents { sequence_id: 7 },
+        ClientWsMsg::Rpc {
+            method: "GET /v1/api/chats".into(),
+            params: Some(serde_json::json!({"limit": 20, "archived": false})),
+            request_id: Uuid::new_v4(),
+        },
+    ]
+}
+
+fn server_samples() -> Vec<ServerWsMsg> {
+    let chat_id = Uuid::new_v4();
+    let new_message = ServerWsMsg::NewMessage { sequence_id: 1, message: Box::new(full_message()) };
+    vec![
+        new_message.clone(),
+        ServerWsMsg::Error {
+            sequence_id: 2,
+            code: "forbidden".into(),
+            message: "not a member".into(),
+            request_id: Some(Uuid::new_v4()),
+        },
+        ServerWsMsg::MessageEdited {
+            sequence_id: 3,
+            message: MessageRow {
+                id: Uuid::new_v4(),
+                chat_id,
+                sender_id: Uuid::new_v4(),
+                content: "edited".into(),
+                entities: vec![MessageEntity {
+                    kind: MessageEntityType::Pre,
+                    offset: 0,
+                    length: 6,
+                    url: None,
+                    user_id: None,
+                    language: Some("rust".into()),
+                }],
+                created_at: Utc::now(),
+                edited_at: Some(Utc::now()),
+                revision: 2,
+            },
+        },
+        ServerWsMsg::MessageDeleted { sequence_id: 4, chat_id, message_ids: vec![Uuid::new_v4(), Uuid::new_v4()] },
+        ServerWsMsg::MessagesRead {
+            sequence_id: 5,
+            chat_id,
+            reader_user_id: Uuid::new_v4(),
+            last_read_message_id: Uuid::new_v4(),
+            read_count: Some(3),
+            is_read_by_peer: None,
+        },
+        ServerWsMsg::PresenceUpdate {
+            sequence_id: 6,
+            user_id: Uuid::new_v4(),
+            status: "offline".into(),
+            last_seen_at: Some(Utc::now().to_rfc3339()),
+            last_seen: None,
+        },
+        ServerWsMsg::PresenceUpdate {
+            sequence_id: 6,
+            user_id: Uuid::new_v4(),
+            status: "offline".into(),
+            last_seen_at: None,
+            last_seen: Some("within_week".into()),
+        },
+        ServerWsMsg::ChatAction {
+            sequence_id: 7,
+            chat_id,
+            action_type: "typing".into(),
+            data: serde_json::json!({"user": {"id": Uuid::new_v4(), "username": "alice"}, "action": "typing", "n": [1, 2.5, null]}),
+        },
+        ServerWsMsg::SyncResponse { sequence_id: 8, events: vec![new_message], has_more: true, resync_required: false },
+        ServerWsMsg::Ack { sequence_id: 9, request_id: Uuid::new_v4(), message: None, scheduled: None },
+        ServerWsMsg::Ack {
+            sequence_id: 10,
+            request_id: Uuid::new_v4(),
+            message: Some(SentMessageAck {
+                message_id: Uuid::new_v4(),
+                client_message_id: Some("c-1".into()),
+                message_sequence_id: Some(10),
+            }),
+            scheduled: None,
+        },
+        ServerWsMsg::Ack {
+            sequence_id: 10,
+            request_id: Uuid::new_v4(),
+            message: None,
+            scheduled: Some(ScheduledMessageDto {
+                id: Uuid::new_v4(),
+                chat_id,
+                content: "later".into(),
+                entities: vec![],
+                reply_to_message_id: None,
+                thread_root_id: Some(Uuid::new_v4()),
+                attachment_ids: vec![Uuid::new_v4()],
+                expires_in: Some(60),
+                disable_link_preview: false,
+                scheduled_at: Utc::now(),
+                created_at: Utc::now(),
+                status: "failed".into(),
+                error: Some("muted".into()),
+            }),
+        },
+        ServerWsMsg::RpcResult {
+            sequence_id: 11,
+            request_id: Uuid::new_v4(),
+            status: 200,
+            result: serde_json::json!([{"id": Uuid::new_v4(), "title": "general", "unread": 3}]),
+        },
+        ServerWsMsg::RpcError {
+            sequence_id: 12,
+            request_id: Uuid::new_v4(),
+            status: 404,
+            code: "not_found".into(),
+            message: "chat not found".into(),
+        },
+        ServerWsMsg::ReactionUpdated {
+            sequence_id: 13,
+            chat_id,
+            message_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            reaction: Reaction::Custom { sticker_id: Uuid::new_v4() },
+            added: false,
+            reactions: vec![ReactionCountDto { reaction: Reaction::Emoji { emoji: "🔥".into() }, count: 3, reacted_by_me: None }],
+        },
+        ServerWsMsg::PollUpdated { sequence_id: 14, chat_id, message_id: Uuid::new_v4(), poll: Box::new(poll()) },
+        ServerWsMsg::LinkPreviewUpdated {
+            sequence_id: 15,
+            chat_id,
+            message_id: Uuid::new_v4(),
+            link_preview: Some(Box::new(link_preview())),
+        },
+        ServerWsMsg::LinkPreviewUpdated { sequence_id: 16, chat_id, message_id: Uuid::new_v4(), link_preview: None },
+        ServerWsMsg::DraftUpdated {
+            sequence_id: 17,
+            chat_id,
+            draft: Some(Box::new(DraftDto {
+                chat_id,
+                content: "half a thought".into(),
+                entities: vec![MessageEntity {
+                    kind: MessageEntityType::Italic,
+                    offset: 0,
+                    length: 4,
+                    url: None,
+                    user_id: None,
+                    language: None,
+                }],
+                reply_to_message_id: Some(Uuid::new_v4()),
+                updated_at: Utc::now(),
+            })),
+        },
+        ServerWsMsg::DraftUpdated { sequence_id: 18, chat_id, draft: None },
+    ]
+}
+
+fn round_trip<T: Serialize + DeserializeOwned>(codec: WsCodec, value: &T) -> anyhow::Result<()> {
+    let bytes = match codec.encode(value)? {
+        WsFrame::Text(t) => {
+            assert_eq!(codec, WsCodec::Json);
+            t.into_bytes()
+        }
+        WsFrame::Binary(b) => {
+            assert_ne!(codec, WsCodec::Json);
+            b
+        }
+    };
+    let decoded: T = codec.decode(&bytes)?;
+    assert_eq!(serde_json::to_value(value)?, serde_json::to_value(&decoded)?, "{:?} changed the value", codec);
+    Ok(())
+}
+
+#[test]
+fn every_variant_round_trips_in_every_codec() -> anyhow::Result<()> {
+    let clients = client_samples();
+    let servers = server_samples();
+    let mut client_names: Vec<_> = clients.iter().map(client_variant).collect();
+    let mut server_names: Vec<_> = servers.iter().map(server_variant).collect();
+    client_names.sort_unstable();
+    client_names.dedup();
+    server_names.sort_unstable();
+    server_names.dedup();
+    assert_eq!(client_names.len(), 7, "every client variant needs a sample");
+    assert_eq!(server_names.len(), 15, "every server variant needs a sample");
+
+    for codec in WsCodec::ALL {
+        for msg in &clients {
+            round_trip(codec, msg)?;
+        }
+        for msg in &servers {
+            round_trip(codec, msg)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn binary_codecs_accept_string_uuids() -> anyhow::Result<()> {
+    // what a client building maps by hand (e.g. from JS objects) sends
+    let chat_id = Uuid::new_v4();
+    let value = serde_json::json!({"type": "start_typing", "chat_id": chat_id.to_string(), "action": "typing"});
+    for codec in [WsCodec::MessagePack, WsCodec::Cbor] {
+        let WsFrame::Binary(bytes) = codec.encode(&value)? else { panic!("binary codec produced text") };
+        match codec.decode::<ClientWsMsg>(&bytes)? {
+            ClientWsMsg::StartTyping { chat_id: decoded, .. } => assert_eq!(decoded, chat_id),
+            other => panic!("decoded {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn negotiation_picks_first_supported_protocol() {
+    assert_eq!(WsCodec::negotiate(None), None);
+    assert_eq!(WsCodec::negotiate(Some("graphql-ws")), None);
+    assert_eq!(WsCodec::negotiate(Some("graphql-ws, cbor, msgpack")), Some(WsCodec::Cbor));
+    assert_eq!(WsCodec::negotiate(Some("MsgPack")), Some(WsCodec::MessagePack));
+    assert_eq!(WsCodec::negotiate(Some("json")), Some(WsCodec::Json));
+}
+
+#[tokio::test]
+async fn msgpack_session_speaks_binary_frames() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping ws test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id: Uuid = app.start_direct(&alice.token, &bob.id).await?.parse()?;
+
+    let mut request = app.ws_url(&alice.token).into_client_request()?;
+    request.headers_mut().insert("Sec-WebSocket-Protocol", "msgpack, json".parse()?);
+    let (mut ws, response) = tokio_tungstenite::connect_async(request).await?;
+    assert_eq!(
+        response.headers().get("sec-websocket-protocol").and_then(|v| v.to_str().ok()),
+        Some("msgpack")
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+    let codec = WsCodec::MessagePack;
+    let request_id = Uuid::new_v4();
+    let send = ClientWsMsg::SendMessage {
+        chat_id,
+        content: Some("packed".into()),
+        entities: vec![],
+        reply_to_message_id: None,
+        thread_root_id: None,
+        attachment_ids: vec![],
+        sticker_id: None,
+        gif: None,
+        poll: None,
+        client_message_id: None,
+        expires_in: None,
+        disable_link_preview: false,
+        scheduled_at: None,
+        request_id: Some(request_id),
+    };
+    let WsFrame::Binary(bytes) = codec.encode(&send)? else { panic!("msgpack must be binary") };
+    ws.send(WsMessage::Binary(bytes)).await?;
+
+    let mut received = Vec::new();
+    while received.len() < 2 {
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("ws stream ended"))??;
+        match frame {
+            WsMessage::Binary(b) => received.push(codec.decode::<ServerWsMsg>(&b)?),
+            WsMessage::Text(t) => panic!("unexpected text frame: {}", t),
+            _ => continue,
+        }
+    }
+    match &received[0] {
+        ServerWsMsg::NewMessage { message, .. } => assert_eq!(message.content, "packed"),
+        other => panic!("expected new_message, got {:?}", other),
+    }
+    match &received[1] {
+        ServerWsMsg::Ack { request_id: rid, message: Some(ack), .. } => {
+            assert_eq!(*rid, request_id);
+            assert!(ack.message_sequence_id.is_some());
+        }
+        other => panic!("expected ack, got {:?}", other),
+    }
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use super::helpers::TestApp;
+use futures_util::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+async fn history(app: &TestApp, token: &str, chat_url: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    let page: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(page["messages"].as_array().unwrap().clone())
+}
+
+async fn unread(app: &TestApp, token: &str, chat_url: &str) -> anyhow::Result<i64> {
+    let body: serde_json::Value = app
+        .client
+        .get(format!("{}/unread_count", chat_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(body["unread"].as_i64().unwrap())
+}
+
+#[tokio::test]
+async fn delete_for_me_hides_only_for_the_caller() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let carol = app.signup("carol").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    let spam = app.send_message(&bob.token, &chat_id, "buy cheap watches").await?;
+    let keep = app.send_message(&bob.token, &chat_id, "see you at noon").await?;
+    assert_eq!(unread(&app, &alice.token, &chat_url).await?, 2);
+    app.client
+        .post(format!("{}/v1/api/saved", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"message_id": spam}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    // in a direct chat nobody may delete the other side's messages for everyone
+    let resp = app
+        .client
+        .delete(format!("{}/v1/api/messages/{}", app.address, spam))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = app
+        .client
+        .delete(format!("{}/v1/api/messages/{}?scope=me", app.address, spam))
+        .bearer_auth(&carol.token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = app
+        .client
+        .delete(format!("{}/v1/api/messages/{}?scope=me", app.address, spam))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let mine = history(&app, &alice.token, &chat_url).await?;
+    assert_eq!(mine.len(), 1);
+    assert_eq!(mine[0]["id"], keep.as_str());
+    let theirs = history(&app, &bob.token, &chat_url).await?;
+    assert_eq!(theirs.len(), 2);
+    assert!(theirs.iter().all(|m| m["content"] != ""));
+    assert_eq!(unread(&app, &alice.token, &chat_url).await?, 1);
+    let found: serde_json::Value = app
+        .client
+        .get(format!("{}/messages/search?q=watches", chat_url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert!(found["messages"].as_array().unwrap().is_empty());
+    let saved: serde_json::Value = app
+        .client
+        .get(format!("{}/v1/api/saved", app.address))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert!(saved["items"].as_array().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn owners_and_admins_delete_for_everyone_in_bulk() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let carol = app.signup("carol").await?;
+    let dave = app.signup("dave").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "cleanup"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_id = group["id"].as_str().unwrap().to_string();
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    for member in [&bob, &carol, &dave] {
+        app.client
+            .post(format!("{}/participants", chat_url))
+            .bearer_auth(&alice.token)
+            .json(&json!({"user_id": member.id}))
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    app.client
+        .post(format!("{}/admins", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id, "permissions": {
+            "can_change_info": false,
+            "can_delete_messages": true,
+            "can_invite_users": false,
+            "can_pin_messages": false,
+            "can_manage_members": false,
+        }}))
+        .send()
+        .await?
+        .error_for_status()?;
+    let first = app.send_message(&carol.token, &chat_id, "spam one").await?;
+    let second = app.send_message(&carol.token, &chat_id, "spam two").await?;
+    let own = app.send_message(&dave.token, &chat_id, "my own words").await?;
+    let other_chat = app.start_direct(&carol.token, &dave.id).await?;
+    let elsewhere = app.send_message(&carol.token, &other_chat, "hi").await?;
+    let bulk_url = format!("{}/v1/api/messages/delete_bulk", app.address);
+
+    // plain members can only take back their own messages
+    let resp = app
+        .client
+        .post(&bulk_url)
+        .bearer_auth(&dave.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [first, own]}))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    app.client
+        .delete(format!("{}/v1/api/messages/{}?scope=everyone", app.address, own))
+        .bearer_auth(&dave.token)
+        .send()
+        .await?
+        .error_for_status()?;
+    // every id must belong to the chat
+    let resp = app
+        .client
+        .post(&bulk_url)
+        .bearer_auth(&bob.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [first, elsewhere]}))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let resp = app
+        .client
+        .post(&bulk_url)
+        .bearer_auth(&bob.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [first, second], "scope": "everyone"}))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: serde_json::Value = resp.json().await?;
+    assert_eq!(body["message_ids"].as_array().unwrap().len(), 2);
+    // already deleted: nothing left to announce
+    let body: serde_json::Value = app
+        .client
+        .post(&bulk_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [first]}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert!(body["message_ids"].as_array().unwrap().is_empty());
+
+    let messages = history(&app, &carol.token, &chat_url).await?;
+    for id in [&first, &second, &own] {
+        let m = messages.iter().find(|m| m["id"] == id.as_str()).unwrap();
+        assert_eq!(m["content"], "");
+    }
+
+    // one message_deleted carries the whole batch
+    let (mut ws, _) = tokio_tungstenite::connect_async(app.ws_url(&carol.token)).await?;
+    ws.send(WsMessage::Text(json!({"type": "sync", "last_sequence_id": 0}).to_string())).await?;
+    let events = loop {
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next()).await?.unwrap()?;
+        if let WsMessage::Text(t) = frame {
+            let v: serde_json::Value = serde_json::from_str(&t)?;
+            if v["type"] == "sync_response" {
+                break v["events"].as_array().unwrap().clone();
+            }
+        }
+    };
+    let deletions: Vec<&serde_json::Value> = events.iter().filter(|e| e["type"] == "message_deleted").collect();
+    assert_eq!(deletions.len(), 2);
+    assert_eq!(deletions[1]["message_ids"].as_array().unwrap().len(), 2);
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use super::helpers::TestApp;
+use futures_util::StreamExt;
+use reqwest::StatusCode;
+use serde_json::json;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+type WsStream =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Skip frames until the next `draft_updated` event.
+async fn next_draft_event(ws: &mut WsStream) -> anyhow::Result<serde_json::Value> {
+    loop {
+        let next = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next())
+            .await
+            .map_err(|_| anyhow::anyhow!("timeout waiting for draft_updated"))?
+            .ok_or_else(|| anyhow::anyhow!("ws stream ended"))??;
+        if let WsMessage::Text(t) = next {
+            let event: serde_json::Value = serde_json::from_str(&t)?;
+            if event["type"] == "draft_updated" {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+async fn chat_list(app: &TestApp, token: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    let page: serde_json::Value = app
+        .client
+        .get(format!("{}/v1/api/chats", app.address))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(page["chats"].as_array().unwrap().clone())
+}
+
+#[tokio::test]
+async fn drafts_sync_across_sessions_and_clear_on_send() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let carol = app.signup("carol").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    let draft_url = format!("{}/draft", chat_url);
+    let reply_to = app.send_message(&bob.token, &chat_id, "what do you think?").await?;
+    let other_chat = app.start_direct(&bob.token, &carol.id).await?;
+    let elsewhere = app.send_message(&bob.token, &other_chat, "not here").await?;
+
+    let (mut phone, _) = tokio_tungstenite::connect_async(app.ws_url(&alice.token)).await?;
+
+    let saved = app
+        .client
+        .put(&draft_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({
+            "content": "  I think we should ",
+            "entities": [{"type": "bold", "offset": 2, "length": 7}],
+            "reply_to_message_id": reply_to,
+        }))
+        .send()
+        .await?;
+    assert_eq!(saved.status(), StatusCode::OK);
+    let saved: serde_json::Value = saved.json().await?;
+    assert_eq!(saved["content"], "I think we should");
+    assert_eq!(saved["entities"][0]["offset"], 0);
+    assert_eq!(saved["reply_to_message_id"], reply_to.as_str());
+
+    let event = next_draft_event(&mut phone).await?;
+    assert_eq!(event["chat_id"], chat_id.as_str());
+    assert_eq!(event["draft"]["content"], "I think we should");
+
+    let fetched: serde_json::Value = app
+        .client
+        .get(&draft_url)
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(fetched, saved);
+    let chats = chat_list(&app, &alice.token).await?;
+    let listed = chats.iter().find(|c| c["id"] == chat_id.as_str()).unwrap();
+    assert_eq!(listed["draft"]["content"], "I think we should");
+
+    // drafts are private and per user
+    let resp = app.client.get(&draft_url).bearer_auth(&bob.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let bob_chats = chat_list(&app, &bob.token).await?;
+    assert!(bob_chats.iter().all(|c| c.get("draft").is_none()));
+    let resp = app.client.get(&draft_url).bearer_auth(&carol.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    for bad in [
+        json!({"content": "hi", "entities": [{"type": "bold", "offset": 1, "length": 5}]}),
+        json!({"content": "hi", "reply_to_message_id": elsewhere}),
+    ] {
+        let resp = app.client.put(&draft_url).bearer_auth(&alice.token).json(&bad).send().await?;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{bad}");
+    }
+
+    // sending uses the draft up
+    app.send_message(&alice.token, &chat_id, "I think we should ship it").await?;
+    let event = next_draft_event(&mut phone).await?;
+    assert!(event["draft"].is_null());
+    let resp = app.client.get(&draft_url).bearer_auth(&alice.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // an emptied composer removes the draft, as does an explicit delete
+    app.client
+        .put(&draft_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "later"}))
+        .send()
+        .await?
+        .error_for_status()?;
+    let resp = app.client.put(&draft_url).bearer_auth(&alice.token).json(&json!({"content": "  "})).send().await?;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = app.client.get(&draft_url).bearer_auth(&alice.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    app.client
+        .put(&draft_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "again"}))
+        .send()
+        .await?
+        .error_for_status()?;
+    let resp = app.client.delete(&draft_url).bearer_auth(&alice.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let chats = chat_list(&app, &alice.token).await?;
+    assert!(chats.iter().all(|c| c.get("draft").is_none()));
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use super::helpers::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn entities_are_validated_stored_and_forwarded() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let carol = app.signup("carol").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+
+    for bad in [
+        json!([{"type": "bold", "offset": 0, "length": 50}]),
+        json!([{"type": "bold", "offset": 1, "length": 0}]),
+        json!([{"type": "text_link", "offset": 0, "length": 2, "url": "javascript:alert(1)"}]),
+        json!([{"type": "bold", "offset": 0, "length": 2, "url": "https://example.com"}]),
+        json!([{"type": "code", "offset": 0, "length": 4}, {"type": "italic", "offset": 2, "length": 4}]),
+        json!([{"type": "text_mention", "offset": 0, "length": 2, "user_id": carol.id}]),
+    ] {
+        let res = app
+            .client
+            .post(format!("{}/messages", chat_url))
+            .bearer_auth(&alice.token)
+            .json(&json!({"content": "hey there", "entities": bad}))
+            .send()
+            .await?;
+        assert_eq!(res.status().as_u16(), 400, "{bad}");
+    }
+
+    // offsets count UTF-16 units (the emoji is two) and follow the trimmed text
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({
+            "content": " 😀 bold and you",
+            "entities": [
+                {"type": "text_mention", "offset": 13, "length": 3, "user_id": bob.id},
+                {"type": "bold", "offset": 4, "length": 4},
+            ],
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message_id = sent["id"].as_str().unwrap().to_string();
+    let message = app.find_message(&bob.token, &chat_id, &message_id).await?;
+    assert_eq!(message["content"], "😀 bold and you");
+    assert_eq!(
+        message["entities"],
+        json!([
+            {"type": "bold", "offset": 3, "length": 4},
+            {"type": "text_mention", "offset": 12, "length": 3, "user_id": bob.id},
+        ])
+    );
+    assert_eq!(message["mentions"][0]["user_id"], bob.id.as_str());
+
+    // an edit replaces the entities and the mentions they carried
+    app.client
+        .patch(format!("{}/v1/api/messages/{}", app.address, message_id))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "see docs", "entities": [{"type": "text_link", "offset": 4, "length": 4, "url": "https://docs.rs"}]}))
+        .send()
+        .await?
+        .error_for_status()?;
+    let message = app.find_message(&bob.token, &chat_id, &message_id).await?;
+    assert_eq!(message["entities"], json!([{"type": "text_link", "offset": 4, "length": 4, "url": "https://docs.rs"}]));
+    assert_eq!(message["mentions"], json!([]));
+
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "links"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let group_url = format!("{}/v1/api/chats/{}", app.address, group["id"].as_str().unwrap());
+    let forwarded: serde_json::Value = app
+        .client
+        .post(format!("{}/forward_messages", group_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"from_chat_id": chat_id, "message_ids": [message_id]}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let copy_id = forwarded["message_ids"][0].as_str().unwrap();
+    let copy = app.find_message(&alice.token, group["id"].as_str().unwrap(), copy_id).await?;
+    assert_eq!(copy["entities"], message["entities"]);
+    Ok(())
+}
@@ -31,7 +31,7 @@ async fn forward_messages_preserves_metadata() -> anyhow::Result<()> {
         .await?
         .json::<serde_json::Value>()
         .await?;
-    let latest = &list.as_array().unwrap()[0];
+    let latest = &list["messages"].as_array().unwrap()[0];
     assert_eq!(
         latest["forwarded_from"]["chat"]["title"].as_str(),
         Some("Source")
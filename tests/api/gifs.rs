@@ -44,7 +44,7 @@ async fn gif_search_and_send_flow() -> anyhow::Result<()> {
 
     let list = app.get_messages(&peer, &chat, false).await?;
     assert_eq!(
-        list.as_array().unwrap()[0]["gif"]["id"].as_str(),
+        list["messages"].as_array().unwrap()[0]["gif"]["id"].as_str(),
         Some("gif123")
     );
 
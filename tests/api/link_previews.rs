@@ -0,0 +1,233 @@
+use super::helpers::TestApp;
+use serde_json::json;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Poll the history until the message carries a preview, or give up after a few seconds.
+async fn wait_for_preview(app: &TestApp, token: &str, chat_id: &str, message_id: &str) -> anyhow::Result<serde_json::Value> {
+    for _ in 0..30 {
+        let message = app.find_message(token, chat_id, message_id).await?;
+        if message.get("link_preview").is_some() {
+            return Ok(message["link_preview"].clone());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!("no preview for {message_id}")
+}
+
+fn png() -> Vec<u8> {
+    let img = image::RgbImage::from_pixel(640, 320, image::Rgb([200, 30, 30]));
+    let mut out = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+    out.into_inner()
+}
+
+/// A tiny PNG whose header claims a canvas beyond the decode limits.
+fn oversized_png() -> Vec<u8> {
+    let img = image::RgbImage::from_pixel(1, 1, image::Rgb([0, 0, 0]));
+    let mut out = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+    let mut png = out.into_inner();
+    // IHDR data follows the 8-byte signature and the chunk's length and type
+    png[16..20].copy_from_slice(&10_000u32.to_be_bytes());
+    png[20..24].copy_from_slice(&10_000u32.to_be_bytes());
+    let crc = png[12..29].iter().fold(!0u32, |mut crc, byte| {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+        crc
+    });
+    png[29..33].copy_from_slice(&(!crc).to_be_bytes());
+    png
+}
+
+#[tokio::test]
+async fn previews_are_fetched_cached_and_optional() -> anyhow::Result<()> {
+    let app = match TestApp::spawn_with(|c| c.link_preview.allow_private_networks = true).await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let site = MockServer::start().await;
+    let page = format!(
+        r#"<html><head><title>Fallback</title>
+        <meta property="og:title" content="Rust &amp; Friends">
+        <meta name="description" content="  A   page about crabs ">
+        <meta property="og:site_name" content='Crab News'>
+        <meta property="og:image" content="/cover.png">
+        </head><body>{}</body></html>"#,
+        "x".repeat(1000)
+    );
+    Mock::given(method("GET"))
+        .and(path("/post"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(page, "text/html; charset=utf-8"))
+        .expect(1)
+        .mount(&site)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/cover.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+        .expect(1)
+        .mount(&site)
+        .await;
+
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    let url = format!("{}/post", site.uri());
+
+    let first = app.post_message(&alice.token, &chat_id, json!({"content": format!("look: {url}#top.")})).await?;
+    let preview = wait_for_preview(&app, &bob.token, &chat_id, &first).await?;
+    assert_eq!(preview["url"], url);
+    assert_eq!(preview["title"], "Rust & Friends");
+    assert_eq!(preview["description"], "A page about crabs");
+    assert_eq!(preview["site_name"], "Crab News");
+    assert_eq!(preview["image"]["width"], 320);
+    assert_eq!(preview["image"]["height"], 160);
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM storage_files WHERE id = $1::uuid AND content_type = 'image/jpeg'")
+        .bind(preview["image"]["file_id"].as_str().unwrap())
+        .fetch_one(&app.pool)
+        .await?;
+    assert_eq!(stored, 1);
+
+    // the cached preview goes out with the message; the page is not fetched again
+    let second: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .json(&json!({"content": url}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let second = app.find_message(&alice.token, &chat_id, second["id"].as_str().unwrap()).await?;
+    assert_eq!(second["link_preview"]["title"], "Rust & Friends");
+
+    let quiet = app.post_message(&alice.token, &chat_id, json!({"content": url, "disable_link_preview": true})).await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(app.find_message(&bob.token, &chat_id, &quiet).await?.get("link_preview").is_none());
+
+    // editing the link away drops the preview
+    app.client
+        .patch(format!("{}/v1/api/messages/{}", app.address, first))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "never mind"}))
+        .send()
+        .await?
+        .error_for_status()?;
+    assert!(app.find_message(&bob.token, &chat_id, &first).await?.get("link_preview").is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn private_addresses_are_not_fetched() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let internal = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw("<title>secret</title>", "text/html"))
+        .expect(0)
+        .mount(&internal)
+        .await;
+
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let url = format!("{}/admin", internal.uri());
+    let id = app.post_message(&alice.token, &chat_id, json!({"content": format!("see {url}")})).await?;
+
+    let mut status = None;
+    for _ in 0..30 {
+        status = sqlx::query_scalar::<_, String>("SELECT status FROM link_previews WHERE url = $1")
+            .bind(&url)
+            .fetch_optional(&app.pool)
+            .await?;
+        if status.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status.as_deref(), Some("failed"));
+    assert!(app.find_message(&bob.token, &chat_id, &id).await?.get("link_preview").is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn oversized_images_are_not_decoded() -> anyhow::Result<()> {
+    let app = match TestApp::spawn_with(|c| c.link_preview.allow_private_networks = true).await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let site = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/huge"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(
+            r#"<html><head><meta property="og:title" content="Huge"><meta property="og:image" content="/huge.png"></head></html>"#,
+            "text/html",
+        ))
+        .mount(&site)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/huge.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(oversized_png(), "image/png"))
+        .mount(&site)
+        .await;
+
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let sent = app.post_message(&alice.token, &chat_id, json!({"content": format!("{}/huge", site.uri())})).await?;
+    let preview = wait_for_preview(&app, &bob.token, &chat_id, &sent).await?;
+    assert_eq!(preview["title"], "Huge");
+    assert!(preview.get("image").is_none() || preview["image"].is_null(), "{preview}");
+    Ok(())
+}
+
+#[tokio::test]
+async fn one_fetch_serves_every_message_linking_the_url() -> anyhow::Result<()> {
+    let app = match TestApp::spawn_with(|c| c.link_preview.allow_private_networks = true).await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let site = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/slow"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(r#"<html><head><meta property="og:title" content="Slow"></head></html>"#, "text/html")
+                .set_delay(Duration::from_millis(500)),
+        )
+        .expect(1)
+        .mount(&site)
+        .await;
+
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let url = format!("{}/slow", site.uri());
+    // both go out before the first fetch finishes
+    let first = app.post_message(&alice.token, &chat_id, json!({"content": url})).await?;
+    let second = app.post_message(&bob.token, &chat_id, json!({"content": url})).await?;
+    for message_id in [first, second] {
+        let preview = wait_for_preview(&app, &alice.token, &chat_id, &message_id).await?;
+        assert_eq!(preview["title"], "Slow");
+    }
+    Ok(())
+}
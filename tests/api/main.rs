@@ -5,15 +5,28 @@ mod avatars;
 mod chat_list;
 mod chats;
 mod clear;
+mod codec;
+mod deletion;
+mod drafts;
+mod entities;
 mod files;
 mod forward;
 mod gifs;
 mod groups;
 mod helpers;
+mod link_previews;
 mod members;
 mod mentions;
 mod messages;
 mod messages_rich;
+mod pagination;
+mod polls;
 mod public;
+mod reactions;
+mod revisions;
+mod saved;
+mod scheduled;
 mod stickers;
+mod threads;
+mod ttl;
 mod ws;
@@ -120,7 +120,7 @@ async fn listing_includes_receipts_and_pin_state() -> anyhow::Result<()> {
     app.mark_messages_read(&token_b, &dm, vec![&msg]).await?;
 
     let list = app.get_messages(&token_a, &dm, true).await?;
-    let top = &list.as_array().unwrap()[0];
+    let top = &list["messages"].as_array().unwrap()[0];
     assert_eq!(top["read_receipt"]["is_read_by_peer"].as_bool(), Some(true));
 
     // pin message in group and ensure flag
@@ -165,7 +165,7 @@ async fn listing_includes_receipts_and_pin_state() -> anyhow::Result<()> {
         .await?
         .json::<serde_json::Value>()
         .await?;
-    let pinned = glist
+    let pinned = glist["messages"]
         .as_array()
         .unwrap()
         .iter()
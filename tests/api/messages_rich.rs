@@ -39,7 +39,7 @@ async fn send_with_attachment_and_reply_and_list() -> anyhow::Result<()> {
         .await?
         .json::<serde_json::Value>()
         .await?;
-    let arr = list.as_array().unwrap();
+    let arr = list["messages"].as_array().unwrap();
     assert!(arr.len() >= 2);
     let latest = &arr[0];
     assert!(latest["attachments"].as_array().unwrap().len() == 1);
@@ -0,0 +1,158 @@
+use super::helpers::TestApp;
+use reqwest::StatusCode;
+
+async fn page(app: &TestApp, token: &str, url: &str, query: &[(&str, &str)]) -> anyhow::Result<serde_json::Value> {
+    let page: serde_json::Value = app
+        .client
+        .get(url)
+        .query(query)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(page)
+}
+
+fn ids(items: &serde_json::Value, field: &str) -> Vec<String> {
+    items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m[field].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn history_pages_through_identical_timestamps_in_both_directions() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    let messages_url = format!("{}/messages", chat_url);
+    let mut sent = Vec::new();
+    for i in 0..5 {
+        sent.push(app.send_message(&alice.token, &chat_id, &format!("ping {} @{}", i, bob.username)).await?);
+    }
+    // a burst that lands in the same microsecond
+    sqlx::query("UPDATE messages SET created_at = '2026-01-01T00:00:00Z' WHERE chat_id = $1::uuid")
+        .bind(&chat_id)
+        .execute(&app.pool)
+        .await?;
+    sqlx::query("UPDATE member_mentions SET created_at = '2026-01-01T00:00:00Z' WHERE chat_id = $1::uuid")
+        .bind(&chat_id)
+        .execute(&app.pool)
+        .await?;
+    let mut expected = sent.clone();
+    expected.sort();
+    expected.reverse();
+
+    // backwards with `next`
+    let mut seen = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut query = vec![("limit", "2")];
+        if let Some(c) = cursor.as_deref() {
+            query.push(("before", c));
+        }
+        let p = page(&app, &bob.token, &messages_url, &query).await?;
+        seen.extend(ids(&p["messages"], "id"));
+        match p["next"].as_str() {
+            Some(next) => cursor = Some(next.to_string()),
+            None => break,
+        }
+    }
+    assert_eq!(seen, expected);
+
+    // forwards with `prev` from the oldest message
+    let oldest = page(&app, &bob.token, &messages_url, &[("around", &expected[4]), ("limit", "1")]).await?;
+    assert_eq!(ids(&oldest["messages"], "id"), vec![expected[4].clone()]);
+    assert!(oldest["next"].is_null());
+    let mut seen = Vec::new();
+    let mut cursor = oldest["prev"].as_str().map(str::to_string);
+    while let Some(c) = cursor {
+        let p = page(&app, &bob.token, &messages_url, &[("after", &c), ("limit", "2")]).await?;
+        let mut chunk = ids(&p["messages"], "id");
+        chunk.extend(seen);
+        seen = chunk;
+        cursor = p["prev"].as_str().map(str::to_string);
+    }
+    assert_eq!(seen, expected[..4]);
+
+    // around keeps the target with context on both sides
+    let around = page(&app, &bob.token, &messages_url, &[("around", &expected[2]), ("limit", "3")]).await?;
+    assert_eq!(ids(&around["messages"], "id"), expected[1..4]);
+    assert!(around["next"].is_string());
+    assert!(around["prev"].is_string());
+
+    // search and mentions follow the same scheme
+    let search_url = format!("{}/search", messages_url);
+    let first = page(&app, &bob.token, &search_url, &[("q", "ping"), ("limit", "3")]).await?;
+    assert_eq!(ids(&first["messages"], "id"), expected[..3]);
+    let rest = page(&app, &bob.token, &search_url, &[("q", "ping"), ("before", first["next"].as_str().unwrap())]).await?;
+    assert_eq!(ids(&rest["messages"], "id"), expected[3..]);
+    assert!(rest["next"].is_null());
+    let mentions_url = format!("{}/member/mentions", chat_url);
+    let first = page(&app, &bob.token, &mentions_url, &[("limit", "4")]).await?;
+    assert_eq!(ids(&first["mentions"], "message_id"), expected[..4]);
+    let rest = page(&app, &bob.token, &mentions_url, &[("before", first["next"].as_str().unwrap())]).await?;
+    assert_eq!(ids(&rest["mentions"], "message_id"), expected[4..]);
+
+    let resp = app
+        .client
+        .get(&messages_url)
+        .query(&[("before", first["next"].as_str().unwrap()), ("after", first["next"].as_str().unwrap())])
+        .bearer_auth(&bob.token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = app.client.get(&messages_url).query(&[("after", "nope")]).bearer_auth(&bob.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let resp = app
+        .client
+        .get(&messages_url)
+        .query(&[("around", uuid::Uuid::new_v4().to_string())])
+        .bearer_auth(&bob.token)
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn chat_list_is_paged() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let mut chats = Vec::new();
+    for name in ["bob", "carol", "dave"] {
+        let peer = app.signup(name).await?;
+        chats.push(app.start_direct(&alice.token, &peer.id).await?);
+    }
+    chats.reverse();
+    let chats_url = format!("{}/v1/api/chats", app.address);
+
+    let first = page(&app, &alice.token, &chats_url, &[("limit", "2")]).await?;
+    assert_eq!(ids(&first["chats"], "id"), chats[..2]);
+    assert!(first["prev"].is_null());
+    let rest = page(&app, &alice.token, &chats_url, &[("before", first["next"].as_str().unwrap())]).await?;
+    assert_eq!(ids(&rest["chats"], "id"), chats[2..]);
+    assert!(rest["next"].is_null());
+    let back = page(&app, &alice.token, &chats_url, &[("after", rest["prev"].as_str().unwrap())]).await?;
+    assert_eq!(ids(&back["chats"], "id"), chats[..2]);
+    assert!(back["prev"].is_null());
+    Ok(())
+}
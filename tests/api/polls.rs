@@ -0,0 +1,191 @@
+use super::helpers::TestApp;
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+/// The poll of `message_id` as `token`'s user sees it in the chat history.
+async fn poll_in_history(app: &TestApp, token: &str, chat_url: &str, message_id: &str) -> anyhow::Result<serde_json::Value> {
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message = history["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["id"] == message_id)
+        .ok_or_else(|| anyhow::anyhow!("message not in history"))?;
+    assert_eq!(message["kind"], "poll");
+    Ok(message["poll"].clone())
+}
+
+#[tokio::test]
+async fn quiz_answers_are_final_and_revealed_after_voting() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "trivia"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, group["id"].as_str().unwrap());
+    app.client
+        .post(format!("{}/participants", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    for bad in [
+        json!({"question": "one option?", "options": ["only"]}),
+        json!({"question": "dupes?", "options": ["a", "A"]}),
+        json!({"question": "quiz?", "options": ["a", "b"], "correct_option_id": 5}),
+        json!({"question": "quiz?", "options": ["a", "b"], "correct_option_id": 0, "allows_multiple": true}),
+        json!({"question": "past?", "options": ["a", "b"], "close_at": Utc::now() - Duration::minutes(1)}),
+    ] {
+        let res = app.client.post(format!("{}/polls", chat_url)).bearer_auth(&alice.token).json(&bad).send().await?;
+        assert_eq!(res.status().as_u16(), 400, "{bad}");
+    }
+
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/polls", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"question": "Largest planet?", "options": ["Mars", "Jupiter", "Venus"], "correct_option_id": 1, "explanation": "It is a gas giant"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message_id = sent["id"].as_str().unwrap().to_string();
+    let votes_url = format!("{}/v1/api/messages/{}/poll/votes", app.address, message_id);
+
+    let poll = poll_in_history(&app, &bob.token, &chat_url, &message_id).await?;
+    assert_eq!(poll["is_quiz"], true);
+    assert!(poll.get("correct_option_id").is_none());
+    let poll = poll_in_history(&app, &alice.token, &chat_url, &message_id).await?;
+    assert_eq!(poll["correct_option_id"], 1);
+
+    let edit = app
+        .client
+        .patch(format!("{}/v1/api/messages/{}", app.address, message_id))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "changed"}))
+        .send()
+        .await?;
+    assert_eq!(edit.status().as_u16(), 400);
+
+    let two = app.client.post(&votes_url).bearer_auth(&bob.token).json(&json!({"option_ids": [0, 1]})).send().await?;
+    assert_eq!(two.status().as_u16(), 400);
+    let res: serde_json::Value = app
+        .client
+        .post(&votes_url)
+        .bearer_auth(&bob.token)
+        .json(&json!({"option_ids": [0]}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(res["poll"]["correct_option_id"], 1);
+    assert_eq!(res["poll"]["explanation"], "It is a gas giant");
+    let change = app.client.post(&votes_url).bearer_auth(&bob.token).json(&json!({"option_ids": [1]})).send().await?;
+    assert_eq!(change.status().as_u16(), 409);
+    let retract = app.client.delete(&votes_url).bearer_auth(&bob.token).send().await?;
+    assert_eq!(retract.status().as_u16(), 400);
+    let voters = app
+        .client
+        .get(format!("{}/v1/api/messages/{}/poll/voters", app.address, message_id))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?;
+    assert_eq!(voters.status().as_u16(), 403);
+
+    let close_url = format!("{}/v1/api/messages/{}/poll/close", app.address, message_id);
+    let not_creator = app.client.post(&close_url).bearer_auth(&bob.token).send().await?;
+    assert_eq!(not_creator.status().as_u16(), 403);
+    let closed: serde_json::Value = app
+        .client
+        .post(&close_url)
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(closed["poll"]["is_closed"], true);
+    let late = app.client.post(&votes_url).bearer_auth(&alice.token).json(&json!({"option_ids": [1]})).send().await?;
+    assert_eq!(late.status().as_u16(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn polls_close_at_their_close_date() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let channel: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/channel", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "news"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, channel["id"].as_str().unwrap());
+
+    let public = app
+        .client
+        .post(format!("{}/polls", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"question": "Who reads this?", "options": ["me", "not me"], "is_anonymous": false}))
+        .send()
+        .await?;
+    assert_eq!(public.status().as_u16(), 400);
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/polls", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"question": "Next topic?", "options": ["rust", "go"], "close_at": Utc::now() + Duration::seconds(1)}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message_id = sent["id"].as_str().unwrap().to_string();
+    assert_eq!(poll_in_history(&app, &alice.token, &chat_url, &message_id).await?["is_closed"], false);
+
+    tokio::time::sleep(std::time::Duration::from_millis(1800)).await;
+    assert_eq!(poll_in_history(&app, &alice.token, &chat_url, &message_id).await?["is_closed"], true);
+    let closed_at: Option<chrono::DateTime<Utc>> =
+        sqlx::query_scalar("SELECT p.closed_at FROM polls p JOIN messages m ON m.poll_id = p.id WHERE m.id = $1::uuid")
+            .bind(&message_id)
+            .fetch_one(&app.pool)
+            .await?;
+    assert!(closed_at.is_some());
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use super::helpers::TestApp;
+use futures_util::future::join_all;
+use serde_json::json;
+
+#[tokio::test]
+async fn concurrent_reactions_respect_the_per_user_limit() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "team"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/{}/messages", app.address, group["id"].as_str().unwrap()))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "react to me"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let reactions_url = format!("{}/v1/api/messages/{}/reactions", app.address, sent["id"].as_str().unwrap());
+
+    let attempts = ["👍", "🔥", "❤", "🎉", "😂", "😮"].map(|emoji| {
+        app.client
+            .post(&reactions_url)
+            .bearer_auth(&alice.token)
+            .json(&json!({"emoji": emoji}))
+            .send()
+    });
+    let statuses: Vec<u16> = join_all(attempts)
+        .await
+        .into_iter()
+        .map(|r| r.map(|r| r.status().as_u16()))
+        .collect::<Result<_, _>>()?;
+    assert_eq!(statuses.iter().filter(|s| **s == 200).count(), 3, "{statuses:?}");
+    assert!(statuses.iter().all(|s| *s == 200 || *s == 422), "{statuses:?}");
+
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM message_reactions WHERE message_id = $1::uuid")
+        .bind(sent["id"].as_str().unwrap())
+        .fetch_one(&app.pool)
+        .await?;
+    assert_eq!(stored, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn reactors_with_equal_timestamps_are_not_skipped_between_pages() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "team"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, group["id"].as_str().unwrap());
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "react to me"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let reactions_url = format!("{}/v1/api/messages/{}/reactions", app.address, sent["id"].as_str().unwrap());
+
+    let mut members = vec![alice];
+    for name in ["bob", "carol"] {
+        let member = app.signup(name).await?;
+        app.client
+            .post(format!("{}/participants", chat_url))
+            .bearer_auth(&members[0].token)
+            .json(&json!({"user_id": member.id}))
+            .send()
+            .await?
+            .error_for_status()?;
+        members.push(member);
+    }
+    for member in &members {
+        app.client
+            .post(&reactions_url)
+            .bearer_auth(&member.token)
+            .json(&json!({"emoji": "👍"}))
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    // the same instant for everyone, so every page boundary falls on a tie
+    sqlx::query("UPDATE message_reactions SET created_at = now() WHERE message_id = $1::uuid")
+        .bind(sent["id"].as_str().unwrap())
+        .execute(&app.pool)
+        .await?;
+
+    let mut seen = Vec::new();
+    let mut before: Option<String> = None;
+    loop {
+        let mut query = vec![("limit", "1".to_string())];
+        if let Some(cursor) = &before {
+            query.push(("before", cursor.clone()));
+        }
+        let page: serde_json::Value = app
+            .client
+            .get(&reactions_url)
+            .bearer_auth(&members[0].token)
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for reactor in page["reactors"].as_array().unwrap() {
+            seen.push(reactor["user_id"].as_str().unwrap().to_string());
+        }
+        match page["next_before"].as_str() {
+            Some(cursor) => before = Some(cursor.to_string()),
+            None => break,
+        }
+    }
+    let mut expected: Vec<String> = members.into_iter().map(|m| m.id).collect();
+    seen.sort();
+    expected.sort();
+    assert_eq!(seen, expected);
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use super::helpers::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn edits_keep_revisions_and_reparse_mentions() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let carol = app.signup("carol").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "drafts"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, group["id"].as_str().unwrap());
+    for member in [&bob, &carol] {
+        app.client
+            .post(format!("{}/participants", chat_url))
+            .bearer_auth(&alice.token)
+            .json(&json!({"user_id": member.id}))
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .json(&json!({"content": format!("hi @{}", alice.username)}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message_id = sent["id"].as_str().unwrap().to_string();
+    let message_url = format!("{}/v1/api/messages/{}", app.address, message_id);
+
+    let edited = app
+        .client
+        .patch(&message_url)
+        .bearer_auth(&bob.token)
+        .header("If-Match", "\"1\"")
+        .json(&json!({"content": format!("hi @{}", carol.username)}))
+        .send()
+        .await?
+        .error_for_status()?;
+    assert_eq!(edited.headers()["etag"], "\"2\"");
+    let edited: serde_json::Value = edited.json().await?;
+    assert_eq!(edited["revision"], 2);
+
+    // a client still holding revision 1 loses the race
+    let stale = app
+        .client
+        .patch(&message_url)
+        .bearer_auth(&bob.token)
+        .json(&json!({"content": "stale", "revision": 1}))
+        .send()
+        .await?;
+    assert_eq!(stale.status().as_u16(), 412);
+    let stale: serde_json::Value = stale.json().await?;
+    assert_eq!(stale["revision"], 2);
+
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message = history["messages"].as_array().unwrap().iter().find(|m| m["id"] == message_id.as_str()).unwrap();
+    assert_eq!(message["revision"], 2);
+    let mentioned: Vec<&str> = message["mentions"].as_array().unwrap().iter().map(|m| m["user_id"].as_str().unwrap()).collect();
+    assert_eq!(mentioned, vec![carol.id.as_str()]);
+
+    let revisions_url = format!("{}/revisions", message_url);
+    let forbidden = app.client.get(&revisions_url).bearer_auth(&carol.token).send().await?;
+    assert_eq!(forbidden.status().as_u16(), 403);
+    let revisions: serde_json::Value = app
+        .client
+        .get(&revisions_url)
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(revisions["revision"], 2);
+    let revisions = revisions["revisions"].as_array().unwrap();
+    assert_eq!(revisions.len(), 1);
+    assert_eq!(revisions[0]["revision"], 1);
+    assert_eq!(revisions[0]["content"], format!("hi @{}", alice.username));
+    Ok(())
+}
+
+#[tokio::test]
+async fn edits_are_refused_after_the_edit_window() -> anyhow::Result<()> {
+    let app = match TestApp::spawn_with(|c| c.messages.edit_window_secs = 1).await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let sent: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/{}/messages", app.address, chat_id))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "typo"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message_url = format!("{}/v1/api/messages/{}", app.address, sent["id"].as_str().unwrap());
+
+    let not_sender = app.client.patch(&message_url).bearer_auth(&bob.token).json(&json!({"content": "mine"})).send().await?;
+    assert_eq!(not_sender.status().as_u16(), 403);
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let late = app.client.patch(&message_url).bearer_auth(&alice.token).json(&json!({"content": "fixed"})).send().await?;
+    assert_eq!(late.status().as_u16(), 403);
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use super::helpers::TestApp;
+use reqwest::StatusCode;
+use serde_json::json;
+
+async fn list(app: &TestApp, token: &str, query: &[(&str, &str)]) -> anyhow::Result<Vec<serde_json::Value>> {
+    let page: serde_json::Value = app
+        .client
+        .get(format!("{}/v1/api/saved", app.address))
+        .query(query)
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(page["items"].as_array().unwrap().clone())
+}
+
+#[tokio::test]
+async fn saved_messages_copy_bookmark_filter_and_tombstone() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let carol = app.signup("carol").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let notes = app.send_message(&bob.token, &chat_id, "meeting notes for friday").await?;
+    let joke = app.send_message(&bob.token, &chat_id, "a joke about meetings").await?;
+    let saved_url = format!("{}/v1/api/saved", app.address);
+
+    let saved_chat: serde_json::Value = app
+        .client
+        .get(format!("{}/chat", saved_url))
+        .bearer_auth(&alice.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(saved_chat["type"], "saved");
+    let saved_chat_id = saved_chat["id"].as_str().unwrap().to_string();
+
+    // bookmark by reference
+    let resp = app
+        .client
+        .post(&saved_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({"message_id": notes, "tag": "work", "note": "read before standup"}))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let bookmark: serde_json::Value = resp.json().await?;
+    assert_eq!(bookmark["kind"], "reference");
+    assert_eq!(bookmark["status"], "available");
+    assert_eq!(bookmark["message"]["id"], notes.as_str());
+    assert_eq!(bookmark["source"]["chat_id"], chat_id.as_str());
+    assert_eq!(bookmark["source"]["sender_id"], bob.id.as_str());
+    let resp = app.client.post(&saved_url).bearer_auth(&alice.token).json(&json!({"message_id": notes})).send().await?;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = app.client.post(&saved_url).bearer_auth(&carol.token).json(&json!({"message_id": notes})).send().await?;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // copy into the saved chat, directly or with forward_messages
+    let copy: serde_json::Value = app
+        .client
+        .post(&saved_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({"message_id": joke, "copy": true, "tag": "fun"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(copy["kind"], "copy");
+    assert_eq!(copy["message"]["chat_id"], saved_chat_id.as_str());
+    assert_eq!(copy["message"]["forwarded_from"]["sender"]["id"], bob.id.as_str());
+    assert_eq!(copy["source"]["message_id"], joke.as_str());
+    app.client
+        .post(format!("{}/v1/api/chats/{}/forward_messages", app.address, saved_chat_id))
+        .bearer_auth(&alice.token)
+        .json(&json!({"from_chat_id": chat_id, "message_ids": [notes]}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    assert_eq!(list(&app, &alice.token, &[]).await?.len(), 3);
+    assert_eq!(list(&app, &alice.token, &[("chat_id", &chat_id)]).await?.len(), 3);
+    assert!(list(&app, &alice.token, &[("chat_id", &saved_chat_id)]).await?.is_empty());
+    let work = list(&app, &alice.token, &[("tag", "work")]).await?;
+    assert_eq!(work.len(), 1);
+    assert_eq!(work[0]["id"], bookmark["id"]);
+    assert_eq!(list(&app, &alice.token, &[("q", "friday")]).await?.len(), 2);
+    assert_eq!(list(&app, &alice.token, &[("q", "standup")]).await?.len(), 1);
+    assert!(list(&app, &bob.token, &[]).await?.is_empty());
+
+    // the bookmark turns into a tombstone once the original is deleted; copies live on
+    app.client
+        .delete(format!("{}/v1/api/messages/{}", app.address, notes))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?
+        .error_for_status()?;
+    let items = list(&app, &alice.token, &[]).await?;
+    let tombstone = items.iter().find(|i| i["id"] == bookmark["id"]).unwrap();
+    assert_eq!(tombstone["status"], "deleted");
+    assert!(tombstone.get("message").is_none());
+    assert_eq!(tombstone["source"]["sender_id"], bob.id.as_str());
+    assert!(items.iter().filter(|i| i["kind"] == "copy").all(|i| i["status"] == "available"));
+    assert_eq!(list(&app, &alice.token, &[("q", "friday")]).await?.len(), 1);
+
+    let item_url = format!("{}/{}", saved_url, bookmark["id"].as_str().unwrap());
+    let updated: serde_json::Value = app
+        .client
+        .patch(&item_url)
+        .bearer_auth(&alice.token)
+        .json(&json!({"tag": ""}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert!(updated.get("tag").is_none());
+    assert_eq!(updated["note"], "read before standup");
+    let resp = app.client.delete(&item_url).bearer_auth(&alice.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let resp = app.client.delete(&item_url).bearer_auth(&alice.token).send().await?;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // the saved chat stays private
+    let resp = app
+        .client
+        .post(format!("{}/v1/api/chats/{}/participants", app.address, saved_chat_id))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id}))
+        .send()
+        .await?;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[tokio::test]
+async fn saved_items_with_equal_timestamps_are_not_skipped_between_pages() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let mut saved = Vec::new();
+    for content in ["one", "two", "three"] {
+        let message_id = app.send_message(&bob.token, &chat_id, content).await?;
+        let item: serde_json::Value = app
+            .client
+            .post(format!("{}/v1/api/saved", app.address))
+            .bearer_auth(&alice.token)
+            .json(&json!({"message_id": message_id}))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        saved.push(item["id"].as_str().unwrap().to_string());
+    }
+    sqlx::query("UPDATE saved_messages SET created_at = now() WHERE id = ANY($1::uuid[])")
+        .bind(&saved)
+        .execute(&app.pool)
+        .await?;
+
+    let mut seen = Vec::new();
+    let mut before: Option<String> = None;
+    loop {
+        let mut query = vec![("limit", "1".to_string())];
+        if let Some(cursor) = &before {
+            query.push(("before", cursor.clone()));
+        }
+        let page: serde_json::Value = app
+            .client
+            .get(format!("{}/v1/api/saved", app.address))
+            .bearer_auth(&alice.token)
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for item in page["items"].as_array().unwrap() {
+            seen.push(item["id"].as_str().unwrap().to_string());
+        }
+        match page["next_before"].as_str() {
+            Some(cursor) => before = Some(cursor.to_string()),
+            None => break,
+        }
+    }
+    seen.sort();
+    saved.sort();
+    assert_eq!(seen, saved);
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use super::helpers::TestApp;
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+async fn history_contents(app: &TestApp, token: &str, chat_url: &str) -> anyhow::Result<Vec<String>> {
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(history["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["kind"] == "text")
+        .map(|m| m["content"].as_str().unwrap().to_string())
+        .collect())
+}
+
+async fn list_scheduled(app: &TestApp, token: &str, chat_url: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    let list: serde_json::Value = app
+        .client
+        .get(format!("{}/scheduled_messages", chat_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(list.as_array().unwrap().clone())
+}
+
+#[tokio::test]
+async fn scheduled_messages_are_sent_later_and_rechecked() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "team"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, group["id"].as_str().unwrap());
+    app.client
+        .post(format!("{}/participants", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let schedule = |content: &str, at: chrono::DateTime<Utc>| {
+        app.client
+            .post(format!("{}/messages", chat_url))
+            .bearer_auth(&bob.token)
+            .json(&json!({"content": content, "scheduled_at": at}))
+            .send()
+    };
+    let past = schedule("too late", Utc::now() - Duration::minutes(1)).await?;
+    assert_eq!(past.status().as_u16(), 400);
+    let soon = schedule("soon", Utc::now() + Duration::seconds(1)).await?;
+    assert_eq!(soon.status().as_u16(), 201);
+    let later: serde_json::Value = schedule("later", Utc::now() + Duration::hours(1)).await?.json().await?;
+    let never: serde_json::Value = schedule("never", Utc::now() + Duration::hours(1)).await?.json().await?;
+    assert_eq!(later["status"], "pending");
+
+    let cancelled = app
+        .client
+        .delete(format!("{}/scheduled_messages/{}", chat_url, never["id"].as_str().unwrap()))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?;
+    assert_eq!(cancelled.status().as_u16(), 204);
+    assert_eq!(list_scheduled(&app, &bob.token, &chat_url).await?.len(), 2);
+    assert!(list_scheduled(&app, &alice.token, &chat_url).await?.is_empty());
+    assert!(history_contents(&app, &alice.token, &chat_url).await?.is_empty());
+
+    // the dispatcher sends it once it is due and drops it from the schedule
+    let mut sent = Vec::new();
+    for _ in 0..25 {
+        sent = history_contents(&app, &alice.token, &chat_url).await?;
+        if !sent.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+    assert_eq!(sent, ["soon"]);
+    let pending = list_scheduled(&app, &bob.token, &chat_url).await?;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["id"], later["id"]);
+
+    // a sender muted in the meantime does not get through
+    app.client
+        .post(format!("{}/actions/mute", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id, "minutes": 5}))
+        .send()
+        .await?
+        .error_for_status()?;
+    let edited: serde_json::Value = app
+        .client
+        .patch(format!("{}/scheduled_messages/{}", chat_url, later["id"].as_str().unwrap()))
+        .bearer_auth(&bob.token)
+        .json(&json!({"content": "edited", "scheduled_at": Utc::now() + Duration::milliseconds(500)}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(edited["content"], "edited");
+    let mut failed = serde_json::Value::Null;
+    for _ in 0..25 {
+        failed = list_scheduled(&app, &bob.token, &chat_url).await?.remove(0);
+        if failed["status"] == "failed" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+    assert_eq!(failed["status"], "failed");
+    assert_eq!(failed["error"], "muted");
+    assert_eq!(history_contents(&app, &alice.token, &chat_url).await?, ["soon"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_claims_are_retried_without_duplicates() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "notes"}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, group["id"].as_str().unwrap());
+    let scheduled: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "reminder", "scheduled_at": Utc::now() + Duration::hours(1)}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let id: uuid::Uuid = scheduled["id"].as_str().unwrap().parse()?;
+
+    // a node claimed it, sent it and died before clearing the row
+    app.client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "reminder"}))
+        .send()
+        .await?
+        .error_for_status()?;
+    sqlx::query("UPDATE messages SET client_message_id = $1 WHERE content = 'reminder'")
+        .bind(format!("scheduled:{}", id))
+        .execute(&app.pool)
+        .await?;
+    sqlx::query("UPDATE scheduled_messages SET status = 'sending', claimed_at = now() - interval '1 hour', scheduled_at = now() WHERE id = $1")
+        .bind(id)
+        .execute(&app.pool)
+        .await?;
+
+    for _ in 0..25 {
+        if list_scheduled(&app, &alice.token, &chat_url).await?.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+    assert!(list_scheduled(&app, &alice.token, &chat_url).await?.is_empty());
+    assert_eq!(history_contents(&app, &alice.token, &chat_url).await?, ["reminder"]);
+    Ok(())
+}
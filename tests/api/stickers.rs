@@ -39,7 +39,7 @@ async fn sticker_pack_flow_and_send() -> anyhow::Result<()> {
         .await?
         .json::<serde_json::Value>()
         .await?;
-    let latest = &list.as_array().unwrap()[0];
+    let latest = &list["messages"].as_array().unwrap()[0];
     assert_eq!(latest["kind"].as_str(), Some("sticker"));
     assert_eq!(latest["sticker"]["emoji"].as_str(), Some("😀"));
 
@@ -0,0 +1,242 @@
+use super::helpers::TestApp;
+use serde_json::json;
+
+#[tokio::test]
+async fn channel_members_comment_in_threads() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let channel: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/channel", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "news"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_id = channel["id"].as_str().unwrap().to_string();
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    app.client
+        .post(format!("{}/participants", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let post: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "release is out"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let post_id = post["id"].as_str().unwrap().to_string();
+
+    // subscribers cannot post top-level, only comment
+    let top_level = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .json(&json!({"content": "hello"}))
+        .send()
+        .await?;
+    assert_eq!(top_level.status().as_u16(), 403);
+    let comment: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .json(&json!({"content": "congrats", "thread_root_id": post_id}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // replying to a comment stays in the post's thread
+    let answer: serde_json::Value = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "thanks", "thread_root_id": comment["id"], "reply_to_message_id": comment["id"]}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let top: Vec<&serde_json::Value> = history["messages"].as_array().unwrap().iter().filter(|m| m["kind"] != "service").collect();
+    assert_eq!(top.len(), 1);
+    assert_eq!(top[0]["id"], post["id"]);
+    assert_eq!(top[0]["thread"]["reply_count"], 2);
+    assert_eq!(top[0]["thread"]["last_replier"]["id"], alice.id);
+    assert_eq!(top[0]["thread"]["unread_count"], 1);
+    let unread: serde_json::Value = app
+        .client
+        .get(format!("{}/unread_count", chat_url))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(unread["unread"], 1);
+
+    let thread_url = format!("{}/v1/api/messages/{}/thread", app.address, post_id);
+    let page: serde_json::Value = app
+        .client
+        .get(&thread_url)
+        .bearer_auth(&bob.token)
+        .query(&[("limit", "1")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(page["root"]["id"], post["id"]);
+    assert_eq!(page["messages"][0]["id"], answer["id"]);
+    assert_eq!(page["messages"][0]["thread_root_id"], post["id"]);
+    let before = page["next"].as_str().unwrap().to_string();
+    let page: serde_json::Value = app
+        .client
+        .get(&thread_url)
+        .bearer_auth(&bob.token)
+        .query(&[("limit", "1"), ("before", before.as_str())])
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(page["messages"][0]["id"], comment["id"]);
+
+    app.client
+        .post(format!("{}/v1/api/messages/read_bulk", app.address))
+        .bearer_auth(&bob.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [answer["id"]]}))
+        .send()
+        .await?
+        .error_for_status()?;
+    let page: serde_json::Value = app
+        .client
+        .get(&thread_url)
+        .bearer_auth(&bob.token)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(page["root"]["thread"]["unread_count"], 0);
+    assert_eq!(page["messages"].as_array().unwrap().len(), 2);
+
+    let nested = app
+        .client
+        .get(format!("{}/v1/api/messages/{}/thread", app.address, comment["id"].as_str().unwrap()))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?;
+    assert_eq!(nested.status().as_u16(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn thread_replies_with_equal_timestamps_are_not_skipped_between_pages() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "team"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let messages_url = format!("{}/v1/api/chats/{}/messages", app.address, group["id"].as_str().unwrap());
+    let mut ids = Vec::new();
+    for content in ["root", "one", "two", "three"] {
+        let thread_root_id = ids.first().cloned();
+        let sent: serde_json::Value = app
+            .client
+            .post(&messages_url)
+            .bearer_auth(&alice.token)
+            .json(&json!({"content": content, "thread_root_id": thread_root_id}))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        ids.push(sent["id"].as_str().unwrap().to_string());
+    }
+    let root_id = ids.remove(0);
+    sqlx::query("UPDATE messages SET created_at = now() WHERE thread_root_id = $1::uuid")
+        .bind(&root_id)
+        .execute(&app.pool)
+        .await?;
+
+    let thread_url = format!("{}/v1/api/messages/{}/thread", app.address, root_id);
+    let mut seen = Vec::new();
+    let mut before: Option<String> = None;
+    loop {
+        let mut query = vec![("limit", "1".to_string())];
+        if let Some(cursor) = &before {
+            query.push(("before", cursor.clone()));
+        }
+        let page: serde_json::Value = app
+            .client
+            .get(&thread_url)
+            .bearer_auth(&alice.token)
+            .query(&query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        for message in page["messages"].as_array().unwrap() {
+            seen.push(message["id"].as_str().unwrap().to_string());
+        }
+        match page["next"].as_str() {
+            Some(cursor) => before = Some(cursor.to_string()),
+            None => break,
+        }
+    }
+    // around a reply centres the page on it
+    let page: serde_json::Value = app
+        .client
+        .get(&thread_url)
+        .bearer_auth(&alice.token)
+        .query(&[("around", seen[1].as_str()), ("limit", "3")])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let around: Vec<&str> = page["messages"].as_array().unwrap().iter().map(|m| m["id"].as_str().unwrap()).collect();
+    assert_eq!(around, seen.iter().map(String::as_str).collect::<Vec<_>>());
+
+    seen.sort();
+    ids.sort();
+    assert_eq!(seen, ids);
+    Ok(())
+}
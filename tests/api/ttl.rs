@@ -0,0 +1,219 @@
+use super::helpers::TestApp;
+use serde_json::json;
+use std::time::Duration;
+
+/// Ids of the messages in the chat's history, newest first.
+async fn history_ids(app: &TestApp, token: &str, chat_url: &str) -> anyhow::Result<Vec<String>> {
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(history["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["kind"] != "service")
+        .map(|m| m["id"].as_str().unwrap().to_string())
+        .collect())
+}
+
+#[tokio::test]
+async fn self_destructing_messages_are_reaped() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+
+    let invalid = app
+        .client
+        .post(format!("{}/messages", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"content": "never", "expires_in": 0}))
+        .send()
+        .await?;
+    assert_eq!(invalid.status().as_u16(), 400);
+
+    let kept = app.post_message(&alice.token, &chat_id, json!({"content": "stays"})).await?;
+    let doomed = app.post_message(&alice.token, &chat_id, json!({"content": "gone soon", "expires_in": 1})).await?;
+
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message = history["messages"].as_array().unwrap().iter().find(|m| m["id"] == doomed.as_str()).unwrap();
+    assert_eq!(message["ttl"]["ttl_seconds"], 1);
+    assert_eq!(message["ttl"]["starts_on_read"], false);
+    assert!(message["ttl"]["started_at"].is_string());
+    assert!(message["ttl"]["expires_at"].is_string());
+
+    tokio::time::sleep(Duration::from_millis(1800)).await;
+    let ids = history_ids(&app, &bob.token, &chat_url).await?;
+    assert!(!ids.contains(&doomed));
+    assert!(ids.contains(&kept));
+    let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE id = $1::uuid")
+        .bind(&doomed)
+        .fetch_one(&app.pool)
+        .await?;
+    assert_eq!(left, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn one_time_media_counts_down_from_first_read() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let chat_id = app.start_direct(&alice.token, &bob.id).await?;
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+
+    let part = reqwest::multipart::Part::bytes(b"secret photo".to_vec())
+        .file_name("photo.bin")
+        .mime_str("application/octet-stream")?;
+    let files: Vec<serde_json::Value> = app
+        .client
+        .post(format!("{}/v1/api/files", app.address))
+        .bearer_auth(&alice.token)
+        .multipart(reqwest::multipart::Form::new().part("f1", part))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let body = json!({"content": "look", "attachment_ids": [files[0]["id"]], "expires_in": 1});
+    let media = app.post_message(&alice.token, &chat_id, body).await?;
+
+    // unread, the timer does not run; the sender reading it does not start it either
+    app.client
+        .post(format!("{}/v1/api/messages/read_bulk", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [media]}))
+        .send()
+        .await?
+        .error_for_status()?;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let history: serde_json::Value = app
+        .client
+        .get(format!("{}/messages", chat_url))
+        .bearer_auth(&bob.token)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let message = history["messages"].as_array().unwrap().iter().find(|m| m["id"] == media.as_str()).unwrap();
+    assert_eq!(message["ttl"]["starts_on_read"], true);
+    assert!(message["ttl"].get("expires_at").is_none());
+
+    app.client
+        .post(format!("{}/v1/api/messages/read_bulk", app.address))
+        .bearer_auth(&bob.token)
+        .json(&json!({"chat_id": chat_id, "message_ids": [media]}))
+        .send()
+        .await?
+        .error_for_status()?;
+    tokio::time::sleep(Duration::from_millis(1800)).await;
+    assert!(!history_ids(&app, &bob.token, &chat_url).await?.contains(&media));
+    let attachments: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM message_attachments WHERE message_id = $1::uuid")
+        .bind(&media)
+        .fetch_one(&app.pool)
+        .await?;
+    assert_eq!(attachments, 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn chat_auto_delete_applies_to_new_messages() -> anyhow::Result<()> {
+    let app = match TestApp::spawn().await {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("skipping test: {}", e);
+            return Ok(());
+        }
+    };
+    let alice = app.signup("alice").await?;
+    let bob = app.signup("bob").await?;
+    let group: serde_json::Value = app
+        .client
+        .post(format!("{}/v1/api/chats/group", app.address))
+        .bearer_auth(&alice.token)
+        .json(&json!({"title": "ephemeral"}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let chat_id = group["id"].as_str().unwrap().to_string();
+    let chat_url = format!("{}/v1/api/chats/{}", app.address, chat_id);
+    app.client
+        .post(format!("{}/participants", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"user_id": bob.id}))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let before = app.post_message(&alice.token, &chat_id, json!({"content": "old"})).await?;
+
+    // plain members cannot change the chat's timer
+    let forbidden = app
+        .client
+        .post(format!("{}/auto_delete", chat_url))
+        .bearer_auth(&bob.token)
+        .json(&json!({"seconds": 1}))
+        .send()
+        .await?;
+    assert_eq!(forbidden.status().as_u16(), 403);
+    let chat: serde_json::Value = app
+        .client
+        .post(format!("{}/auto_delete", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"seconds": 1}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert_eq!(chat["auto_delete_seconds"], 1);
+
+    let after = app.post_message(&bob.token, &chat_id, json!({"content": "fleeting"})).await?;
+    tokio::time::sleep(Duration::from_millis(1800)).await;
+    let ids = history_ids(&app, &alice.token, &chat_url).await?;
+    assert!(ids.contains(&before));
+    assert!(!ids.contains(&after));
+
+    let chat: serde_json::Value = app
+        .client
+        .post(format!("{}/auto_delete", chat_url))
+        .bearer_auth(&alice.token)
+        .json(&json!({"seconds": null}))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    assert!(chat.get("auto_delete_seconds").is_none());
+    Ok(())
+}